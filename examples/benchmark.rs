@@ -0,0 +1,44 @@
+//! Times a word which leans on the bootstrapper's colon words,
+//! with each way of compiling them and each stack model.
+//! Run with `cargo run --release --features runtime --example benchmark [ITERATIONS]`.
+
+use forsm::{ForthVm, Options, StackModel, Threading};
+use std::{error::Error, time::Instant};
+
+const BENCHMARK: &str =
+    r#": BENCHMARK ( n -- ) 0 do s" swap" find-name drop s" -12345" ?number 2drop loop ;"#;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let iterations: i32 = match std::env::args().nth(1) {
+        Some(arg) => arg.parse()?,
+        None => 10_000,
+    };
+    let configurations = [
+        ("threaded", Threading::Indirect, false),
+        ("subroutine-threaded", Threading::Subroutine, false),
+        ("inlined", Threading::Subroutine, true),
+    ];
+    let stack_models = [
+        ("", StackModel::Memory),
+        (", cached top", StackModel::CachedTop),
+    ];
+    for (name, threading, inline_primitives) in configurations {
+        for (model_name, stack_model) in stack_models {
+            let vm = ForthVm::new(&Options {
+                threading,
+                inline_primitives,
+                stack_model,
+                ..Default::default()
+            })?;
+            vm.evaluate(BENCHMARK)?;
+            let start = Instant::now();
+            vm.evaluate(&format!("{} BENCHMARK", iterations))?;
+            println!(
+                "{:>32}: {:?}",
+                name.to_owned() + model_name,
+                start.elapsed()
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,159 @@
+//! A small debugger for the interpreter. Each line is Forth to evaluate, unless it's a command:
+//!   break WORD, delete WORD    stop whenever WORD runs, or stop stopping there
+//!   step FORTH...              evaluate FORTH, stopping before the first XT it runs
+//! Once it stops, it takes these until it's told to carry on:
+//!   s(tep), n(ext), f(inish), c(ontinue)
+//!   stack, rstack, bt, mem ADDRESS [LEN], break WORD, delete WORD, breakpoints
+//! Run with `cargo run --release --features runtime --example debugger [PRELUDE...]`.
+
+use forsm::{Debugger, ForthVm, Options, Resume, Stop};
+use std::{
+    error::Error,
+    io::{self, BufRead, Write},
+    path::PathBuf,
+};
+
+const COMMANDS: &str =
+    "commands: s(tep) n(ext) f(inish) c(ontinue) stack rstack bt mem break delete breakpoints";
+
+fn prompt(prompt: &str) -> Option<String> {
+    print!("{}", prompt);
+    io::stdout().flush().ok()?;
+    let mut line = String::new();
+    match io::stdin().lock().read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line.trim().to_owned()),
+    }
+}
+
+// Addresses are decimal, or hex with a $ or 0x in front
+fn parse_number(text: &str) -> Option<i32> {
+    match text.strip_prefix('$').or_else(|| text.strip_prefix("0x")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok().map(|value| value as i32),
+        None => text.parse().ok(),
+    }
+}
+
+fn print_cells(cells: &[i32]) {
+    let cells: Vec<_> = cells.iter().map(|cell| cell.to_string()).collect();
+    println!("<{}> {}", cells.len(), cells.join(" "));
+}
+
+fn print_memory(stop: &Stop<'_>, address: i32, len: usize) {
+    let bytes = match stop.read_memory(address, len) {
+        Some(bytes) => bytes,
+        None => return println!("{} bytes at {} are out of bounds", len, address),
+    };
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        let hex: Vec<_> = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let text: String = chunk
+            .iter()
+            .map(|&byte| match byte {
+                0x20..=0x7e => byte as char,
+                _ => '.',
+            })
+            .collect();
+        println!(
+            "{:08x}  {:<47}  {}",
+            address as usize + line * 16,
+            hex.join(" "),
+            text
+        );
+    }
+}
+
+fn stopped(stop: &mut Stop<'_>) -> Resume {
+    let word = stop.word().unwrap_or("?").to_owned();
+    match stop.backtrace().first() {
+        Some(frame) => println!("stopped in {}, about to run {}", frame, word),
+        None => println!("stopped, about to run {}", word),
+    }
+    print_cells(&stop.stack());
+    loop {
+        let line = match prompt("(debug) ") {
+            Some(line) => line,
+            None => return Resume::Continue,
+        };
+        let mut args = line.split_whitespace();
+        match (args.next(), args.next()) {
+            (Some("s"), _) | (Some("step"), _) => return Resume::Step,
+            (Some("n"), _) | (Some("next"), _) => return Resume::StepOver,
+            (Some("f"), _) | (Some("finish"), _) => return Resume::StepOut,
+            (Some("c"), _) | (Some("continue"), _) => return Resume::Continue,
+            (Some("stack"), _) => print_cells(&stop.stack()),
+            (Some("rstack"), _) => print_cells(&stop.return_stack()),
+            (Some("bt"), _) => {
+                for frame in stop.backtrace() {
+                    println!("  in {}", frame);
+                }
+            }
+            (Some("mem"), Some(address)) => match parse_number(address) {
+                Some(address) => {
+                    let len = args.next().and_then(parse_number).unwrap_or(64);
+                    print_memory(stop, address, len as usize);
+                }
+                None => println!("{} isn't an address", address),
+            },
+            (Some("break"), Some(word)) => {
+                if !stop.set_breakpoint(word) {
+                    println!("no word called {}", word);
+                }
+            }
+            (Some("delete"), Some(word)) => {
+                if !stop.clear_breakpoint(word) {
+                    println!("no breakpoint on {}", word);
+                }
+            }
+            (Some("breakpoints"), _) => {
+                for word in stop.breakpoints() {
+                    println!("  {}", word);
+                }
+            }
+            (None, _) => {}
+            _ => println!("{}", COMMANDS),
+        }
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut options = Options::default();
+    let prelude: Vec<PathBuf> = std::env::args().skip(1).map(PathBuf::from).collect();
+    if !prelude.is_empty() {
+        options.prelude = prelude;
+    }
+    let vm = ForthVm::with_debugger(&options, Debugger::new(stopped))?;
+
+    while let Some(line) = prompt("> ") {
+        let source = match line.split_once(' ') {
+            Some(("break", word)) => {
+                if let Err(error) = vm.set_breakpoint(word.trim()) {
+                    println!("{}", error);
+                }
+                continue;
+            }
+            Some(("delete", word)) => {
+                match vm.clear_breakpoint(word.trim()) {
+                    Ok(true) => {}
+                    Ok(false) => println!("no breakpoint on {}", word.trim()),
+                    Err(error) => println!("{}", error),
+                }
+                continue;
+            }
+            Some(("step", source)) => {
+                vm.break_next()?;
+                source
+            }
+            _ => line.as_str(),
+        };
+        match vm.evaluate(source) {
+            Ok(output) => println!("{} ok", output),
+            Err(error) => {
+                println!("{}", error);
+                for frame in vm.backtrace()? {
+                    println!("  in {}", frame);
+                }
+            }
+        }
+    }
+    Ok(())
+}
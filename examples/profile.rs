@@ -0,0 +1,70 @@
+//! Profiles the interpreter loading its prelude, then running some scripts.
+//! Prints a flat profile of the words which ran the most XTs, and optionally writes
+//! the sampled stacks in the collapsed format which flamegraph tools read.
+//! Run with `cargo run --release --features runtime --example profile -- [OPTIONS] [SCRIPT...]`.
+
+use forsm::{profile, Options};
+use std::{error::Error, fs, path::PathBuf};
+
+const USAGE: &str = "Usage: profile [OPTIONS] [SCRIPT...]
+
+Options:
+      --period <XTS>      Sample the return stack every this many XTs (default: 100)
+      --collapsed <PATH>  Write the sampled stacks for a flamegraph
+      --top <WORDS>       How many words to list (default: 30)";
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut period = 100;
+    let mut collapsed = None;
+    let mut top = 30;
+    let mut input = String::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--period" => period = value()?.parse()?,
+            "--collapsed" => collapsed = Some(PathBuf::from(value()?)),
+            "--top" => top = value()?.parse()?,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            script => {
+                input += &fs::read_to_string(script)?;
+                input.push('\n');
+            }
+        }
+    }
+
+    let profile = profile(&Options::default(), &input, period)?;
+    print!("{}", profile.output);
+    println!(
+        "\n{} XTs run, the return stack sampled every {}",
+        profile.executed, profile.period
+    );
+    println!(
+        "{:>10} {:>10} {:>6} {:>10} {:>6}  word",
+        "calls", "self", "%", "total", "%"
+    );
+    let percent = |count: u64| 100.0 * count as f64 / profile.executed.max(1) as f64;
+    for word in profile.words.iter().take(top) {
+        println!(
+            "{:>10} {:>10} {:>5.1}% {:>10} {:>5.1}%  {}",
+            word.calls,
+            word.exclusive,
+            percent(word.exclusive),
+            word.inclusive,
+            percent(word.inclusive),
+            word.name
+        );
+    }
+    if let Some(path) = collapsed {
+        fs::write(&path, profile.collapsed())?;
+        println!(
+            "\nWrote {} stacks to {}",
+            profile.stacks.len(),
+            path.display()
+        );
+    }
+    Ok(())
+}
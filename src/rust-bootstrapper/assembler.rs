@@ -2,12 +2,16 @@ use anyhow::Result;
 use parity_wasm::{
     builder::{self, signature, GlobalBuilder, ModuleBuilder},
     elements::{
-        FuncBody,
+        CustomSection, FuncBody, FunctionNameSubsection,
         Instruction::{self, I32Const},
-        Instructions, Local, ValueType,
+        Instructions, Local, LocalNameSubsection, NameMap, NameSection, Section, ValueType,
     },
     serialize,
 };
+use std::collections::HashMap;
+
+mod validation;
+pub use validation::ValidationError;
 
 #[derive(Clone)]
 enum FuncSource {
@@ -19,6 +23,9 @@ struct Func {
     pub params: Vec<ValueType>,
     pub results: Vec<ValueType>,
     pub source: FuncSource,
+    // for the name section, so that traps and debuggers can say which function is which
+    pub name: Option<String>,
+    pub local_names: Vec<String>,
 }
 impl Func {
     pub fn is_import(&self) -> bool {
@@ -72,13 +79,16 @@ pub struct Assembler {
     globals: u32,
     functions: Vec<Func>,
     exported_functions: Vec<(String, u32)>,
+    exported_globals: Vec<(String, u32)>,
     table_entries: Vec<u32>,
+    custom_sections: Vec<CustomSection>,
 }
 impl Assembler {
-    pub fn add_memory(&mut self) {
+    pub fn add_memory(&mut self, min_pages: u32) {
         self.update(|builder| {
             builder
                 .memory()
+                .with_min(min_pages)
                 .with_max(Some(200))
                 .build()
                 .export()
@@ -135,6 +145,8 @@ impl Assembler {
             params,
             results,
             source: FuncSource::Imported { module, field },
+            name: None,
+            local_names: vec![],
         });
         // this isn't the "real" function index webassembly will use because imports come first,
         // but we will fix that at compile time.
@@ -156,18 +168,55 @@ impl Assembler {
             params,
             results,
             source: FuncSource::Native(body),
+            name: None,
+            local_names: vec![],
         });
         // this isn't the "real" function index webassembly will use because imports come first,
         // but we will fix that at compile time.
         index
     }
 
+    /// Replace the body of a native function, for one which calls functions defined after it
+    pub fn set_native_func_body(
+        &mut self,
+        func: u32,
+        locals: Vec<ValueType>,
+        instructions: Vec<Instruction>,
+    ) {
+        let locals = locals.into_iter().map(|t| Local::new(1, t)).collect();
+        let body = FuncBody::new(locals, Instructions::new(instructions));
+        self.functions[func as usize].source = FuncSource::Native(body);
+    }
+
+    /// Name a function, and its params and locals (in that order), in the module's name section
+    pub fn name_func(&mut self, func: u32, name: &str, locals: &[&str]) {
+        let func = &mut self.functions[func as usize];
+        func.name = Some(name.to_owned());
+        func.local_names = locals.iter().map(|local| local.to_string()).collect();
+    }
+
+    /// Add a custom section, which WASM runtimes ignore but other tools can read
+    pub fn add_custom_section(&mut self, name: &str, payload: Vec<u8>) {
+        self.custom_sections
+            .push(CustomSection::new(name.to_owned(), payload));
+    }
+
     pub fn add_exported_func(&mut self, field: &str, func: u32) {
         self.exported_functions.push((field.to_owned(), func));
     }
 
+    pub fn add_exported_global(&mut self, field: &str, global: u32) {
+        self.exported_globals.push((field.to_owned(), global));
+    }
+
     pub fn compile(mut self) -> Result<Vec<u8>> {
-        self.correct_function_indices();
+        let real_indices = self.correct_function_indices();
+        let names = self.name_section(&real_indices);
+        let function_names: HashMap<u32, String> = self
+            .functions
+            .iter()
+            .filter_map(|func| Some((real_indices[func.id as usize], func.name.clone()?)))
+            .collect();
         let mut builder = self
             .builder
             .unwrap()
@@ -181,6 +230,14 @@ impl Assembler {
         for (field, func) in self.exported_functions.iter() {
             builder = builder.export().field(field).internal().func(*func).build();
         }
+        for (field, global) in self.exported_globals.iter() {
+            builder = builder
+                .export()
+                .field(field)
+                .internal()
+                .global(*global)
+                .build();
+        }
         builder = builder
             .export()
             .field("__indirect_function_table")
@@ -188,12 +245,39 @@ impl Assembler {
             .table(0)
             .build();
 
-        let module = builder.build();
+        let mut module = builder.build();
+        // custom sections go after the data
+        for section in self.custom_sections {
+            module.sections_mut().push(Section::Custom(section));
+        }
+        module.sections_mut().push(Section::Name(names));
         let binary = serialize(module)?;
+        // catch mistakes in hand-written instructions here, instead of when wasmer loads the module
+        validation::validate(&binary, &function_names)?;
         Ok(binary)
     }
 
-    fn correct_function_indices(&mut self) {
+    fn name_section(&self, real_indices: &[u32]) -> NameSection {
+        let mut functions = FunctionNameSubsection::default();
+        let mut locals = LocalNameSubsection::default();
+        for func in self.functions.iter() {
+            let index = real_indices[func.id as usize];
+            if let Some(name) = &func.name {
+                functions.names_mut().insert(index, name.clone());
+            }
+            if !func.local_names.is_empty() {
+                let mut names = NameMap::default();
+                for (local, name) in func.local_names.iter().enumerate() {
+                    names.insert(local as u32, name.clone());
+                }
+                locals.local_names_mut().insert(index, names);
+            }
+        }
+        NameSection::new(None, Some(functions), Some(locals))
+    }
+
+    // Returns the real index of every function, by the index it was added with
+    fn correct_function_indices(&mut self) -> Vec<u32> {
         // Webassembly gives functions indices according to the order they were defined,
         // and requires all imports to be defined before any "native" functions.
         // Define them in the "appropriate" order
@@ -206,11 +290,14 @@ impl Assembler {
             real_indices[import.id as usize] = current_index;
             current_index += 1;
         }
-        for def in defs {
+        for def in defs.iter() {
             real_indices[def.id as usize] = current_index;
-            def.update_func_refs(&real_indices);
             current_index += 1;
         }
+        // functions can call others defined after them, so wait until every index is known
+        for def in defs {
+            def.update_func_refs(&real_indices);
+        }
 
         for (_, func_export) in self.exported_functions.iter_mut() {
             *func_export = real_indices[*func_export as usize];
@@ -218,6 +305,7 @@ impl Assembler {
         for table_entry in self.table_entries.iter_mut() {
             *table_entry = real_indices[*table_entry as usize];
         }
+        real_indices
     }
 
     fn update<T>(&mut self, func: T)
@@ -236,7 +324,9 @@ impl Default for Assembler {
             globals: 0,
             functions: vec![],
             exported_functions: vec![],
+            exported_globals: vec![],
             table_entries: vec![],
+            custom_sections: vec![],
         }
     }
 }
@@ -0,0 +1,73 @@
+use std::{collections::HashMap, fmt, ops::Range};
+use wasmparser::{ImportSectionEntryType, Parser, Payload, Validator};
+
+/// Why a module the assembler built isn't valid WASM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    /// The function which failed to validate, by its name in the name section
+    /// (like "forth:DUP" or "callable:DOCOL"), if the error was inside one
+    pub function: Option<String>,
+    /// The index of that function in the module
+    pub function_index: Option<u32>,
+    /// Where in the binary the error was found
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.function, self.function_index) {
+            (Some(name), _) => write!(f, "Invalid WASM in function {}: ", name)?,
+            (None, Some(index)) => write!(f, "Invalid WASM in function #{}: ", index)?,
+            (None, None) => write!(f, "Invalid WASM: ")?,
+        }
+        write!(f, "{} (at offset {:#x})", self.message, self.offset)
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/* Check a serialized module, and blame any error on the function it was found in.
+ * Names are by real function index, the same as the name section's. */
+pub fn validate(binary: &[u8], names: &HashMap<u32, String>) -> Result<(), ValidationError> {
+    let error = match Validator::new().validate_all(binary) {
+        Ok(()) => return Ok(()),
+        Err(error) => error,
+    };
+    let offset = error.offset();
+    let function_index = function_bodies(binary)
+        .into_iter()
+        .find(|(_, range)| range.contains(&offset))
+        .map(|(index, _)| index);
+    Err(ValidationError {
+        function: function_index.and_then(|index| names.get(&index).cloned()),
+        function_index,
+        offset,
+        message: error.message().to_owned(),
+    })
+}
+
+// The index of every function with a body, and where its body is in the binary
+fn function_bodies(binary: &[u8]) -> Vec<(u32, Range<usize>)> {
+    let mut imports = 0;
+    let mut bodies = vec![];
+    for payload in Parser::new(0).parse_all(binary) {
+        match payload {
+            Ok(Payload::ImportSection(section)) => {
+                for import in section.into_iter().flatten() {
+                    if let ImportSectionEntryType::Function(_) = import.ty {
+                        imports += 1;
+                    }
+                }
+            }
+            Ok(Payload::CodeSectionEntry(body)) => {
+                let range = body.range();
+                bodies.push((imports + bodies.len() as u32, range.start..range.end));
+            }
+            Ok(_) => {}
+            // whatever's left can't be parsed, let alone validated
+            Err(_) => break,
+        }
+    }
+    bodies
+}
@@ -1,11 +1,15 @@
-use super::compiler::{ColonValue::*, Compiler, ParamType::*};
+use super::compiler::{ColonValue::*, Compiler, ParamType::*, FILE_BUFFER_SIZE};
+use super::Options;
+use anyhow::{bail, Result};
+use std::path::PathBuf;
 
-/* Build a very basic INTERPRET word */
-pub fn build(compiler: &mut Compiler) {
+/* Build a very basic INTERPRET word, and a _start which includes the prelude */
+pub fn build(compiler: &mut Compiler, options: &Options) -> Result<()> {
     build_error_handling(compiler);
     build_io(compiler);
     build_parser(compiler);
     build_interpreter(compiler);
+    build_start(compiler, options)
 }
 
 fn build_error_handling(compiler: &mut Compiler) {
@@ -16,10 +20,107 @@ fn build_error_handling(compiler: &mut Compiler) {
         vec![I32],
         vec![],
     );
-    // exit the process if the error is nonzero ( err -- )
-    compiler.define_colon_word(
-        "THROW",
-        vec![XT("?DUP"), QBranch(4), XT("PROC-EXIT")],
+
+    // What was being interpreted, so that uncaught exceptions can say where they came from
+    compiler.define_variable_word(">SOURCE-NAME", 0);
+    compiler.define_variable_word("#SOURCE-NAME", 0);
+    compiler.define_variable_word("SOURCE-LINE", 0);
+    compiler.define_variable_word(">LAST-NAME", 0);
+    compiler.define_variable_word("#LAST-NAME", 0);
+
+    // Where each line of source started compiling, so that addresses can be traced back to it.
+    // Each entry is four cells: HERE when the line was read, the name of its source and that name's length,
+    // and the line number.
+    let layout = *compiler.layout();
+    compiler.define_constant_word("SOURCE-MAP", layout.source_map());
+    compiler.define_constant_word("SOURCE-MAP-CAPACITY", layout.source_map_capacity);
+    compiler.define_variable_word("#SOURCE-MAP", 0);
+    compiler.define_forth_word(
+        "RECORD-SOURCE",
+        r#"( c-addr u line -- ) \ remember that what's compiled from now on came from this line
+        #source-map @ if
+            #source-map @ 1- 16 * source-map + @ cp @ = if
+                -1 #source-map +! \ the last line compiled nothing, so this one replaces it
+            then
+        then
+        #source-map @ source-map-capacity = if
+            drop 2drop exit \ the map is full, so later lines go unmapped
+        then
+        #source-map @ 16 * source-map + >r
+        r@ 12 + ! r@ 8 + ! r@ 4 + ! cp @ r> !
+        1 #source-map +!
+        "#,
+    );
+    compiler.define_forth_word(
+        "FORGET-SOURCE",
+        r#"( -- ) \ there's no more context once a file is done
+        0 #source-name !
+        0 #last-name !
+        "#,
+    );
+
+    compiler.define_forth_word(
+        "(U.)",
+        r#"( u -- ) \ print an unsigned number, without a trailing space
+        10 u/mod ?dup if (u.) then
+        '0' + emit
+        "#,
+    );
+    compiler.define_forth_word(
+        "(.)",
+        r#"( n -- ) \ print a signed number, without a trailing space
+        dup <0 if '-' emit negate then
+        (u.)
+        "#,
+    );
+
+    compiler.define_forth_word(
+        ">ENCLOSING",
+        r#"( addr -- nt | 0 ) \ find the colon word whose body holds an address, like a saved IP
+        dup dict-base u< over cp @ u< =0 or if drop 0 exit then
+        latest @
+        begin dup while
+            2dup name>xt u> if \ words are in address order, so the newest one before addr holds it
+                nip dup name>xt @ (docol) <> if drop 0 then exit
+            then
+            name>backword
+        repeat
+        nip
+        "#,
+    );
+    compiler.define_forth_word(
+        ".BACKTRACE",
+        r#"( -- ) \ print the words which the last THROW unwound, innermost first
+        0 begin dup #backtrace @ < while
+            dup cells backtrace + @
+            dup >enclosing ?dup if
+                s"   in " type dup name>string type
+                '+' emit name>xt - (u.) 10 emit
+            else
+                drop \ not a return address, like a loop index
+            then
+            1+
+        repeat
+        drop
+        "#,
+    );
+
+    compiler.define_forth_word(
+        "(UNCAUGHT)",
+        r#"( n -- ) \ report an exception which nothing caught, and exit
+        0 'uncaught ! \ if reporting it throws too, just trap
+        s" Error " type dup (.)
+        #last-name @ if
+            s"  in " type >last-name @ #last-name @ type
+        then
+        #source-name @ if
+            s"  at " type >source-name @ #source-name @ type
+            ':' emit source-line @ (.)
+        then
+        10 emit
+        .backtrace
+        proc-exit
+        "#,
     );
 }
 
@@ -103,551 +204,557 @@ fn build_io(compiler: &mut Compiler) {
         vec![I32],
     );
 
-    compiler.define_constant_word("INBUF", 0x100);
+    let layout = *compiler.layout();
+
+    // INBUF usually points to a buffer which files are read into,
+    // but when including embedded source it points straight at the source text instead.
+    compiler.define_constant_word("FILEBUF", layout.file_buffer());
+    compiler.define_constant_word("FILEBUF-SIZE", FILE_BUFFER_SIZE);
+    compiler.define_variable_word("'INBUF", layout.file_buffer());
+    compiler.define_forth_word("INBUF", "( -- c-addr ) 'inbuf @");
     compiler.define_variable_word(">INBUF", 0);
     compiler.define_variable_word("#INBUF", 0);
 
     // iovec/ciovec are variables, the constants are just their addresses
-    compiler.define_constant_word("IOVEC", 0xf8);
-    compiler.define_constant_word("CIOVEC", 0xf0);
+    compiler.define_constant_word("IOVEC", layout.iovec());
+    compiler.define_constant_word("CIOVEC", layout.ciovec());
 
     compiler.define_variable_word(">SOURCE-ID", 0);
-    compiler.define_colon_word("SOURCE-ID", vec![XT(">SOURCE-ID"), XT("@")]);
-
-    // read a chunk of stdin into the file buffer ( -- )
-    #[rustfmt::skip]
-    compiler.define_colon_word(
+    compiler.define_forth_word("SOURCE-ID", "( -- fileid ) >source-id @");
+
+    compiler.define_forth_word(
+        "LOAD-FILE-CHUNK",
+        r#"( -- ) \ read a chunk of the current file into the file buffer
+        filebuf 'inbuf !
+        \ Prepare the iovec to fill the file buffer
+        inbuf iovec !
+        filebuf-size iovec 4 + !
+        \ try to read that many bytes
+        source-id iovec 1 #inbuf fd-read throw
+        \ reset stdinbuf pointer
+        0 >inbuf !
+        "#,
+    );
+
+    // the embedded source which hasn't been read yet
+    compiler.define_variable_word(">EMBEDDED", 0);
+    compiler.define_variable_word("#EMBEDDED", 0);
+
+    compiler.define_forth_word(
+        "LOAD-EMBEDDED-CHUNK",
+        r#"( -- ) \ "read" all remaining embedded source, by pointing the input buffer at it
+        >embedded @ 'inbuf !
+        #embedded @ #inbuf !
+        0 #embedded ! \ there's nothing left to read after this
+        0 >inbuf !
+        "#,
+    );
+
+    compiler.define_forth_word(
         "LOAD-INPUT-CHUNK",
-        vec![
-            // Prepare the iovec to read 1024 bytes into stdinbuf
-            XT("INBUF"), XT("IOVEC"), XT("!"),
-            Lit(1024), XT("IOVEC"), Lit(4), XT("+"), XT("!"),
-            // try to read 1024 bytes
-            XT("SOURCE-ID"), XT("IOVEC"), Lit(1), XT("#INBUF"), XT("FD-READ"), XT("THROW"),
-            // reset stdinbuf pointer
-            Lit(0), XT(">INBUF"), XT("!"),
-        ],
+        r#"( -- ) \ refill the input buffer from the current source
+        source-id -1 = if \ embedded source has an ID of -1
+            load-embedded-chunk
+        else
+            load-file-chunk
+        then
+        "#,
     );
 
-    compiler.define_colon_word(
-        "INBUF-EMPTY?",
-        vec![XT(">INBUF"), XT("@"), XT("#INBUF"), XT("@"), XT("=")],
-    );
+    compiler.define_forth_word("INBUF-EMPTY?", "( -- ? ) >inbuf @ #inbuf @ =");
 
     compiler.define_constant_word("EOF", -1);
-    // ( -- c|EOF )
-    #[rustfmt::skip]
-    compiler.define_colon_word(
+    // how many newlines have been read from the current source
+    compiler.define_variable_word("LINES-READ", 0);
+    compiler.define_forth_word(
         "READ-INPUT-CHAR",
-        vec![
-            XT("INBUF-EMPTY?"), QBranch(24),
-            XT("LOAD-INPUT-CHUNK"), // read into the stdin buffer if we need to
-            XT("INBUF-EMPTY?"), QBranch(8),
-            XT("EOF"), XT("EXIT"), // If stdin is STILL empty after loading a chunk, it's really empty
-            XT("INBUF"), XT(">INBUF"), XT("@"), XT("+"), XT("C@"), // return the first character from the buffer
-            Lit(1), XT(">INBUF"), XT("+!"), // advance the buffer pointer
-        ],
-    );
-
-    // is this character a line terminator? ( c -- ? )
-    #[rustfmt::skip]
-    compiler.define_colon_word(
-        "IS-TERM?",
-        vec![
-            XT("DUP"), Lit(13), XT("="),
-            XT("SWAP"), Lit(10), XT("="), XT("OR"),
-        ],
-    );
-
-    // ( c-addr u -- n )
-    #[rustfmt::skip]
-    compiler.define_colon_word(
+        r#"( -- c|EOF )
+        inbuf-empty? if
+            load-input-chunk \ read into the stdin buffer if we need to
+            inbuf-empty? if
+                eof exit \ If stdin is STILL empty after loading a chunk, it's really empty
+            then
+        then
+        inbuf >inbuf @ + c@ \ return the first character from the buffer
+        1 >inbuf +! \ advance the buffer pointer
+        dup 10 = if 1 lines-read +! then
+        "#,
+    );
+
+    // is this character a line terminator?
+    compiler.define_forth_word("IS-TERM?", "( c -- ? ) dup 13 = swap 10 = or");
+
+    compiler.define_forth_word(
         "ACCEPT",
-        vec![
-            XT("DUP"), XT("=0"), QBranch(16), // if someone is not asking for chars
-            XT("2DROP"), Lit(0), XT("EXIT"), // return early
-
-            XT("DUP"), XT(">R"), // hold onto the original requested length for later
-
-            // start of loop ( c-addr u )
-            XT("READ-INPUT-CHAR"),
-            XT("DUP"), XT("EOF"), XT("<>"), // while we haven't hit the end of the file
-            XT("OVER"), XT("IS-TERM?"), XT("AND"), // and we're reading newlines 
-            QBranch(12),
-            XT("DROP"), // discard the character
-            Branch(-48),
-
-            // start of loop ( c-addr u c|eof )
-            XT("DUP"), XT("EOF"), XT("<>"), // while we haven't hit EOF
-            XT("OVER"), XT("IS-TERM?"), XT("=0"), XT("AND"), // and we haven't hit newlines
-            QBranch(64),
-            XT("ROT"), XT("SWAP"), XT("OVER"), XT("C!"), // write to the buffer
-            XT("1+"), XT("SWAP"), XT("1-"), // increment the buffer
-            XT("DUP"), QBranch(12), XT("READ-INPUT-CHAR"), Branch(4), XT("EOF"), // grab the next char (or EOF if we're done)
-            Branch(-100),
-
-            XT("DROP"), // drop the final newline/EOF we read
-            XT("NIP"), XT("R>"), XT("SWAP"), XT("-"), // return the char count
-        ],
+        r#"( c-addr u -- n )
+        dup =0 if \ if someone is not asking for chars
+            2drop 0 exit \ return early
+        then
+
+        dup >r \ hold onto the original requested length for later
+
+        begin ( c-addr u )
+            read-input-char
+            dup eof <> \ while we haven't hit the end of the file
+            over is-term? and \ and we're reading newlines
+        while
+            drop \ discard the character
+        repeat
+        lines-read @ 1+ source-line ! \ this is the line we're reading
+
+        begin ( c-addr u c|eof )
+            dup eof <> \ while we haven't hit EOF
+            over is-term? =0 and \ and we haven't hit newlines
+        while
+            rot swap over c! \ write to the buffer
+            1+ swap 1- \ increment the buffer
+            dup if read-input-char else eof then \ grab the next char (or EOF if we're done)
+        repeat
+
+        drop \ drop the final newline/EOF we read
+        nip r> swap - \ return the char count
+        "#,
     );
 
     // using a variable as a 1-byte buffer holding the character to EMIT
     compiler.define_variable_word("EMIT-BUFFER", 0);
     compiler.define_variable_word("BYTES-WRITTEN", 0);
 
-    // ( c -- )
-    #[rustfmt::skip]
-    compiler.define_colon_word(
+    compiler.define_forth_word(
         "EMIT",
-        vec![
-            XT("EMIT-BUFFER"), XT("!"), // store the character in a buffer
-            XT("EMIT-BUFFER"), XT("CIOVEC"), XT("!"), // set up the ciovec
-            Lit(1), XT("CIOVEC"), Lit(4), XT("+"), XT("!"),
-            Lit(1), XT("CIOVEC"), Lit(1), XT("BYTES-WRITTEN"), XT("FD-WRITE"), XT("THROW"),
-        ],
+        r#"( c -- )
+        emit-buffer ! \ store the character in a buffer
+        emit-buffer ciovec ! \ set up the ciovec
+        1 ciovec 4 + !
+        1 ciovec 1 bytes-written fd-write throw
+        "#,
     );
 
-    // ( c-addr u -- )
-    #[rustfmt::skip]
-    compiler.define_colon_word(
+    compiler.define_forth_word(
         "TYPE",
-        vec![
-            // store the buffer in our ciovec
-            XT("SWAP"), XT("CIOVEC"), XT("!"),
-            // start of loop
-            XT("?DUP"), XT(">0"), QBranch(88), // while we have bytes to write..
-            // try to write U bytes to the file
-            XT("DUP"), XT("CIOVEC"), Lit(4), XT("+"), XT("!"),
-            Lit(1), XT("CIOVEC"), Lit(1), XT("BYTES-WRITTEN"), XT("FD-WRITE"), XT("THROW"),
-            XT("BYTES-WRITTEN"), XT("@"),
-            // however many bytes we write, inc the buffer by that much
-            XT("DUP"), XT("CIOVEC"), XT("+!"),
-            // subtract BYTES-WRITTEN from what's left to write
-            XT("-"),
-            // and start again
-            Branch(-104),
-            // we're done!
-        ],
+        r#"( c-addr u -- )
+        swap ciovec ! \ store the buffer in our ciovec
+        begin
+            dup >0 \ while we have bytes to write..
+        while
+            \ try to write U bytes to the file
+            dup ciovec 4 + !
+            1 ciovec 1 bytes-written fd-write throw
+            bytes-written @
+            \ however many bytes we write, inc the buffer by that much
+            dup ciovec +!
+            \ subtract BYTES-WRITTEN from what's left to write
+            -
+            \ and start again
+        repeat
+        drop \ we're done!
+        "#,
     );
 
     compiler.define_constant_word("INIT-DIR-FD", 4);
     compiler.define_variable_word(">FD", 0);
 
-    // ( c-addr u fam -- fileid err )
-    #[rustfmt::skip]
-    compiler.define_colon_word(
+    compiler.define_forth_word(
         "OPEN-FILE",
-        vec![
-            XT("DROP"), // ignore fam for now, just implementing reads
-            XT("INIT-DIR-FD"), Lit(0), XT("2SWAP"),
-            Lit(0), Lit(0x1fffffff), Lit(0), Lit(0x1fffffff), Lit(0), // give ourselves full rights
-            Lit(0), XT(">FD"),
-            XT("PATH-OPEN"), // finally actually call this function
-            XT(">FD"), XT("@"), XT("SWAP"),
-        ],
+        r#"( c-addr u fam -- fileid err )
+        drop \ ignore fam for now, just implementing reads
+        init-dir-fd 0 2swap
+        0 $1fffffff 0 $1fffffff 0 \ give ourselves full rights
+        0 >fd
+        path-open \ finally actually call this function
+        >fd @ swap
+        "#,
     );
 }
 
 fn build_parser(compiler: &mut Compiler) {
     compiler.define_variable_word(">IN", 0);
 
-    compiler.define_constant_word("TIB", 0x10);
-    compiler.define_constant_word("TIB-MAX", 0xc0);
+    let layout = *compiler.layout();
+    compiler.define_constant_word("TIB", layout.tib());
+    compiler.define_constant_word("TIB-MAX", layout.tib_size);
     compiler.define_variable_word("#TIB", 0);
 
-    // refill TIB from stdin, return whether stdin is empty
-    // ( -- ? )
-    #[rustfmt::skip]
-    compiler.define_colon_word(
+    compiler.define_forth_word(
         "REFILL",
-        vec![
-            Lit(0), XT(">IN"), XT("!"), // Reset >IN
-            XT("TIB"), XT("TIB-MAX"), XT("ACCEPT"), // Read a line
-            XT("DUP"), XT("#TIB"), XT("!"), // store the new length of TIB
-            XT("<>0"), // return if it's nonzero
-        ],
+        r#"( -- ? ) \ refill TIB from stdin, return whether stdin is empty
+        0 >in ! \ Reset >IN
+        tib tib-max accept \ Read a line
+        >source-name @ #source-name @ source-line @ record-source
+        dup #tib ! \ store the new length of TIB
+        <>0 \ return if it's nonzero
+        "#,
     );
 
-    // current address and length of the input buffer ( -- c-addr u )
-    compiler.define_colon_word("SOURCE", vec![XT("TIB"), XT("#TIB"), XT("@")]);
+    // current address and length of the input buffer
+    compiler.define_forth_word("SOURCE", "( -- c-addr u ) tib #tib @");
 
-    // is there anything to parse ( -- ? )
-    compiler.define_colon_word(
-        "PARSING?",
-        vec![XT(">IN"), XT("@"), XT("SOURCE"), XT("NIP"), XT("<>")],
-    );
+    // is there anything to parse
+    compiler.define_forth_word("PARSING?", "( -- ? ) >in @ source nip <>");
 
-    // get the address of the head of the parse area ( -- addr )
-    compiler.define_colon_word(
-        "'IN",
-        vec![XT(">IN"), XT("@"), XT("SOURCE"), XT("DROP"), XT("+")],
-    );
+    // get the address of the head of the parse area
+    compiler.define_forth_word("'IN", "( -- addr ) >in @ source drop +");
 
-    // get first character in the parse area ( -- c )
-    compiler.define_colon_word("IN@", vec![XT("'IN"), XT("C@")]);
+    // get first character in the parse area
+    compiler.define_forth_word("IN@", "( -- c ) 'in c@");
 
-    // Increment the head of the parse area ( -- )
-    compiler.define_colon_word("1+IN!", vec![Lit(1), XT(">IN"), XT("+!")]);
+    // Increment the head of the parse area
+    compiler.define_forth_word("1+IN!", "( -- ) 1 >in +!");
 
-    // Parse from the input buffer until we see a delimiter ( c -- c-addr u )
-    #[rustfmt::skip]
-    compiler.define_colon_word(
+    compiler.define_forth_word(
         "PARSE",
-        vec![
-            // store the current parse location on the stack, it's word start
-            XT("'IN"), XT("SWAP"),
-            // parse until we see char-to-ignore or we're done
-            XT("PARSING?"),
-            XT("OVER"), XT("IN@"), XT("<>"),
-            XT("AND"), QBranch(12),
-            XT("1+IN!"),
-            Branch(-40),
-            // store the current parse location on the stack, it's word end
-            XT("'IN"), XT("SWAP"),
-            // consume ending delimiters
-            XT("PARSING?"),
-            XT("OVER"), XT("IN@"), XT("="),
-            XT("AND"), QBranch(12),
-            XT("1+IN!"),
-            Branch(-40),
-            // Get char-to-ignore off the stack, turn word end into a length
-            XT("DROP"), XT("OVER"), XT("-"),
-        ]
-    );
-
-    // Parse a word from the input buffer ( -- c-addr u )
-    #[rustfmt::skip]
-    compiler.define_colon_word(
+        r#"( c -- c-addr u ) \ Parse from the input buffer until we see a delimiter
+        \ store the current parse location on the stack, it's word start
+        'in swap
+        \ parse until we see char-to-ignore or we're done
+        begin parsing? over in@ <> and
+        while 1+in!
+        repeat
+        \ store the current parse location on the stack, it's word end
+        'in swap
+        \ consume ending delimiters
+        begin parsing? over in@ = and
+        while 1+in!
+        repeat
+        \ Get char-to-ignore off the stack, turn word end into a length
+        drop over -
+        "#,
+    );
+
+    compiler.define_forth_word(
         "PARSE-NAME",
-        vec![
-            // ignore leading spaces
-            XT("PARSING?"),
-            Lit(32), XT("IN@"), XT("="),
-            XT("AND"), QBranch(12),
-            XT("1+IN!"),
-            Branch(-44),
-            // we are at the head of our word! parse the rest normally
-            Lit(32), XT("PARSE"),
-        ],
-    );
-
-    // Capitalize a character ( c -- C )
-    #[rustfmt::skip]
-    compiler.define_colon_word(
+        r#"( -- c-addr u ) \ Parse a word from the input buffer
+        \ ignore leading spaces
+        begin parsing? 32 in@ = and
+        while 1+in!
+        repeat
+        \ we are at the head of our word! parse the rest normally
+        32 parse
+        "#,
+    );
+
+    compiler.define_forth_word(
         "UPCHAR",
-        vec![
-            XT("DUP"), Lit(97 /* a */), XT(">="),
-            XT("OVER"), Lit(122 /* z */), XT("<="), XT("AND"),
-            QBranch(12),
-            Lit(32), XT("-")
-        ],
+        r#"( c -- C ) \ Capitalize a character
+        dup 'a' >= over 'z' <= and if
+            32 -
+        then
+        "#,
     );
 
     compiler.define_variable_word("BASE", 10);
 
-    // try to parse a digit ( c -- n -1 | 0 )
-    #[rustfmt::skip]
-    compiler.define_colon_word(
+    compiler.define_forth_word(
         "?DIGIT",
-        vec![
-            XT("UPCHAR"), // parse hex as uppercase
-
-            XT("DUP"), Lit(48), XT(">="),
-            XT("OVER"), Lit(57), XT("<="), XT("AND"),
-            QBranch(20), // if [0-9]
-            Lit(48), XT("-"), // subtract '0'
-
-            Branch(76), // else
-            XT("DUP"), Lit(65), XT(">="),
-            XT("OVER"), Lit(90), XT("<="), XT("AND"),
-            QBranch(12), // if [A-Z]
-            Lit(55), XT("-"), // subtract 'A', add 10
-
-            Branch(12), // else
-            XT("DROP"), XT("FALSE"), XT("EXIT"), // not a digit
-            // end if
-
-            XT("DUP"), XT("BASE"), XT("@"), XT(">="),
-            QBranch(16), // if this isn't a valid digit in the current base
-            XT("DROP"), XT("FALSE"),
-            Branch(4), // else
-            XT("TRUE"),
-        ],
-    );
-
-    // Try parsing a string as a number ( c-addr u -- n -1 | 0 )
-    #[rustfmt::skip]
-    compiler.define_colon_word(
+        r#"( c -- n -1 | 0 ) \ try to parse a digit
+        upchar \ parse hex as uppercase
+
+        dup '0' >= over '9' <= and if
+            '0' -
+        else dup 'A' >= over 'Z' <= and if
+            55 - \ subtract 'A', add 10
+        else
+            drop false exit \ not a digit
+        then then
+
+        dup base @ >= if \ if this isn't a valid digit in the current base
+            drop false
+        else
+            true
+        then
+        "#,
+    );
+
+    compiler.define_forth_word(
         "?NUMBER",
-        vec![
-            XT("DUP"), XT("=0"),
-            QBranch(12), // if the string is empty, it's not a number
-            XT("2DROP"), XT("FALSE"), XT("EXIT"),
-
-            XT("OVER"), XT("C@"), Lit(45), XT("="), // does it start with -?
-            XT("DUP"), XT(">R"), // store whether it does on the return stack
-            QBranch(16), // if it does, skip past the -
-            XT("1-"), XT("SWAP"), XT("1+"), XT("SWAP"),
-
-            XT("DUP"), XT("=0"),
-            QBranch(12), // if we're out of characters NOW it's also not a number
-            XT("2DROP"), XT("FALSE"), XT("EXIT"),
-
-            XT("OVER"), XT("+"), XT(">R"), // store our final str-address in the return stack
-            Lit(0), // store our running summation on the stack
-            XT("SWAP"),
-
-            // start loop ( n c-addr )
-            XT("DUP"), XT("C@"), XT("?DIGIT"), XT("=0"),
-            QBranch(24), // if the next char is NOT a digit
-            XT("R>"), XT("R>"), XT("2DROP"), XT("2DROP"), // clean the stack
-            XT("FALSE"), XT("EXIT"), // and get outta here
-            // end if
-
-            XT("ROT"), XT("BASE"), XT("@"), XT("*"), XT("+"), XT("SWAP"), // add digit to running total
-            XT("1+"), // increment address
-            XT("DUP"), XT("R@"), XT("="), // if we're out of input,
-            QBranch(-96), // back to start of loop
-
-            XT("DROP"), // we're done with the input string
-            XT("R>"), XT("DROP"), // we're done with the target string
-
-            // negate it if we have to, add TRUE, and we're good
-            XT("R>"), QBranch(16),
-            Lit(0), XT("SWAP"), XT("-"),
-            XT("TRUE")
-        ],
-    );
-}
+        r#"( c-addr u -- n -1 | 0 ) \ Try parsing a string as a number
+        dup ?branch not-a-number \ if the string is empty, it's not a number
 
-fn build_interpreter(compiler: &mut Compiler) {
-    // Case-insensitive string equality against a known-capital string
-    // ( c-addr1 u1 C-ADDR U2 -- ? )
-    #[rustfmt::skip]
-    compiler.define_colon_word(
-        "STR-UPPER-EQ?",
-        vec![
-            XT("ROT"), XT("SWAP"), // ( c-addr1 c-addr2 u1 u2 )
-            XT("OVER"), XT("<>"), QBranch(16), // If lengths mismatch, return now
-            XT("2DROP"), XT("DROP"), XT("FALSE"), XT("EXIT"),
-            // then
+        over c@ '-' = \ does it start with -?
+        dup >r \ store whether it does on the return stack
+        if \ if it does, skip past the -
+            1- swap 1+ swap
+        then
 
-            // stack is now ( c-addr1 c-addr2 u )
-            // start of loop
-            XT("?DUP"), QBranch(96), // if length is 0, break outta the loop
+        dup ?branch not-a-number \ if we're out of characters NOW it's also not a number
 
-            XT(">R"), // push length into return stack
-            XT("OVER"), XT("C@"), XT("UPCHAR"), XT("OVER"), XT("C@"), XT("<>"), // are chars not-equal?
-            QBranch(28), // if
-            XT("R>"), XT("2DROP"), XT("DROP"), //fix the stacks
-            XT("FALSE"), XT("EXIT"), // return false
-            Branch(24), // else
-            XT("SWAP"), XT("1+"), XT("SWAP"), XT("1+"), // increment pointers
-            XT("R>"), XT("1-"), // get the count out of the return stack and decremented
-            // then
+        over + >r \ store our final str-address in the return stack
+        0 \ store our running summation on the stack
+        swap
 
-            Branch(-108), // end of loop
+        begin ( n c-addr )
+            dup c@ ?digit =0 if \ if the next char is NOT a digit
+                r> r> 2drop \ clean the return stack
+                branch not-a-number \ and get outta here
+            then
 
-            XT("2DROP"), XT("TRUE"), // if we made it this far we win!
-        ],
-    );
+            rot base @ * + swap \ add digit to running total
+            1+ \ increment address
+            dup r@ = \ until we're out of input
+        until
+
+        drop \ we're done with the input string
+        r> drop \ we're done with the target string
 
-    // Given an address, return the next aligned address ( addr -- addr )
-    compiler.define_colon_word("ALIGNED", vec![Lit(3), XT("+"), Lit(-4), XT("AND")]);
+        \ negate it if we have to, add TRUE, and we're good
+        r> if 0 swap - then
+        true exit
 
-    // Given a name token, get its length ( nt -- u )
-    compiler.define_colon_word("NAME>U", vec![XT("C@"), Lit(31), XT("AND")]);
+        label not-a-number ( c-addr u )
+        2drop false
+        "#,
+    );
+}
 
-    // Given a name token, get the name ( nt -- c-addr u )
-    #[rustfmt::skip]
-    compiler.define_colon_word(
+fn build_interpreter(compiler: &mut Compiler) {
+    compiler.define_forth_word(
+        "STR-UPPER-EQ?",
+        r#"( c-addr1 u1 C-ADDR U2 -- ? ) \ Case-insensitive string equality against a known-capital string
+        rot swap ( c-addr1 c-addr2 u1 u2 )
+        over <> if \ If lengths mismatch, return now
+            2drop drop false exit
+        then
+
+        \ stack is now ( c-addr1 c-addr2 u )
+        begin
+            ?dup \ if length is 0, break outta the loop
+        while
+            >r \ push length into return stack
+            over c@ upchar over c@ <> if \ are chars not-equal?
+                r> 2drop drop \ fix the stacks
+                false exit \ return false
+            else
+                swap 1+ swap 1+ \ increment pointers
+                r> 1- \ get the count out of the return stack and decremented
+            then
+        repeat
+
+        2drop true \ if we made it this far we win!
+        "#,
+    );
+
+    // Given an address, return the next aligned address
+    compiler.define_forth_word("ALIGNED", "( addr -- addr ) 3 + -4 and");
+
+    // Given a name token, get its length
+    compiler.define_forth_word("NAME>U", "( nt -- u ) c@ 31 and");
+
+    // Given a name token, get the name
+    compiler.define_forth_word(
         "NAME>STRING",
-        vec![
-            XT("DUP"), XT("1+"), // start of the name is 1 byte after the head
-            XT("SWAP"), XT("NAME>U"),
-        ],
+        r#"( nt -- c-addr u )
+        dup 1+ \ start of the name is 1 byte after the head
+        swap name>u"#,
     );
 
-    // given a name token, get the name token before it ( nt -- nt | 0 )
-    #[rustfmt::skip]
-    compiler.define_colon_word(
+    // given a name token, get the name token before it
+    compiler.define_forth_word(
         "NAME>BACKWORD",
-        vec![
-            XT("DUP"), XT("NAME>U"), // get word length
-            XT("1+"), XT("+"), // backword is 1 + len bytes into the def
-            XT("ALIGNED"),
-            XT("@"),
-        ],
-    );
-
-    // given a name token, does that token point to an immediate word? ( nt -- ? )
-    compiler.define_colon_word(
-        "NAME>IMMEDIATE?",
-        vec![XT("C@"), Lit(128), XT("AND"), XT("<>0")],
-    );
-    // given a name token, is that token trampolined to a host word? ( nt -- ? )
-    compiler.define_colon_word(
-        "NAME>TRAMPOLINED?",
-        vec![XT("C@"), Lit(64), XT("AND"), XT("<>0")],
-    );
-    compiler.define_colon_word("+NAME>IMMEDIATE?", vec![Lit(128), XT("SWAP"), XT("CSET")]);
-    compiler.define_colon_word("+NAME>TRAMPOLINED?", vec![Lit(64), XT("SWAP"), XT("CSET")]);
+        r#"( nt -- nt | 0 )
+        dup name>u \ get word length
+        1+ + \ backword is 1 + len bytes into the def
+        aligned @"#,
+    );
+
+    // given a name token, does that token point to an immediate word?
+    compiler.define_forth_word("NAME>IMMEDIATE?", "( nt -- ? ) c@ 128 and <>0");
+    // given a name token, is that token trampolined to a host word?
+    compiler.define_forth_word("NAME>TRAMPOLINED?", "( nt -- ? ) c@ 64 and <>0");
+    compiler.define_forth_word("+NAME>IMMEDIATE?", "( nt -- ) 128 swap cset");
+    compiler.define_forth_word("+NAME>TRAMPOLINED?", "( nt -- ) 64 swap cset");
     // The "hidden bit" is the high bit of the first character of the identifier.
     // This is always safe to use (even words with a 0-length name will have padding here),
     // and automatically causes string equality checks for ASCII input to fail.
-    compiler.define_colon_word("+NAME>HIDDEN?", vec![Lit(32768), XT("SWAP"), XT("CSET")]);
-    compiler.define_colon_word("-NAME>HIDDEN?", vec![Lit(32768), XT("SWAP"), XT("CRESET")]);
+    compiler.define_forth_word("+NAME>HIDDEN?", "( nt -- ) 32768 swap cset");
+    compiler.define_forth_word("-NAME>HIDDEN?", "( nt -- ) 32768 swap creset");
 
-    // given a name token, get the execution token ( nt -- xt )
+    // given a name token, get the execution token
     // xt is 1 + len + 4 bytes in to the definition, plus alignment
-    compiler.define_colon_word(
-        "NAME>XT",
-        vec![
-            XT("DUP"),
-            XT("NAME>U"),
-            XT("+"),
-            Lit(5),
-            XT("+"),
-            XT("ALIGNED"),
-        ],
-    );
-
-    // Find the address of some word ( c-addr u -- nt | 0 )
-    #[rustfmt::skip]
-    compiler.define_colon_word(
-        "FIND-NAME",
-        vec![
-            XT("LATEST"), XT("@"), // start at the end of the dictionary
-
-            // start of loop
-            XT("DUP"), XT("=0"), // if we've found null
-            QBranch(16), // give up
-            XT("2DROP"), XT("DROP"), // flush the stack
-            XT("FALSE"), XT("EXIT"), // and exit with haste and falseness
+    compiler.define_forth_word("NAME>XT", "( nt -- xt ) dup name>u + 5 + aligned");
 
-            XT(">R"), XT("2DUP"), // set up copies of c-addr and u
-            XT("R@"), XT("NAME>STRING"), // and extract the name from the nt
-            XT("STR-UPPER-EQ?"),// Are they equal?
-
-            QBranch(20), // this IS it chief!
-            XT("2DROP"), // get rid of c-addr and u
-            XT("R>"), XT("EXIT"), // return the address of the word
-            Branch(8), // this ain't it chief
-            XT("R>"), XT("NAME>BACKWORD"), // go to the previous def
-            Branch(-96), // end of loop
-        ],
+    compiler.define_forth_word(
+        "FIND-NAME",
+        r#"( c-addr u -- nt | 0 ) \ Find the address of some word
+        latest @ \ start at the end of the dictionary
+        begin
+            dup =0 if \ if we've found null, give up
+                2drop drop \ flush the stack
+                false exit \ and exit with haste and falseness
+            then
+
+            >r 2dup \ set up copies of c-addr and u
+            r@ name>string \ and extract the name from the nt
+            str-upper-eq? if \ this IS it chief!
+                2drop \ get rid of c-addr and u
+                r> exit \ return the address of the word
+            then \ this ain't it chief
+            r> name>backword \ go to the previous def
+        again
+        "#,
     );
 
     compiler.define_variable_word("STATE", 0);
-    compiler.define_colon_word("COMPILING?", vec![XT("STATE"), XT("@")]);
+    compiler.define_forth_word("COMPILING?", "( -- ? ) state @");
+
+    compiler.define_forth_word(
+        "ALLOT",
+        r#"( n -- ) \ reserve n bytes at the end of the dictionary
+        dup cp @ + dict-limit @ u> if \ it doesn't fit before the end of the dictionary's space
+            -8 throw \ dictionary overflow, before it runs into the stacks or the next heap block
+        then
+        cp +! \ shift end of dictionary over
+        "#,
+    );
 
-    // append a cell to the end of the dictionary( n -- )
-    #[rustfmt::skip]
-    compiler.define_colon_word(
+    compiler.define_forth_word(
         ",",
-        vec![
-            XT("CP"), XT("@"), XT("!"), // save value at end of dictionary
-            Lit(4), XT("CP"), XT("+!"), // shift end of dictionary over
-        ],
+        r#"( n -- ) \ append a cell to the end of the dictionary
+        4 allot cp @ 4 - ! \ save value at end of dictionary
+        "#,
     );
 
-    // append a byte to the end of the dictionary ( n -- )
-    #[rustfmt::skip]
-    compiler.define_colon_word(
+    compiler.define_forth_word(
         "C,",
-        vec![
-            XT("CP"), XT("@"), XT("C!"), // save value at end of dictionary
-            Lit(1), XT("CP"), XT("+!"), // shift end of dictionary over
-        ],
+        r#"( c -- ) \ append a byte to the end of the dictionary
+        1 allot cp @ 1- c! \ save value at end of dictionary
+        "#,
     );
 
-    // append a compiled literal to the end of the dictionary ( n -- )
-    compiler.define_colon_word(
-        "COMPILE-LITERAL",
-        vec![XT("LIT"), XT("LIT"), XT(","), XT(",")],
-    );
+    // append a compiled literal to the end of the dictionary
+    compiler.define_forth_word("COMPILE-LITERAL", "( n -- ) lit lit , ,");
 
-    // Perform interpretation semantics for a word ( nt -- )
-    compiler.define_colon_word("INTERPRET-NAME", vec![XT("NAME>XT"), XT("EXECUTE")]);
+    // Perform interpretation semantics for a word
+    compiler.define_forth_word("INTERPRET-NAME", "( nt -- ) name>xt execute");
 
-    // Perform compilation semantics for a word ( nt -- )
-    #[rustfmt::skip]
-    compiler.define_colon_word(
+    compiler.define_forth_word(
         "COMPILE-NAME",
-        vec![
-            XT("DUP"), XT("NAME>XT"), // get the word's XT
-            XT("SWAP"), XT("NAME>IMMEDIATE?"), // is the word immediate?
-            QBranch(12),   // if so,
-            XT("EXECUTE"), // run it right away
-            Branch(4),     // else,
-            XT(","),       // bake it in
-        ],
-    );
-
-    // execute words in a loop until the input buffer empties ( -- )
-    #[rustfmt::skip]
-    compiler.define_colon_word(
+        r#"( nt -- ) \ Perform compilation semantics for a word
+        dup name>xt \ get the word's XT
+        swap name>immediate? if \ is the word immediate?
+            execute \ if so, run it right away
+        else
+            , \ else, bake it in
+        then
+        "#,
+    );
+
+    compiler.define_forth_word(
         "INTERPRET",
-        vec![
-            // start of loop
-            XT("PARSE-NAME"), // parse a space-delimited word from input
-
-            XT("DUP"), XT("=0"),
-            QBranch(8), // if the word is 0-length, we're done!
-            XT("2DROP"), XT("EXIT"),
-
-            XT("2DUP"), XT("FIND-NAME"), // look it up in the dictionary
-            XT("?DUP"),
-
-            QBranch(44), // if we found the word in the dictionary,
-            XT("NIP"), XT("NIP"), // clean the name out of the stack, we're done with it
-            XT("COMPILING?"),
-            QBranch(12),
-            XT("COMPILE-NAME"),
-            Branch(4),
-            XT("INTERPRET-NAME"),
-
-            Branch(48), // if we did not find the word,
-            XT("?NUMBER"), // maybe it's a number?
-            QBranch(24),  // if so, either bake the value in or leave it on the stack
-            XT("COMPILING?"),
-            QBranch(4),
-            XT("COMPILE-LITERAL"),
-            Branch(12), // if not, error and exit
-            Lit(-1), XT("THROW"),
-
-            Branch(-148), // end of loop
-        ],
-    );
-
-    // include a file by path ( c-addr u -- )
-    #[rustfmt::skip]
-    compiler.define_colon_word(
+        r#"( i*x -- j*x ) \ execute words in a loop until the input buffer empties
+        begin
+            parse-name \ parse a space-delimited word from input
+            dup =0 if \ if the word is 0-length, we're done!
+                2drop exit
+            then
+
+            2dup #last-name ! >last-name ! \ remember it in case it throws
+            2dup find-name \ look it up in the dictionary
+            ?dup if \ if we found the word in the dictionary,
+                nip nip \ clean the name out of the stack, we're done with it
+                compiling? if compile-name else interpret-name then
+            else \ if we did not find the word,
+                ?number if \ maybe it's a number? if so, either bake the value in or leave it on the stack
+                    compiling? if compile-literal then
+                else \ if not, error and exit
+                    -1 throw
+                then
+            then
+        again
+        "#,
+    );
+
+    compiler.define_forth_word(
         "INCLUDED",
-        vec![
-            XT("SOURCE-ID"), XT("THROW"),         // for now, can't load 2 files at once
-            Lit(0), XT("OPEN-FILE"), XT("THROW"), // actually open the file
-            XT(">SOURCE-ID"), XT("!"),            // switch to the FD
-
-            // start of execution loop
-            XT("REFILL"),
-            QBranch(12),     // quit if we are done
-            XT("INTERPRET"), // run code
-            Branch(-24),     // Good! Now do it again
-
-            XT("SOURCE-ID"), XT("FD-CLOSE"), XT("THROW"), // close the file
-            Lit(0), XT(">SOURCE-ID"), XT("!"),            // reset source
-        ],
-    );
-
-    let start_instructions: Vec<_> = std::fs::read_dir("./src/prelude")
-        .unwrap()
-        .flat_map(|file| {
-            let raw_name = file.unwrap().file_name();
-            let name = format!("src/prelude/{}", raw_name.to_string_lossy());
-            vec![StringLit(name), XT("INCLUDED")]
-        })
-        .collect();
-
-    compiler.define_colon_word("_start", start_instructions);
+        r#"( c-addr u -- ) \ include a file by path
+        source-id throw \ for now, can't load 2 files at once
+        2dup #source-name ! >source-name !
+        0 lines-read !
+        0 open-file throw \ actually open the file
+        >source-id ! \ switch to the FD
+
+        begin refill \ quit if we are done
+        while interpret \ run code
+        repeat \ Good! Now do it again
+
+        source-id fd-close throw \ close the file
+        0 >source-id ! \ reset source
+        forget-source
+        "#,
+    );
+
+    compiler.define_forth_word(
+        "INCLUDE-EMBEDDED",
+        r#"( c-addr1 u1 c-addr2 u2 -- ) \ include source code which was embedded into memory, named c-addr2 u2
+        source-id throw \ for now, can't load 2 files at once
+        #source-name ! >source-name !
+        0 lines-read !
+        #embedded ! >embedded !
+        -1 >source-id ! \ switch to the embedded source
+
+        begin refill \ quit if we are done
+        while interpret \ run code
+        repeat \ Good! Now do it again
+
+        0 >source-id ! \ reset source
+        forget-source
+        "#,
+    );
+}
+
+fn build_start(compiler: &mut Compiler, options: &Options) -> Result<()> {
+    // HOST-FINALIZE passes main to START-MAIN once the prelude is loaded
+    compiler.define_variable_word("MAIN-XT", 0);
+    if options.snapshot {
+        // Stop before main, so that the host can snapshot memory.
+        // The snapshot's _start resumes from here.
+        compiler.define_forth_word("START-MAIN", "( xt -- ) main-xt ! stop");
+        compiler.define_forth_word("RESUME-MAIN", "( -- ) main-xt @ execute");
+        compiler.define_entry_point("_resume", "RESUME-MAIN");
+    } else {
+        compiler.define_forth_word("START-MAIN", "( xt -- ) execute");
+    }
+
+    // report anything the prelude throws, instead of just exiting
+    let mut start_instructions = vec![XT("LIT"), XT("(UNCAUGHT)"), XT("'UNCAUGHT"), XT("!")];
+    for file in prelude_files(&options.prelude)? {
+        let name = file.to_string_lossy().into_owned();
+        if options.embed_prelude {
+            // bake the source into the binary, so we don't need to read the file at runtime
+            let source = std::fs::read(&file)?;
+            let len = source.len() as i32;
+            let address = compiler.define_static_data(source);
+            start_instructions.push(Lit(address));
+            start_instructions.push(Lit(len));
+            start_instructions.push(StringLit(name));
+            start_instructions.push(XT("INCLUDE-EMBEDDED"));
+        } else {
+            start_instructions.push(StringLit(name));
+            start_instructions.push(XT("INCLUDED"));
+        }
+    }
+    compiler.define_colon_word("_start", "( -- )", start_instructions);
+    Ok(())
+}
+
+// Directories contribute every file inside them, in order of name
+fn prelude_files(prelude: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for path in prelude {
+        if path.is_dir() {
+            let mut entries = vec![];
+            for entry in std::fs::read_dir(path)? {
+                let entry = entry?;
+                if entry.file_type()?.is_file() {
+                    entries.push(path.join(entry.file_name()));
+                }
+            }
+            entries.sort();
+            files.extend(entries);
+        } else if path.is_file() {
+            files.push(path.clone());
+        } else {
+            bail!("Prelude file {} does not exist", path.display());
+        }
+    }
+    Ok(files)
 }
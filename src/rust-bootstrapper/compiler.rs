@@ -1,18 +1,241 @@
 use super::assembler::Assembler;
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use parity_wasm::elements::{
     BlockType,
     Instruction::{self, *},
     ValueType,
 };
-use std::collections::HashMap;
+use std::{collections::HashMap, panic::Location};
+
+mod dictionary;
+mod forth_source;
+mod inliner;
+mod layout;
+mod stack_effect;
+mod subroutine;
+
+pub(crate) use dictionary::{
+    encode_dictionary, encode_origins, read_origins, walk_dictionary, Origin,
+};
+pub use dictionary::{
+    read_dictionary, DictionaryEntry, WordKind, DICTIONARY_SECTION, ORIGINS_SECTION,
+};
+use inliner::Inliner;
+pub use layout::{MemoryLayout, CODE_INDEX_BITS, FILE_BUFFER_SIZE};
+use stack_effect::{StackEffect, Step};
+use subroutine::{Op, Subroutine};
 
 pub enum ColonValue {
     XT(&'static str),
     Lit(i32),
     StringLit(String),
-    Branch(i32),
-    QBranch(i32),
+    /// A named spot in the word, which branches can jump to
+    Label(&'static str),
+    /// Always jump to a label
+    Branch(&'static str),
+    /// Jump to a label if the top of the stack is false
+    QBranch(&'static str),
+    // Structured control flow, which behaves like the Forth words of the same names
+    If,
+    Else,
+    Then,
+    Begin,
+    Until,
+    Again,
+    While,
+    Repeat,
+}
+
+// Where a branch can go: a named label, or one generated for structured control flow
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Label {
+    Named(&'static str),
+    Anonymous(usize),
+}
+
+// A colon word with all of its control flow turned into labels and jumps
+enum LoweredValue {
+    Value(ColonValue),
+    Mark(Label),
+    Jump(&'static str, Label),
+}
+
+// A word used by a colon definition before it was defined, to patch in once it is
+struct ForwardReference {
+    address: i32,
+    word: &'static str,
+    definition: String,
+}
+
+// What a colon definition compiles into
+struct CompiledColonWord {
+    parameter: Vec<u8>,
+    // words which don't exist yet
+    forward_references: Vec<ForwardReference>,
+    steps: Vec<Step>,
+    // set if the word runs as a function of its own, instead of through DOCOL
+    subroutine: Option<Vec<Op>>,
+}
+
+// How a subroutine-threaded colon word can call another word without going through EXECUTE
+#[derive(Clone, Copy)]
+enum DirectCall {
+    // a native word's function
+    Native(u32),
+    // another subroutine-threaded colon word, which might unwind past its caller
+    Colon(u32),
+    // EXECUTE itself, which might run a threaded word
+    Execute,
+}
+
+// A subroutine-threaded colon word, whose function is filled in once every word is defined
+struct PendingSubroutine {
+    name: String,
+    func: u32,
+    ops: Vec<Op>,
+}
+
+// What structured control flow has left to resolve
+enum Control {
+    // a forward jump, waiting for its target
+    Orig(Label),
+    // a backward jump target
+    Dest(Label),
+}
+
+fn lower_control_flow(values: Vec<ColonValue>) -> Result<Vec<LoweredValue>> {
+    let mut lowered = vec![];
+    let mut controls = vec![];
+    let mut next_label = 0;
+    let mut new_label = || {
+        next_label += 1;
+        Label::Anonymous(next_label)
+    };
+    for value in values {
+        match value {
+            ColonValue::Label(name) => lowered.push(LoweredValue::Mark(Label::Named(name))),
+            ColonValue::Branch(name) => {
+                lowered.push(LoweredValue::Jump("BRANCH", Label::Named(name)))
+            }
+            ColonValue::QBranch(name) => {
+                lowered.push(LoweredValue::Jump("?BRANCH", Label::Named(name)))
+            }
+            ColonValue::If => {
+                let orig = new_label();
+                lowered.push(LoweredValue::Jump("?BRANCH", orig));
+                controls.push(Control::Orig(orig));
+            }
+            ColonValue::Else => {
+                let orig = pop_orig(&mut controls, "ELSE")?;
+                let new_orig = new_label();
+                lowered.push(LoweredValue::Jump("BRANCH", new_orig));
+                lowered.push(LoweredValue::Mark(orig));
+                controls.push(Control::Orig(new_orig));
+            }
+            ColonValue::Then => {
+                let orig = pop_orig(&mut controls, "THEN")?;
+                lowered.push(LoweredValue::Mark(orig));
+            }
+            ColonValue::Begin => {
+                let dest = new_label();
+                lowered.push(LoweredValue::Mark(dest));
+                controls.push(Control::Dest(dest));
+            }
+            ColonValue::Until => {
+                let dest = pop_dest(&mut controls, "UNTIL")?;
+                lowered.push(LoweredValue::Jump("?BRANCH", dest));
+            }
+            ColonValue::Again => {
+                let dest = pop_dest(&mut controls, "AGAIN")?;
+                lowered.push(LoweredValue::Jump("BRANCH", dest));
+            }
+            ColonValue::While => {
+                // the loop's BEGIN stays on top, so REPEAT can find it
+                let dest = pop_dest(&mut controls, "WHILE")?;
+                let orig = new_label();
+                lowered.push(LoweredValue::Jump("?BRANCH", orig));
+                controls.push(Control::Orig(orig));
+                controls.push(Control::Dest(dest));
+            }
+            ColonValue::Repeat => {
+                let dest = pop_dest(&mut controls, "REPEAT")?;
+                lowered.push(LoweredValue::Jump("BRANCH", dest));
+                let orig = pop_orig(&mut controls, "REPEAT")?;
+                lowered.push(LoweredValue::Mark(orig));
+            }
+            value => lowered.push(LoweredValue::Value(value)),
+        }
+    }
+    if !controls.is_empty() {
+        bail!("unterminated control flow");
+    }
+    Ok(lowered)
+}
+
+fn pop_orig(controls: &mut Vec<Control>, word: &str) -> Result<Label> {
+    match controls.pop() {
+        Some(Control::Orig(label)) => Ok(label),
+        _ => bail!("{} without a matching IF or WHILE", word),
+    }
+}
+
+fn pop_dest(controls: &mut Vec<Control>, word: &str) -> Result<Label> {
+    match controls.pop() {
+        Some(Control::Dest(label)) => Ok(label),
+        _ => bail!("{} without a matching BEGIN", word),
+    }
+}
+
+/// Which words the compiled module exposes as exported functions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Exports {
+    /// Only export `_start`, the conventional WASI entry point.
+    #[default]
+    Start,
+    /// Export every word as a function-which-EXECUTEs-that-word,
+    /// plus the raw stack functions. Mostly useful for testing.
+    All,
+}
+/// How the colon words defined by the compiler run.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Threading {
+    /// Every colon word is a list of XTs, which the inner interpreter loop EXECUTEs in turn.
+    #[default]
+    Indirect,
+    /// Colon words are compiled into functions of their own, which call native words directly.
+    /// Words which manipulate the IP or return stack frames (like CATCH) are still threaded.
+    Subroutine,
+}
+
+/// Where the parameter stack keeps its values.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StackModel {
+    /// Every value on the stack lives in memory.
+    #[default]
+    Memory,
+    /// The top of the stack lives in the exported "tos" global, and only deeper values are in memory.
+    /// The cell at "sp" is left over from whatever used to be on top.
+    CachedTop,
+}
+
+#[derive(Clone, Default)]
+pub struct CompilerOptions {
+    pub exports: Exports,
+    /// Check for stack overflow and underflow before touching either stack.
+    /// A failed check traps, with the THROW code in the exported "error" global.
+    pub checked_stacks: bool,
+    /// Where the dictionary, stacks and heap go
+    pub layout: MemoryLayout,
+    /// Whether colon words are threaded, or compiled into functions of their own
+    pub threading: Threading,
+    /// Paste small native words into the subroutine-threaded colon words which call them,
+    /// and keep values off of the parameter stack between them where possible.
+    pub inline_primitives: bool,
+    /// Whether the top of the parameter stack lives in memory, or in a global of its own
+    pub stack_model: StackModel,
+    /// Call the imported "forsm.debug_hook" with the IP, XT, SP and RP
+    /// before the inner interpreter runs each XT.
+    pub debug_hook: bool,
 }
 
 #[derive(Clone, Copy)]
@@ -50,32 +273,65 @@ impl ParamType {
 }
 
 pub struct Compiler {
+    options: CompilerOptions,
     assembler: Assembler,
+    error: u32,
     stack: u32,
     push: u32,
     pop: u32,
     push_d: u32,
     pop_d: u32,
+    r_stack: u32,
     push_r: u32,
     pop_r: u32,
+    f_stack: u32,
+    push_f: u32,
+    pop_f: u32,
     docon: u32,
     dovar: u32,
     docol: u32,
     start: u32,
+    execute_nested: u32,
     ip: u32,
+    stopped: u32,
     cp: i32,
     latest_address: i32,
+    heap_base: i32,
     execution_tokens: HashMap<String, i32>,
+    word_kinds: HashMap<i32, WordKind>,
+    // where in the Rust source each word was defined, by XT
+    origins: HashMap<i32, &'static Location<'static>>,
+    entry_points: Vec<(String, String)>,
+    forward_references: Vec<ForwardReference>,
+    effects: HashMap<String, StackEffect>,
+    colon_words: Vec<(String, Vec<Step>)>,
+    direct_calls: HashMap<i32, DirectCall>,
+    subroutines: Vec<PendingSubroutine>,
+    // the locals and bodies of natives which are small enough to inline
+    inlinable_natives: HashMap<u32, (Vec<ValueType>, Vec<Instruction>)>,
+    cached_top: Option<CachedTop>,
+    errors: Vec<String>,
 }
 
-const DICTIONARY_BASE: i32 = 0x1000;
-const PARAM_STACK_BASE: i32 = 0xed00;
-const RETURN_STACK_BASE: i32 = 0xf100;
-const HEAP_BASE: i32 = 0xf100;
+/* With the top of the stack cached in a global, natives which reach into the stack themselves
+ * write it back to memory first, and call push and pop functions which use memory alone. */
+struct CachedTop {
+    tos: u32,
+    // the usual push and pop functions, and the memory-only versions to call instead
+    memory_calls: HashMap<u32, u32>,
+}
 
-const DICTIONARY_CAPACITY: i32 = PARAM_STACK_BASE - DICTIONARY_BASE;
+// Where a stack lives, and the standard THROW codes for running off either end of it.
+// Stacks grow down, from their base towards their limit.
+struct StackBounds {
+    base: i32,
+    limit: i32,
+    overflow: i32,
+    underflow: i32,
+}
 
 const ALIGNMENT: i32 = 4;
+const PAGE_SIZE: i32 = 0x10000;
 
 fn required_padding(offset: i32) -> i32 {
     -offset & (ALIGNMENT - 1)
@@ -88,37 +344,146 @@ fn header_size(name: &str) -> i32 {
 }
 
 impl Compiler {
+    #[track_caller]
     pub fn define_constant_word(&mut self, name: &str, value: i32) {
+        self.declare_effect(name, &format!("( -- {} )", value));
         let docon = self.docon;
-        self.define_word(name, docon, &value.to_le_bytes());
+        self.define_word(name, WordKind::Constant, docon, &value.to_le_bytes());
     }
 
+    #[track_caller]
     pub fn define_variable_word(&mut self, name: &str, initial_value: i32) {
+        self.declare_effect(name, "( -- a-addr )");
         let dovar = self.dovar;
-        self.define_word(name, dovar, &initial_value.to_le_bytes());
+        self.define_word(
+            name,
+            WordKind::Variable,
+            dovar,
+            &initial_value.to_le_bytes(),
+        );
+    }
+
+    /// Define a colon word, which should have the given stack effect, e.g. `( c-addr u -- n )`.
+    #[track_caller]
+    pub fn define_colon_word(&mut self, name: &str, effect: &str, values: Vec<ColonValue>) {
+        let threading = self.options.threading;
+        self.define_colon_word_with(name, effect, values, threading);
+    }
+
+    #[track_caller]
+    fn define_colon_word_with(
+        &mut self,
+        name: &str,
+        effect: &str,
+        values: Vec<ColonValue>,
+        threading: Threading,
+    ) {
+        // Mistakes are reported when the module is compiled,
+        // and words which aren't defined yet are resolved then too.
+        self.declare_effect(name, effect);
+        match self.compile_colon_word(name, values, threading) {
+            Ok(CompiledColonWord {
+                parameter,
+                steps,
+                subroutine: Some(ops),
+                ..
+            }) => {
+                // The function's body calls words which might not exist yet, so it's filled in later
+                let func = self.create_native_func(&format!("forth:{}", name), vec![], vec![]);
+                let code = self.add_callable(func);
+                let xt = self.define_word(name, WordKind::Colon, code, &parameter);
+                self.direct_calls.insert(xt, DirectCall::Colon(func));
+                self.subroutines.push(PendingSubroutine {
+                    name: name.to_owned(),
+                    func,
+                    ops,
+                });
+                self.colon_words.push((name.to_owned(), steps));
+            }
+            Ok(CompiledColonWord {
+                parameter,
+                forward_references,
+                steps,
+                subroutine: None,
+            }) => {
+                let docol = self.docol;
+                self.define_word(name, WordKind::Colon, docol, &parameter);
+                self.forward_references.extend(forward_references);
+                self.colon_words.push((name.to_owned(), steps));
+            }
+            Err(error) => self.errors.push(format!("In {}: {}", name, error)),
+        }
     }
 
-    pub fn define_colon_word(&mut self, name: &str, values: Vec<ColonValue>) {
-        let docol = self.docol;
+    /// Define a colon word from Forth-like source, e.g. `( x -- ) dup 0= if 2drop 0 exit then`.
+    /// The source starts with the word's stack effect.
+    #[track_caller]
+    pub fn define_forth_word(&mut self, name: &str, source: &'static str) {
+        let parsed = forth_source::split_effect(source)
+            .and_then(|(effect, body)| Ok((effect, forth_source::parse(body)?)));
+        match parsed {
+            Ok((effect, values)) => self.define_colon_word(name, effect, values),
+            Err(error) => self.errors.push(format!("In {}: {}", name, error)),
+        }
+    }
+
+    fn compile_colon_word(
+        &self,
+        name: &str,
+        values: Vec<ColonValue>,
+        threading: Threading,
+    ) -> Result<CompiledColonWord> {
         let lit_xt = self.get_execution_token("LIT");
         let branch_xt = self.get_execution_token("BRANCH");
-        let q_branch_xt = self.get_execution_token("?BRANCH");
+        let values = lower_control_flow(values)?;
+
+        // find where every label is, to turn jumps absolute
+        let mut labels = HashMap::new();
+        let mut cp = self.cp + header_size(name);
+        for value in values.iter() {
+            match value {
+                LoweredValue::Value(ColonValue::StringLit(value)) => {
+                    cp += 8 + aligned(value.len() as i32) + 16;
+                }
+                LoweredValue::Value(ColonValue::Lit(_)) => cp += 8,
+                LoweredValue::Value(_) => cp += 4,
+                LoweredValue::Jump(_, _) => cp += 8,
+                LoweredValue::Mark(label) => {
+                    if labels.insert(*label, cp).is_some() {
+                        if let Label::Named(label) = label {
+                            bail!("duplicate label {}", label);
+                        }
+                    }
+                }
+            }
+        }
+
         let mut bytes = vec![];
-        // track the end of the dictionary as we go, to turn relative jumps absolute
+        let mut forward_references = vec![];
         let mut cp = self.cp + header_size(name);
-        for value in values {
+        for value in values.iter() {
             match value {
-                ColonValue::XT(name) => {
+                LoweredValue::Value(ColonValue::XT(word)) => {
+                    let xt = match self.find_execution_token(word) {
+                        Some(xt) => xt,
+                        None => {
+                            forward_references.push(ForwardReference {
+                                address: cp,
+                                word,
+                                definition: name.to_owned(),
+                            });
+                            0
+                        }
+                    };
                     cp += 4;
-                    let xt = self.get_execution_token(name);
                     bytes.extend_from_slice(&xt.to_le_bytes())
                 }
-                ColonValue::Lit(value) => {
+                LoweredValue::Value(ColonValue::Lit(value)) => {
                     cp += 8;
                     bytes.extend_from_slice(&lit_xt.to_le_bytes());
                     bytes.extend_from_slice(&value.to_le_bytes());
                 }
-                ColonValue::StringLit(value) => {
+                LoweredValue::Value(ColonValue::StringLit(value)) => {
                     let data_start = cp + 8;
                     let data_len = value.len() as i32;
                     let padding = required_padding(data_len);
@@ -133,26 +498,47 @@ impl Compiler {
                     bytes.extend_from_slice(&lit_xt.to_le_bytes());
                     bytes.extend_from_slice(&data_len.to_le_bytes());
                 }
-                ColonValue::Branch(offset) => {
-                    cp += 8;
-                    let target = cp + offset;
-                    bytes.extend_from_slice(&branch_xt.to_le_bytes());
-                    bytes.extend_from_slice(&target.to_le_bytes());
-                }
-                ColonValue::QBranch(offset) => {
+                LoweredValue::Value(_) => unreachable!("control flow was already lowered"),
+                LoweredValue::Jump(word, label) => {
                     cp += 8;
-                    let target = cp + offset;
-                    bytes.extend_from_slice(&q_branch_xt.to_le_bytes());
+                    let target = match (labels.get(label), *label) {
+                        (Some(target), _) => *target,
+                        (None, Label::Named(label)) => bail!("undefined label {}", label),
+                        (None, Label::Anonymous(_)) => {
+                            unreachable!("generated labels are always marked")
+                        }
+                    };
+                    bytes.extend_from_slice(&self.get_execution_token(word).to_le_bytes());
                     bytes.extend_from_slice(&target.to_le_bytes());
                 }
+                LoweredValue::Mark(_) => {}
             }
         }
         let exit_xt = self.get_execution_token("EXIT");
         bytes.extend_from_slice(&exit_xt.to_le_bytes());
-
-        self.define_word(name, docol, &bytes);
+        let steps = stack_effect::steps(&values);
+
+        // Words which can't be compiled into a function of their own are threaded instead
+        if threading == Threading::Subroutine {
+            let data_start = self.cp + header_size(name);
+            if let Ok(Subroutine { ops, data }) = subroutine::compile(&values, data_start) {
+                return Ok(CompiledColonWord {
+                    parameter: data,
+                    forward_references: vec![],
+                    steps,
+                    subroutine: Some(ops),
+                });
+            }
+        }
+        Ok(CompiledColonWord {
+            parameter: bytes,
+            forward_references,
+            steps,
+            subroutine: None,
+        })
     }
 
+    #[track_caller]
     pub fn define_imported_word(
         &mut self,
         name: &str,
@@ -177,6 +563,8 @@ impl Compiler {
             to_value_types(&params),
             to_value_types(&results),
         );
+        self.assembler
+            .name_func(func, &format!("import:{}.{}", module, field), &[]);
         let params_bytes = params.iter().map(|p| p.bytes()).sum();
         let results_bytes = results.iter().map(|p| p.bytes()).sum();
 
@@ -231,11 +619,57 @@ impl Compiler {
             _type.store(result_offset, &mut instructions);
             result_offset += _type.bytes();
         }
-        self.define_native_word(name, locals, instructions);
+        // Doubles take two cells, so they need a name which says so
+        let names = |types: &[ParamType]| -> Vec<&str> {
+            types
+                .iter()
+                .map(|t| match t {
+                    ParamType::I32 => "n",
+                    ParamType::I64 => "d",
+                })
+                .collect()
+        };
+        let effect = format!(
+            "( {} -- {} )",
+            names(&params).join(" "),
+            names(&results).join(" ")
+        );
+        self.define_native_word(name, &effect, locals, instructions);
+    }
+
+    /// Store some read-only data (such as embedded source code) outside of the dictionary.
+    /// Static data lives where the layout's heap would start, and the heap starts after it.
+    /// Returns the address of the data.
+    pub fn define_static_data(&mut self, data: Vec<u8>) -> i32 {
+        let address = self.heap_base;
+        self.heap_base = aligned(address + data.len() as i32);
+        self.assembler.add_data(address, data);
+        address
+    }
+
+    /// Export a function which runs the given word, alongside the usual exports.
+    pub fn define_entry_point(&mut self, export: &str, word: &str) {
+        self.entry_points.push((export.to_owned(), word.to_owned()));
+    }
+
+    /// The names of every word defined so far, in the order they were defined.
+    pub fn words(&self) -> Vec<String> {
+        let mut words: Vec<(&String, &i32)> = self.execution_tokens.iter().collect();
+        words.sort_by_key(|(_, xt)| **xt);
+        words.into_iter().map(|(name, _)| name.clone()).collect()
+    }
+
+    /// Where everything lives in memory, for words which need addresses of their own.
+    pub fn layout(&self) -> &MemoryLayout {
+        &self.options.layout
     }
 
     pub fn compile(self) -> Result<Vec<u8>> {
-        self.finalize().assembler.compile()
+        let compiler = self.finalize();
+        if !compiler.errors.is_empty() {
+            return Err(anyhow!("{}", compiler.errors.join("\n")));
+        }
+        compiler.assembler.compile()
     }
 
     fn initialize(mut self) -> Self {
@@ -243,19 +677,45 @@ impl Compiler {
         self.define_memory();
         self.define_execution();
         self.define_math();
+        self.define_floats();
+        self.define_exceptions();
+
+        // Build the code field for a callable which takes an immediate address, like (DODOES)
+        self.define_colon_word(
+            "CODE-FIELD",
+            "( u a-addr -- x )",
+            vec![
+                ColonValue::Lit(CODE_INDEX_BITS - 2),
+                ColonValue::XT("LSHIFT"),
+                ColonValue::XT("OR"),
+            ],
+        );
 
         // Define dictionary-related words here as well
         // We don't have some real values yet, but other code needs to reference them
-        self.define_constant_word("DICT-BASE", DICTIONARY_BASE);
-        self.define_constant_word("DICT-CAPACITY", DICTIONARY_CAPACITY);
-        self.define_variable_word("CP", DICTIONARY_BASE);
-        self.define_variable_word("LATEST", DICTIONARY_BASE);
+        let layout = self.options.layout;
+        self.define_constant_word("DICT-BASE", layout.dictionary_base());
+        self.define_constant_word("DICT-CAPACITY", layout.dictionary_capacity);
+        // the end of the dictionary's current space; ALLOT grows or throws rather than go past it
+        self.define_variable_word(
+            "DICT-LIMIT",
+            layout.dictionary_base() + layout.dictionary_capacity,
+        );
+        // code fields can't point any higher, so the dictionary can't grow past this
+        self.define_constant_word("DICT-CEILING", layout.dictionary_ceiling());
+        self.define_variable_word("CP", layout.dictionary_base());
+        self.define_variable_word("LATEST", layout.dictionary_base());
         self
     }
 
     fn define_stacks(&mut self) {
-        let define_stack = |assembler: &mut Assembler, stack| {
-            let push_instructions = vec![
+        let error = self.add_global(0);
+        self.assembler.add_exported_global("error", error);
+        self.error = error;
+
+        let define_stack = |compiler: &mut Self, stack, bounds: &StackBounds, suffix: &str| {
+            let mut push_instructions = compiler.check_overflow(stack, bounds, 4);
+            push_instructions.extend(vec![
                 // decrement stack pointer
                 GetGlobal(stack),
                 I32Const(4),
@@ -266,11 +726,18 @@ impl Compiler {
                 GetLocal(0),
                 I32Store(2, 0),
                 End,
-            ];
-            let push =
-                assembler.add_native_func(vec![ValueType::I32], vec![], vec![], push_instructions);
+            ]);
+            let push = compiler.assembler.add_native_func(
+                vec![ValueType::I32],
+                vec![],
+                vec![],
+                push_instructions,
+            );
+            let name = format!("push{}", suffix);
+            compiler.assembler.name_func(push, &name, &["value"]);
 
-            let pop_instructions = vec![
+            let mut pop_instructions = compiler.check_underflow(stack, bounds, 4);
+            pop_instructions.extend(vec![
                 // read data
                 GetGlobal(stack),
                 I32Load(2, 0),
@@ -280,85 +747,148 @@ impl Compiler {
                 I32Add,
                 SetGlobal(stack),
                 End,
-            ];
-            let pop =
-                assembler.add_native_func(vec![], vec![ValueType::I32], vec![], pop_instructions);
+            ]);
+            let pop = compiler.assembler.add_native_func(
+                vec![],
+                vec![ValueType::I32],
+                vec![],
+                pop_instructions,
+            );
+            let name = format!("pop{}", suffix);
+            compiler.assembler.name_func(pop, &name, &[]);
 
             (push, pop)
         };
         // define the normal stack
-        let stack = self.add_global(PARAM_STACK_BASE);
-        let (push, pop) = define_stack(&mut self.assembler, stack);
+        let param_stack = self.param_stack();
+        let stack = self.add_global(param_stack.base);
+        self.assembler.add_exported_global("sp", stack);
+        let (push, pop) = define_stack(self, stack, &param_stack, "");
         self.stack = stack;
         self.push = push;
         self.pop = pop;
 
+        let mut push_d_instructions = self.check_overflow(stack, &param_stack, 8);
+        push_d_instructions.extend(vec![
+            // decrement stack pointer
+            GetGlobal(stack),
+            I32Const(8),
+            I32Sub,
+            SetGlobal(stack),
+            // write data
+            GetGlobal(stack),
+            GetLocal(0),
+            I64Const(32),
+            I64Rotl,
+            I64Store(3, 0),
+            End,
+        ]);
         let push_d = self.assembler.add_native_func(
             vec![ValueType::I64],
             vec![],
             vec![],
-            vec![
-                // decrement stack pointer
-                GetGlobal(stack),
-                I32Const(8),
-                I32Sub,
-                SetGlobal(stack),
-                // write data
-                GetGlobal(stack),
-                GetLocal(0),
-                I64Const(32),
-                I64Rotl,
-                I64Store(3, 0),
-                End,
-            ],
+            push_d_instructions,
         );
+        let mut pop_d_instructions = self.check_underflow(stack, &param_stack, 8);
+        pop_d_instructions.extend(vec![
+            // read data
+            GetGlobal(stack),
+            I64Load(3, 0),
+            I64Const(32),
+            I64Rotl,
+            // increment stack pointer
+            GetGlobal(stack),
+            I32Const(8),
+            I32Add,
+            SetGlobal(stack),
+            End,
+        ]);
         let pop_d = self.assembler.add_native_func(
             vec![],
             vec![ValueType::I64],
             vec![],
-            vec![
-                // read data
-                GetGlobal(stack),
-                I64Load(3, 0),
-                I64Const(32),
-                I64Rotl,
-                // increment stack pointer
-                GetGlobal(stack),
-                I32Const(8),
-                I32Add,
-                SetGlobal(stack),
-                End,
-            ],
+            pop_d_instructions,
         );
+        self.assembler.name_func(push_d, "push_d", &["value"]);
+        self.assembler.name_func(pop_d, "pop_d", &[]);
         self.push_d = push_d;
         self.pop_d = pop_d;
+        if self.options.stack_model == StackModel::CachedTop {
+            self.define_cached_top(&param_stack);
+        }
+        let (push, pop) = (self.push, self.pop);
+        let tos = self.cached_tos();
 
         // define the return stack
-        let r_stack = self.add_global(RETURN_STACK_BASE);
-        let (push_r, pop_r) = define_stack(&mut self.assembler, r_stack);
+        let return_stack = self.return_stack();
+        let r_stack = self.add_global(return_stack.base);
+        self.assembler.add_exported_global("rp", r_stack);
+        let (push_r, pop_r) = define_stack(self, r_stack, &return_stack, "_r");
+        self.r_stack = r_stack;
         self.push_r = push_r;
         self.pop_r = pop_r;
 
-        #[cfg(test)]
-        {
-            self.assembler.add_exported_func("push", push);
-            self.assembler.add_exported_func("pop", pop);
-            self.assembler.add_exported_func("push_d", push_d);
-            self.assembler.add_exported_func("pop_d", pop_d);
-        }
-        self.define_native_word(
-            "DUP",
+        // define the float stack, which holds f64s
+        let float_stack = self.float_stack();
+        let f_stack = self.add_global(float_stack.base);
+        self.assembler.add_exported_global("fp", f_stack);
+        let mut push_f_instructions = self.check_overflow(f_stack, &float_stack, 8);
+        push_f_instructions.extend(vec![
+            // decrement stack pointer
+            GetGlobal(f_stack),
+            I32Const(8),
+            I32Sub,
+            SetGlobal(f_stack),
+            // write data
+            GetGlobal(f_stack),
+            GetLocal(0),
+            F64Store(3, 0),
+            End,
+        ]);
+        let push_f = self.assembler.add_native_func(
+            vec![ValueType::F64],
             vec![],
+            vec![],
+            push_f_instructions,
+        );
+        let mut pop_f_instructions = self.check_underflow(f_stack, &float_stack, 8);
+        pop_f_instructions.extend(vec![
+            // read data
+            GetGlobal(f_stack),
+            F64Load(3, 0),
+            // increment stack pointer
+            GetGlobal(f_stack),
+            I32Const(8),
+            I32Add,
+            SetGlobal(f_stack),
+            End,
+        ]);
+        let pop_f = self.assembler.add_native_func(
+            vec![],
+            vec![ValueType::F64],
+            vec![],
+            pop_f_instructions,
+        );
+        self.assembler.name_func(push_f, "push_f", &["value"]);
+        self.assembler.name_func(pop_f, "pop_f", &[]);
+        self.f_stack = f_stack;
+        self.push_f = push_f;
+        self.pop_f = pop_f;
+
+        self.define_stack_word(
+            "DUP",
+            "( x -- x x )",
             vec![
                 // just push the top of the stack onto itself
                 GetGlobal(stack),
                 I32Load(2, 0),
                 Call(push),
             ],
+            vec![GetGlobal(tos), Call(push)],
         );
-        self.define_native_word(
+        self.define_stack_word(
             "?DUP",
-            vec![],
+            "( x -- 0 | x x )",
             vec![
                 GetGlobal(stack),
                 I32Load(2, 0),
@@ -368,10 +898,18 @@ impl Compiler {
                 Call(push),
                 End,
             ],
+            vec![
+                GetGlobal(tos),
+                TeeLocal(0),
+                If(BlockType::NoResult),
+                GetLocal(0),
+                Call(push),
+                End,
+            ],
         );
-        self.define_native_word(
+        self.define_stack_word(
             "2DUP",
-            vec![],
+            "( x1 x2 -- x1 x2 x1 x2 )",
             vec![
                 GetGlobal(stack),
                 I32Const(8),
@@ -383,10 +921,19 @@ impl Compiler {
                 I64Load(3, 8),
                 I64Store(3, 0),
             ],
+            vec![
+                // OVER OVER
+                GetGlobal(stack),
+                I32Load(2, 4),
+                Call(push),
+                GetGlobal(stack),
+                I32Load(2, 4),
+                Call(push),
+            ],
         );
-        self.define_native_word(
+        self.define_stack_word(
             "DROP",
-            vec![],
+            "( x -- )",
             vec![
                 // just increment the stack pointer
                 GetGlobal(stack),
@@ -394,10 +941,20 @@ impl Compiler {
                 I32Add,
                 SetGlobal(stack),
             ],
+            vec![
+                GetGlobal(stack),
+                I32Const(4),
+                I32Add,
+                SetGlobal(stack),
+                // the next value down is the new top
+                GetGlobal(stack),
+                I32Load(2, 0),
+                SetGlobal(tos),
+            ],
         );
-        self.define_native_word(
+        self.define_stack_word(
             "2DROP",
-            vec![],
+            "( x1 x2 -- )",
             vec![
                 // just increment the stack pointer
                 GetGlobal(stack),
@@ -405,10 +962,20 @@ impl Compiler {
                 I32Add,
                 SetGlobal(stack),
             ],
+            vec![
+                GetGlobal(stack),
+                I32Const(8),
+                I32Add,
+                SetGlobal(stack),
+                // x1 was the next value down
+                GetGlobal(stack),
+                I32Load(2, 0),
+                SetGlobal(tos),
+            ],
         );
-        self.define_native_word(
+        self.define_stack_word(
             "SWAP",
-            vec![],
+            "( x1 x2 -- x2 x1 )",
             vec![
                 // don't bother touching the stack pointer
                 GetGlobal(stack),
@@ -421,9 +988,18 @@ impl Compiler {
                 I32Store(2, 0),
                 I32Store(2, 4),
             ],
+            vec![
+                GetGlobal(stack),
+                GetGlobal(tos),
+                GetGlobal(stack),
+                I32Load(2, 4),
+                SetGlobal(tos),
+                I32Store(2, 4),
+            ],
         );
         self.define_native_word(
             "2SWAP",
+            "( x1 x2 x3 x4 -- x3 x4 x1 x2 )",
             vec![],
             vec![
                 // don't bother touching the stack pointer
@@ -438,9 +1014,9 @@ impl Compiler {
                 I64Store(3, 8),
             ],
         );
-        self.define_native_word(
+        self.define_stack_word(
             "OVER",
-            vec![],
+            "( x1 x2 -- x1 x2 x1 )",
             vec![
                 GetGlobal(stack),
                 I32Const(4),
@@ -452,9 +1028,11 @@ impl Compiler {
                 I32Load(2, 8),
                 I32Store(2, 0),
             ],
+            vec![GetGlobal(stack), I32Load(2, 4), Call(push)],
         );
         self.define_native_word(
             "2OVER",
+            "( x1 x2 x3 x4 -- x1 x2 x3 x4 x1 x2 )",
             vec![],
             vec![
                 GetGlobal(stack),
@@ -468,9 +1046,9 @@ impl Compiler {
                 I64Store(2, 0),
             ],
         );
-        self.define_native_word(
+        self.define_stack_word(
             "NIP",
-            vec![],
+            "( x1 x2 -- x2 )",
             vec![
                 GetGlobal(stack),
                 TeeLocal(0),
@@ -482,10 +1060,17 @@ impl Compiler {
                 I32Add,
                 SetGlobal(stack), // head += 4
             ],
+            vec![
+                // the top stays put, so just forget the value under it
+                GetGlobal(stack),
+                I32Const(4),
+                I32Add,
+                SetGlobal(stack),
+            ],
         );
-        self.define_native_word(
+        self.define_stack_word(
             "TUCK",
-            vec![],
+            "( x1 x2 -- x2 x1 x2 )",
             vec![
                 GetGlobal(stack),
                 I32Const(4),
@@ -508,10 +1093,24 @@ impl Compiler {
                 GetLocal(0),
                 SetGlobal(stack),
             ],
+            vec![
+                GetGlobal(stack),
+                I32Const(4),
+                I32Sub,
+                TeeLocal(0),
+                SetGlobal(stack),
+                GetLocal(0),
+                GetLocal(0),
+                I32Load(2, 8),
+                I32Store(2, 4),
+                GetLocal(0),
+                GetGlobal(tos),
+                I32Store(2, 8),
+            ],
         );
-        self.define_native_word(
+        self.define_stack_word(
             "ROT",
-            vec![],
+            "( x1 x2 x3 -- x2 x3 x1 )",
             vec![
                 // spin your elements round and round
                 GetGlobal(stack),
@@ -528,10 +1127,23 @@ impl Compiler {
                 I32Store(2, 8),
                 I32Store(2, 4),
             ],
+            vec![
+                GetGlobal(stack),
+                TeeLocal(0),
+                I32Load(2, 8),
+                GetLocal(0),
+                GetLocal(0),
+                I32Load(2, 4),
+                I32Store(2, 8),
+                GetLocal(0),
+                GetGlobal(tos),
+                I32Store(2, 4),
+                SetGlobal(tos),
+            ],
         );
-        self.define_native_word(
+        self.define_stack_word(
             "-ROT",
-            vec![],
+            "( x1 x2 x3 -- x3 x1 x2 )",
             vec![
                 // like two rots, or rot backwards
                 GetGlobal(stack),
@@ -548,10 +1160,23 @@ impl Compiler {
                 I32Store(2, 0),
                 I32Store(2, 8),
             ],
+            vec![
+                GetGlobal(stack),
+                TeeLocal(0),
+                I32Load(2, 4),
+                GetLocal(0),
+                GetLocal(0),
+                I32Load(2, 8),
+                I32Store(2, 4),
+                GetLocal(0),
+                GetGlobal(tos),
+                I32Store(2, 8),
+                SetGlobal(tos),
+            ],
         );
-        self.define_native_word(
+        self.define_stack_word(
             "PICK",
-            vec![],
+            "( i*x u -- i*x x )",
             vec![
                 GetGlobal(stack),
                 TeeLocal(0),
@@ -566,31 +1191,45 @@ impl Compiler {
                 I32Load(2, 0),  // read that offset from the head
                 I32Store(2, 0), // and store it back in the head
             ],
-        );
-        self.define_native_word(
-            "DEPTH",
-            vec![],
             vec![
-                I32Const(PARAM_STACK_BASE),
                 GetGlobal(stack),
-                I32Sub,
+                GetGlobal(tos),
+                I32Const(1),
+                I32Add,
+                I32Const(2),
+                I32Shl,
+                I32Add,
+                I32Load(2, 0),
+                SetGlobal(tos),
+            ],
+        );
+        self.define_native_word(
+            "DEPTH",
+            "( -- +n )",
+            vec![],
+            vec![
+                I32Const(param_stack.base),
+                GetGlobal(stack),
+                I32Sub,
                 I32Const(2),
                 I32ShrU,
                 Call(push),
             ],
         );
-        self.define_native_word(">R", vec![], vec![Call(pop), Call(push_r)]);
-        self.define_native_word("R>", vec![], vec![Call(pop_r), Call(push)]);
+        self.define_native_word(">R", "( x -- )", vec![], vec![Call(pop), Call(push_r)]);
+        self.define_native_word("R>", "( -- x )", vec![], vec![Call(pop_r), Call(push)]);
         self.define_native_word(
             "R@",
+            "( -- x )",
             vec![],
             vec![GetGlobal(r_stack), I32Load(2, 0), Call(push)],
         );
         self.define_native_word(
             "R-DEPTH",
+            "( -- +n )",
             vec![],
             vec![
-                I32Const(RETURN_STACK_BASE),
+                I32Const(return_stack.base),
                 GetGlobal(r_stack),
                 I32Sub,
                 I32Const(2),
@@ -598,6 +1237,32 @@ impl Compiler {
                 Call(push),
             ],
         );
+
+        // Raw stack pointers, so that CATCH can save both stacks and THROW can restore them
+        self.define_native_word(
+            "SP@",
+            "( -- a-addr )",
+            vec![],
+            vec![GetGlobal(stack), Call(push)],
+        );
+        self.define_native_word(
+            "SP!",
+            "( i*x a-addr -- j*x )",
+            vec![],
+            vec![Call(pop), SetGlobal(stack)],
+        );
+        self.define_native_word(
+            "RP@",
+            "( -- a-addr )",
+            vec![],
+            vec![GetGlobal(r_stack), Call(push)],
+        );
+        self.define_native_word(
+            "RP!",
+            "( a-addr -- )",
+            vec![],
+            vec![Call(pop), SetGlobal(r_stack)],
+        );
     }
 
     fn define_memory(&mut self) {
@@ -606,6 +1271,7 @@ impl Compiler {
 
         // constants
         let docon = self.create_native_callable(
+            "DOCON",
             vec![],
             vec![
                 // The value of our parameter is the value of the constant, just fetch and push it
@@ -619,6 +1285,7 @@ impl Compiler {
 
         // variables
         let dovar = self.create_native_callable(
+            "DOVAR",
             vec![],
             vec![
                 // the address of our parameter IS the address of the variable, just push it
@@ -629,11 +1296,27 @@ impl Compiler {
         self.dovar = dovar;
         self.define_constant_word("(DOVAR)", dovar as i32);
         self.define_constant_word("CELL", 4);
-        self.define_native_word("CELLS", vec![], vec![Call(pop), I32Const(2), I32Shl, Call(push)]);
-        self.define_native_word("!", vec![], vec![Call(pop), Call(pop), I32Store(2, 0)]);
-        self.define_native_word("@", vec![], vec![Call(pop), I32Load(2, 0), Call(push)]);
+        self.define_native_word(
+            "CELLS",
+            "( n1 -- n2 )",
+            vec![],
+            vec![Call(pop), I32Const(2), I32Shl, Call(push)],
+        );
+        self.define_stack_word(
+            "!",
+            "( x a-addr -- )",
+            vec![Call(pop), Call(pop), I32Store(2, 0)],
+            self.cached_store(I32Store(2, 0)),
+        );
+        self.define_stack_word(
+            "@",
+            "( a-addr -- x )",
+            vec![Call(pop), I32Load(2, 0), Call(push)],
+            self.cached_fetch(I32Load(2, 0)),
+        );
         self.define_native_word(
             "+!",
+            "( n a-addr -- )",
             vec![],
             vec![
                 Call(pop),
@@ -645,14 +1328,33 @@ impl Compiler {
                 I32Store(2, 0),
             ],
         );
-        self.define_native_word("C!", vec![], vec![Call(pop), Call(pop), I32Store8(0, 0)]);
-        self.define_native_word("C@", vec![], vec![Call(pop), I32Load8U(0, 0), Call(push)]);
+        self.define_stack_word(
+            "C!",
+            "( c c-addr -- )",
+            vec![Call(pop), Call(pop), I32Store8(0, 0)],
+            self.cached_store(I32Store8(0, 0)),
+        );
+        self.define_stack_word(
+            "C@",
+            "( c-addr -- c )",
+            vec![Call(pop), I32Load8U(0, 0), Call(push)],
+            self.cached_fetch(I32Load8U(0, 0)),
+        );
+
+        // the return stack grows down from here
+        self.define_constant_word("RETURN-STACK-BASE", self.return_stack().base);
 
         // heap words
-        self.define_constant_word("HEAP-BASE", HEAP_BASE);
-        self.define_native_word("MEMORY.SIZE", vec![], vec![CurrentMemory(0), Call(push)]);
+        self.define_constant_word("HEAP-BASE", self.heap_base);
+        self.define_native_word(
+            "MEMORY.SIZE",
+            "( -- u )",
+            vec![],
+            vec![CurrentMemory(0), Call(push)],
+        );
         self.define_native_word(
             "MEMORY.GROW",
+            "( u1 -- u2 )",
             vec![],
             vec![Call(pop), GrowMemory(0), Call(push)],
         );
@@ -665,8 +1367,35 @@ impl Compiler {
         let pop_r = self.pop_r;
 
         let ip = self.add_global(0);
+        self.assembler.add_exported_global("ip", ip);
         self.ip = ip;
         let stopped = self.add_global(0);
+        self.stopped = stopped;
+
+        // Debug builds tell the host about each XT before running it, so that it can stop there
+        let stack = self.stack;
+        let r_stack = self.r_stack;
+        let debug_hook = match self.options.debug_hook {
+            true => {
+                let hook = self.assembler.add_imported_func(
+                    "forsm".to_owned(),
+                    "debug_hook".to_owned(),
+                    vec![ValueType::I32; 4],
+                    vec![],
+                );
+                self.assembler
+                    .name_func(hook, "import:forsm.debug_hook", &[]);
+                vec![
+                    GetGlobal(ip),
+                    GetGlobal(ip),
+                    I32Load(2, 0),
+                    GetGlobal(stack),
+                    GetGlobal(r_stack),
+                    Call(hook),
+                ]
+            }
+            false => vec![],
+        };
 
         // "execute" takes an XT as a parameter and runs it
         let callable_sig = self
@@ -679,61 +1408,124 @@ impl Compiler {
             vec![
                 // The argument is an execution token (XT).
                 // In this system, an execution token is a 32-bit address.
-                // The low 8 bits of the value are a table index,
-                // and the rest are a 24-bit "immediate" value.
+                // The low CODE_INDEX_BITS of the value are a table index,
+                // and the rest are an "immediate" cell-aligned address, divided by 4.
                 // Any parameter data is stored immediately after it.
                 // Call the func with:
                 // arg0: the address of the parameter data.
-                // arg1: the immediate
+                // arg1: the immediate address
                 GetLocal(0),
                 I32Const(4),
                 I32Add,
                 GetLocal(0),
                 I32Load(2, 0),
                 TeeLocal(1),
-                I32Const(8),
-                I32ShrU, // top 24 bits are arg1
+                I32Const(CODE_INDEX_BITS - 2),
+                I32ShrU,
+                I32Const(-4),
+                I32And, // the top bits are arg1
                 GetLocal(1),
-                I32Const(255),
-                I32And, // bottom 8 bits are the func index
+                I32Const((1 << CODE_INDEX_BITS) - 1),
+                I32And, // the bottom bits are the func index
                 CallIndirect(callable_sig, 0),
                 End,
             ],
         );
-        self.define_native_word("EXECUTE", vec![], vec![Call(pop), Call(execute)]);
+        self.assembler
+            .name_func(execute, "execute", &["xt", "code-field"]);
+        self.define_native_word(
+            "EXECUTE",
+            "( i*x xt -- j*x )",
+            vec![],
+            vec![Call(pop), Call(execute)],
+        );
+        // Subroutine-threaded words EXECUTE through "execute_nested" instead
+        let execute_xt = self.get_execution_token("EXECUTE");
+        self.direct_calls.insert(execute_xt, DirectCall::Execute);
+
+        // "execute_nested" runs an XT to completion, even if it's a threaded colon word.
+        // It runs the inner interpreter until the return stack is back where it started,
+        // which is also how it notices a THROW to a CATCH outside of it.
+        let mut execute_nested_instructions = vec![
+            GetGlobal(r_stack),
+            SetLocal(1),
+            GetLocal(0),
+            Call(execute),
+            Block(BlockType::NoResult),
+            Loop(BlockType::NoResult),
+            // stop once the word has returned (or the program has stopped)
+            GetGlobal(r_stack),
+            GetLocal(1),
+            I32GeU,
+            GetGlobal(stopped),
+            I32Or,
+            BrIf(1),
+            // otherwise, run the next XT, like the main loop does
+            GetGlobal(ip),
+            I32Const(4),
+            I32Add,
+            SetGlobal(ip),
+        ];
+        execute_nested_instructions.extend(debug_hook.iter().cloned());
+        execute_nested_instructions.extend(vec![
+            GetGlobal(ip),
+            I32Load(2, 0),
+            Call(execute),
+            Br(0),
+            End,
+            End,
+            End,
+        ]);
+        let execute_nested = self.assembler.add_native_func(
+            vec![ValueType::I32],
+            vec![],
+            vec![ValueType::I32],
+            execute_nested_instructions,
+        );
+        self.assembler
+            .name_func(execute_nested, "execute_nested", &["xt", "return-depth"]);
+        self.execute_nested = execute_nested;
 
         // Start is the interpreter's main loop, it calls EXECUTE until the program says to stop.
         // Assuming that the caller has set IP to something reasonable first.
-        let start = self.assembler.add_native_func(
-            vec![],
-            vec![],
+        let mut start_instructions = vec![
+            // mark that we should NOT stop yet
+            I32Const(0),
+            SetGlobal(stopped),
+            // loop until execution is not "in progress"
+            Loop(BlockType::NoResult),
+        ];
+        start_instructions.extend(debug_hook);
+        start_instructions.extend(vec![
+            GetGlobal(ip), // IP is a pointer to an XT
+            I32Load(2, 0), // Deref it to get our next XT
+            Call(execute), // Run it
+            GetGlobal(ip),
+            I32Const(4),
+            I32Add,
+            SetGlobal(ip), // increment the IP
+            // loop if we still have not been stopped
+            GetGlobal(stopped),
+            I32Eqz,
+            BrIf(0),
+            End,
+            End,
+        ]);
+        let start = self
+            .assembler
+            .add_native_func(vec![], vec![], vec![], start_instructions);
+        self.assembler.name_func(start, "start", &[]);
+        self.start = start;
+        self.define_native_word(
+            "STOP",
+            "( -- )",
             vec![],
-            vec![
-                // mark that we should NOT stop yet
-                I32Const(0),
-                SetGlobal(stopped),
-                // loop until execution is not "in progress"
-                Loop(BlockType::NoResult),
-                GetGlobal(ip), // IP is a pointer to an XT
-                I32Load(2, 0), // Deref it to get our next XT
-                Call(execute), // Run it
-                GetGlobal(ip),
-                I32Const(4),
-                I32Add,
-                SetGlobal(ip), // increment the IP
-                // loop if we still have not been stopped
-                GetGlobal(stopped),
-                I32Eqz,
-                BrIf(0),
-                End,
-                End,
-            ],
+            vec![I32Const(-1), SetGlobal(stopped)],
         );
-        self.start = start;
-        self.define_native_word("STOP", vec![], vec![I32Const(-1), SetGlobal(stopped)]);
 
         // DOCOL is how a colon word is executed. It just messes with the IP.
         let docol = self.create_native_callable(
+            "DOCOL",
             vec![],
             vec![
                 // push IP onto the return stack
@@ -751,6 +1543,7 @@ impl Compiler {
         // EXIT is how a colon word returns. It just restores the old IP.
         self.define_native_word(
             "EXIT",
+            "( -- )",
             vec![],
             vec![
                 // Set IP to whatever's the head of the return stack
@@ -762,6 +1555,7 @@ impl Compiler {
         // DODOES is what lets you customize runtime behavior of a word.
         // It does what DOCOL does, except it also pushes a word onto the stack.
         let dodoes = self.create_native_callable(
+            "DODOES",
             vec![],
             vec![
                 // push the head of our parameter onto the stack
@@ -781,6 +1575,7 @@ impl Compiler {
 
         self.define_native_word(
             "LIT",
+            "( -- x )",
             vec![],
             vec![
                 // The instruction pointer is pointing to LIT's XT inside of a colon definition.
@@ -798,6 +1593,7 @@ impl Compiler {
         );
         self.define_native_word(
             "BRANCH",
+            "( -- )",
             vec![],
             vec![
                 // The instruction pointer is pointing to BRANCH's XT inside of a colon definition.
@@ -811,6 +1607,7 @@ impl Compiler {
         );
         self.define_native_word(
             "?BRANCH",
+            "( x -- )",
             vec![],
             vec![
                 // Branch if the head of the stack is "false" (0)
@@ -883,17 +1680,34 @@ impl Compiler {
             res.push(Call(push));
             res
         };
-        self.define_native_word("+", vec![], binary_i32(I32Add));
-        self.define_native_word("-", vec![], binary_i32(I32Sub));
-        self.define_native_word("*", vec![], binary_i32(I32Mul));
+        self.define_stack_word(
+            "+",
+            "( n1 n2 -- n3 )",
+            binary_i32(I32Add),
+            self.cached_binary_i32(I32Add),
+        );
+        self.define_stack_word(
+            "-",
+            "( n1 n2 -- n3 )",
+            binary_i32(I32Sub),
+            self.cached_binary_i32(I32Sub),
+        );
+        self.define_stack_word(
+            "*",
+            "( n1 n2 -- n3 )",
+            binary_i32(I32Mul),
+            self.cached_binary_i32(I32Mul),
+        );
 
         self.define_native_word(
             "NEGATE",
+            "( n1 -- n2 )",
             vec![],
             vec![I32Const(0), Call(pop), I32Sub, Call(push)],
         );
         self.define_native_word(
             "ABS",
+            "( n -- u )",
             vec![],
             vec![
                 Call(pop),
@@ -909,17 +1723,39 @@ impl Compiler {
             ],
         );
 
-        self.define_native_word("S>D", vec![], vec![Call(pop), I64ExtendSI32, Call(push_d)]);
-        self.define_native_word("D>S", vec![], vec![Call(pop_d), I32WrapI64, Call(push)]);
+        self.define_native_word(
+            "S>D",
+            "( n -- d )",
+            vec![],
+            vec![Call(pop), I64ExtendSI32, Call(push_d)],
+        );
+        self.define_native_word(
+            "D>S",
+            "( d -- n )",
+            vec![],
+            vec![Call(pop_d), I32WrapI64, Call(push)],
+        );
         self.define_native_word(
             "M+",
+            "( d1 n -- d2 )",
             vec![],
             vec![Call(pop), I64ExtendSI32, Call(pop_d), I64Add, Call(push_d)],
         );
-        self.define_native_word("D+", vec![ValueType::I64], binary_i64(I64Add));
-        self.define_native_word("D-", vec![ValueType::I64], binary_i64(I64Sub));
+        self.define_native_word(
+            "D+",
+            "( d1 d2 -- d3 )",
+            vec![ValueType::I64],
+            binary_i64(I64Add),
+        );
+        self.define_native_word(
+            "D-",
+            "( d1 d2 -- d3 )",
+            vec![ValueType::I64],
+            binary_i64(I64Sub),
+        );
         self.define_native_word(
             "DABS",
+            "( d -- ud )",
             vec![ValueType::I64, ValueType::I64],
             vec![
                 Call(pop_d),
@@ -936,12 +1772,14 @@ impl Compiler {
         );
         self.define_native_word(
             "DNEGATE",
+            "( d1 -- d2 )",
             vec![],
             vec![I64Const(0), Call(pop_d), I64Sub, Call(push_d)],
         );
 
         self.define_native_word(
             "M*",
+            "( n1 n2 -- d )",
             vec![],
             vec![
                 Call(pop),
@@ -957,6 +1795,7 @@ impl Compiler {
 
         self.define_native_word(
             "UM*",
+            "( u1 u2 -- ud )",
             vec![],
             vec![
                 Call(pop),
@@ -972,6 +1811,7 @@ impl Compiler {
 
         self.define_native_word(
             "D*",
+            "( d1 n -- d2 )",
             vec![],
             vec![
                 Call(pop),
@@ -986,6 +1826,7 @@ impl Compiler {
 
         self.define_native_word(
             "UD*",
+            "( ud1 u -- ud2 )",
             vec![],
             vec![
                 Call(pop),
@@ -1000,17 +1841,14 @@ impl Compiler {
 
         self.define_native_word(
             "DD*",
+            "( d1 d2 -- d3 )",
             vec![],
-            vec![
-                Call(pop_d),
-                Call(pop_d),
-                I64Mul,
-                Call(push_d),
-            ]
+            vec![Call(pop_d), Call(pop_d), I64Mul, Call(push_d)],
         );
 
         self.define_native_word(
             "UM/MOD",
+            "( ud u1 -- u2 u3 )",
             vec![ValueType::I64, ValueType::I64],
             vec![
                 Call(pop),
@@ -1032,6 +1870,7 @@ impl Compiler {
 
         self.define_native_word(
             "/MOD",
+            "( n1 n2 -- n3 n4 )",
             vec![ValueType::I32, ValueType::I32, ValueType::I32],
             vec![
                 Call(pop),
@@ -1073,11 +1912,20 @@ impl Compiler {
             ],
         );
 
-        self.define_colon_word("/", vec![ColonValue::XT("/MOD"), ColonValue::XT("NIP")]);
-        self.define_colon_word("MOD", vec![ColonValue::XT("/MOD"), ColonValue::XT("DROP")]);
+        self.define_colon_word(
+            "/",
+            "( n1 n2 -- n3 )",
+            vec![ColonValue::XT("/MOD"), ColonValue::XT("NIP")],
+        );
+        self.define_colon_word(
+            "MOD",
+            "( n1 n2 -- n3 )",
+            vec![ColonValue::XT("/MOD"), ColonValue::XT("DROP")],
+        );
 
         self.define_native_word(
             "U/MOD",
+            "( u1 u2 -- u3 u4 )",
             vec![],
             vec![
                 Call(pop),
@@ -1096,6 +1944,7 @@ impl Compiler {
 
         self.define_native_word(
             "SM/REM",
+            "( d n1 -- n2 n3 )",
             vec![ValueType::I64, ValueType::I64],
             vec![
                 Call(pop),
@@ -1117,6 +1966,7 @@ impl Compiler {
 
         self.define_native_word(
             "FM/MOD",
+            "( d n1 -- n2 n3 )",
             vec![
                 ValueType::I64,
                 ValueType::I64,
@@ -1168,6 +2018,7 @@ impl Compiler {
 
         self.define_native_word(
             "UD/MOD",
+            "( ud1 u1 -- u2 ud2 )",
             vec![ValueType::I64, ValueType::I64],
             vec![
                 Call(pop),
@@ -1188,6 +2039,7 @@ impl Compiler {
 
         self.define_native_word(
             "MIN",
+            "( n1 n2 -- n3 )",
             vec![],
             vec![
                 Call(pop),
@@ -1203,6 +2055,7 @@ impl Compiler {
         );
         self.define_native_word(
             "MAX",
+            "( n1 n2 -- n3 )",
             vec![],
             vec![
                 Call(pop),
@@ -1218,6 +2071,7 @@ impl Compiler {
         );
         self.define_native_word(
             "DMIN",
+            "( d1 d2 -- d3 )",
             vec![ValueType::I64, ValueType::I64],
             vec![
                 Call(pop_d),
@@ -1233,6 +2087,7 @@ impl Compiler {
         );
         self.define_native_word(
             "DMAX",
+            "( d1 d2 -- d3 )",
             vec![ValueType::I64, ValueType::I64],
             vec![
                 Call(pop_d),
@@ -1249,26 +2104,59 @@ impl Compiler {
 
         self.define_native_word(
             "1+",
+            "( n1 -- n2 )",
             vec![],
             vec![Call(pop), I32Const(1), I32Add, Call(push)],
         );
         self.define_native_word(
             "1-",
+            "( n1 -- n2 )",
             vec![],
             vec![Call(pop), I32Const(1), I32Sub, Call(push)],
         );
         self.define_native_word(
             "INVERT",
+            "( x1 -- x2 )",
             vec![],
             vec![Call(pop), I32Const(-1), I32Xor, Call(push)],
         );
 
-        self.define_native_word("AND", vec![], binary_i32(I32And));
-        self.define_native_word("OR", vec![], binary_i32(I32Or));
-        self.define_native_word("XOR", vec![], binary_i32(I32Xor));
-        self.define_native_word("LSHIFT", vec![], binary_i32(I32Shl));
-        self.define_native_word("RSHIFT", vec![], binary_i32(I32ShrU));
-        self.define_native_word("ARSHIFT", vec![], binary_i32(I32ShrS));
+        self.define_stack_word(
+            "AND",
+            "( x1 x2 -- x3 )",
+            binary_i32(I32And),
+            self.cached_binary_i32(I32And),
+        );
+        self.define_stack_word(
+            "OR",
+            "( x1 x2 -- x3 )",
+            binary_i32(I32Or),
+            self.cached_binary_i32(I32Or),
+        );
+        self.define_stack_word(
+            "XOR",
+            "( x1 x2 -- x3 )",
+            binary_i32(I32Xor),
+            self.cached_binary_i32(I32Xor),
+        );
+        self.define_stack_word(
+            "LSHIFT",
+            "( x1 u -- x2 )",
+            binary_i32(I32Shl),
+            self.cached_binary_i32(I32Shl),
+        );
+        self.define_stack_word(
+            "RSHIFT",
+            "( x1 u -- x2 )",
+            binary_i32(I32ShrU),
+            self.cached_binary_i32(I32ShrU),
+        );
+        self.define_stack_word(
+            "ARSHIFT",
+            "( x1 u -- x2 )",
+            binary_i32(I32ShrS),
+            self.cached_binary_i32(I32ShrS),
+        );
 
         // ( mask addr -- )
         let bitmanip = |manip: Vec<Instruction>| {
@@ -1283,31 +2171,36 @@ impl Compiler {
             instructions.push(I32Store(2, 0));
             instructions
         };
-        self.define_native_word("CSET", vec![], bitmanip(vec![I32Or]));
+        self.define_native_word("CSET", "( x c-addr -- )", vec![], bitmanip(vec![I32Or]));
         self.define_native_word(
             "CRESET",
+            "( x c-addr -- )",
             vec![],
             bitmanip(vec![I32Const(-1), I32Xor, I32And]),
         );
-        self.define_native_word("CTOGGLE", vec![], bitmanip(vec![I32Xor]));
+        self.define_native_word("CTOGGLE", "( x c-addr -- )", vec![], bitmanip(vec![I32Xor]));
 
         self.define_native_word(
             "2*",
+            "( x1 -- x2 )",
             vec![],
             vec![Call(pop), I32Const(1), I32Shl, Call(push)],
         );
         self.define_native_word(
             "D2*",
+            "( d1 -- d2 )",
             vec![],
             vec![Call(pop_d), I64Const(1), I64Shl, Call(push_d)],
         );
         self.define_native_word(
             "2/",
+            "( x1 -- x2 )",
             vec![],
             vec![Call(pop), I32Const(1), I32ShrS, Call(push)],
         );
         self.define_native_word(
             "D2/",
+            "( d1 -- d2 )",
             vec![],
             vec![Call(pop_d), I64Const(1), I64ShrS, Call(push_d)],
         );
@@ -1315,29 +2208,61 @@ impl Compiler {
         self.define_constant_word("FALSE", 0);
         self.define_constant_word("TRUE", -1);
 
-        self.define_native_word("=", vec![], binary_i32_bool(I32Eq));
-        self.define_native_word("D=", vec![ValueType::I64], binary_i64_bool(I64Eq));
-        self.define_native_word("<>", vec![], binary_i32_bool(I32Ne));
-        self.define_native_word("D<>", vec![ValueType::I64], binary_i64_bool(I64Ne));
-        self.define_native_word("<", vec![], binary_i32_bool(I32LtS));
-        self.define_native_word("U<", vec![], binary_i32_bool(I32LtU));
-        self.define_native_word("D<", vec![ValueType::I64], binary_i64_bool(I64LtS));
-        self.define_native_word(">", vec![], binary_i32_bool(I32GtS));
-        self.define_native_word("U>", vec![], binary_i32_bool(I32GtU));
-        self.define_native_word("D>", vec![ValueType::I64], binary_i64_bool(I64GtS));
-        self.define_native_word("<=", vec![], binary_i32_bool(I32LeS));
-        self.define_native_word("U<=", vec![], binary_i32_bool(I32LeU));
-        self.define_native_word("D<=", vec![ValueType::I64], binary_i64_bool(I64LeS));
-        self.define_native_word(">=", vec![], binary_i32_bool(I32GeS));
-        self.define_native_word("U>=", vec![], binary_i32_bool(I32GeU));
-        self.define_native_word("D>=", vec![ValueType::I64], binary_i64_bool(I64GeS));
+        self.define_native_word("=", "( n1 n2 -- flag )", vec![], binary_i32_bool(I32Eq));
+        self.define_native_word(
+            "D=",
+            "( d1 d2 -- flag )",
+            vec![ValueType::I64],
+            binary_i64_bool(I64Eq),
+        );
+        self.define_native_word("<>", "( n1 n2 -- flag )", vec![], binary_i32_bool(I32Ne));
+        self.define_native_word(
+            "D<>",
+            "( d1 d2 -- flag )",
+            vec![ValueType::I64],
+            binary_i64_bool(I64Ne),
+        );
+        self.define_native_word("<", "( n1 n2 -- flag )", vec![], binary_i32_bool(I32LtS));
+        self.define_native_word("U<", "( u1 u2 -- flag )", vec![], binary_i32_bool(I32LtU));
+        self.define_native_word(
+            "D<",
+            "( d1 d2 -- flag )",
+            vec![ValueType::I64],
+            binary_i64_bool(I64LtS),
+        );
+        self.define_native_word(">", "( n1 n2 -- flag )", vec![], binary_i32_bool(I32GtS));
+        self.define_native_word("U>", "( u1 u2 -- flag )", vec![], binary_i32_bool(I32GtU));
+        self.define_native_word(
+            "D>",
+            "( d1 d2 -- flag )",
+            vec![ValueType::I64],
+            binary_i64_bool(I64GtS),
+        );
+        self.define_native_word("<=", "( n1 n2 -- flag )", vec![], binary_i32_bool(I32LeS));
+        self.define_native_word("U<=", "( u1 u2 -- flag )", vec![], binary_i32_bool(I32LeU));
+        self.define_native_word(
+            "D<=",
+            "( d1 d2 -- flag )",
+            vec![ValueType::I64],
+            binary_i64_bool(I64LeS),
+        );
+        self.define_native_word(">=", "( n1 n2 -- flag )", vec![], binary_i32_bool(I32GeS));
+        self.define_native_word("U>=", "( u1 u2 -- flag )", vec![], binary_i32_bool(I32GeU));
+        self.define_native_word(
+            "D>=",
+            "( d1 d2 -- flag )",
+            vec![ValueType::I64],
+            binary_i64_bool(I64GeS),
+        );
         self.define_native_word(
             "=0",
+            "( x -- flag )",
             vec![],
             vec![I32Const(0), Call(pop), I32Eqz, I32Sub, Call(push)],
         );
         self.define_native_word(
             "<>0",
+            "( x -- flag )",
             vec![],
             vec![
                 I32Const(0),
@@ -1350,6 +2275,7 @@ impl Compiler {
         );
         self.define_native_word(
             "<0",
+            "( n -- flag )",
             vec![],
             vec![
                 I32Const(0),
@@ -1362,6 +2288,7 @@ impl Compiler {
         );
         self.define_native_word(
             ">0",
+            "( n -- flag )",
             vec![],
             vec![
                 I32Const(0),
@@ -1374,39 +2301,475 @@ impl Compiler {
         );
     }
 
+    fn define_floats(&mut self) {
+        let push = self.push;
+        let pop = self.pop;
+        let push_d = self.push_d;
+        let pop_d = self.pop_d;
+        let push_f = self.push_f;
+        let pop_f = self.pop_f;
+        let f_stack = self.f_stack;
+        let float_base = self.options.layout.float_stack_base();
+        let unary_f64 = |op| vec![Call(pop_f), op, Call(push_f)];
+        let binary_f64 = |op| {
+            vec![
+                //swap the top of the stack before calling the real ops
+                Call(pop_f),
+                SetLocal(2),
+                Call(pop_f),
+                GetLocal(2),
+                op,
+                Call(push_f),
+            ]
+        };
+        let binary_f64_bool = |op| {
+            vec![
+                I32Const(0),
+                Call(pop_f),
+                SetLocal(2),
+                Call(pop_f),
+                GetLocal(2),
+                op,
+                I32Sub,
+                Call(push),
+            ]
+        };
+
+        // Interpreters without a float stack define this as false
+        self.define_constant_word("FLOATS?", -1);
+        self.define_native_word("FDROP", "( F: r -- )", vec![], vec![Call(pop_f), Drop]);
+        self.define_native_word(
+            "FDUP",
+            "( F: r -- r r )",
+            vec![ValueType::F64],
+            vec![
+                Call(pop_f),
+                TeeLocal(2),
+                Call(push_f),
+                GetLocal(2),
+                Call(push_f),
+            ],
+        );
+        self.define_native_word(
+            "FSWAP",
+            "( F: r1 r2 -- r2 r1 )",
+            vec![ValueType::F64, ValueType::F64],
+            vec![
+                Call(pop_f),
+                SetLocal(2),
+                Call(pop_f),
+                SetLocal(3),
+                GetLocal(2),
+                Call(push_f),
+                GetLocal(3),
+                Call(push_f),
+            ],
+        );
+        self.define_native_word(
+            "FOVER",
+            "( F: r1 r2 -- r1 r2 r1 )",
+            vec![ValueType::F64, ValueType::F64],
+            vec![
+                Call(pop_f),
+                SetLocal(2),
+                Call(pop_f),
+                TeeLocal(3),
+                Call(push_f),
+                GetLocal(2),
+                Call(push_f),
+                GetLocal(3),
+                Call(push_f),
+            ],
+        );
+        self.define_native_word(
+            "FROT",
+            "( F: r1 r2 r3 -- r2 r3 r1 )",
+            vec![ValueType::F64, ValueType::F64, ValueType::F64],
+            vec![
+                Call(pop_f),
+                SetLocal(2),
+                Call(pop_f),
+                SetLocal(3),
+                Call(pop_f),
+                SetLocal(4),
+                GetLocal(3),
+                Call(push_f),
+                GetLocal(2),
+                Call(push_f),
+                GetLocal(4),
+                Call(push_f),
+            ],
+        );
+        self.define_native_word(
+            "FDEPTH",
+            "( -- +n )",
+            vec![],
+            vec![
+                I32Const(float_base),
+                GetGlobal(f_stack),
+                I32Sub,
+                I32Const(3),
+                I32ShrU,
+                Call(push),
+            ],
+        );
+        // CATCH saves and restores the float stack with these
+        self.define_native_word(
+            "FP@",
+            "( -- f-addr )",
+            vec![],
+            vec![GetGlobal(f_stack), Call(push)],
+        );
+        self.define_native_word(
+            "FP!",
+            "( f-addr -- )",
+            vec![],
+            vec![Call(pop), SetGlobal(f_stack)],
+        );
+
+        self.define_native_word(
+            "F@",
+            "( f-addr -- ) ( F: -- r )",
+            vec![],
+            vec![Call(pop), F64Load(3, 0), Call(push_f)],
+        );
+        self.define_native_word(
+            "F!",
+            "( f-addr -- ) ( F: r -- )",
+            vec![],
+            vec![Call(pop), Call(pop_f), F64Store(3, 0)],
+        );
+        let ip = self.ip;
+        self.define_native_word(
+            "FLIT",
+            "( F: -- r )",
+            vec![],
+            vec![
+                // Like LIT, but the literal after the XT takes two cells
+                GetGlobal(ip),
+                I32Const(4),
+                I32Add,
+                TeeLocal(0),
+                F64Load(2, 0),
+                Call(push_f),
+                GetLocal(0),
+                I32Const(4),
+                I32Add,
+                SetGlobal(ip),
+            ],
+        );
+
+        let binary_words = [
+            ("F+", F64Add),
+            ("F-", F64Sub),
+            ("F*", F64Mul),
+            ("F/", F64Div),
+            ("FMIN", F64Min),
+            ("FMAX", F64Max),
+        ];
+        for (name, op) in binary_words {
+            self.define_native_word(
+                name,
+                "( F: r1 r2 -- r3 )",
+                vec![ValueType::F64],
+                binary_f64(op),
+            );
+        }
+        let unary_words = [
+            ("FNEGATE", F64Neg),
+            ("FABS", F64Abs),
+            ("FSQRT", F64Sqrt),
+            ("FLOOR", F64Floor),
+            // rounds to even, like Forth 2012 asks
+            ("FROUND", F64Nearest),
+            ("FTRUNC", F64Trunc),
+        ];
+        for (name, op) in unary_words {
+            self.define_native_word(name, "( F: r1 -- r2 )", vec![], unary_f64(op));
+        }
+
+        self.define_native_word(
+            "F<",
+            "( -- flag ) ( F: r1 r2 -- )",
+            vec![ValueType::F64],
+            binary_f64_bool(F64Lt),
+        );
+        self.define_native_word(
+            "F0<",
+            "( -- flag ) ( F: r -- )",
+            vec![],
+            vec![
+                I32Const(0),
+                Call(pop_f),
+                F64Const(0),
+                F64Lt,
+                I32Sub,
+                Call(push),
+            ],
+        );
+        self.define_native_word(
+            "F0=",
+            "( -- flag ) ( F: r -- )",
+            vec![],
+            vec![
+                I32Const(0),
+                Call(pop_f),
+                F64Const(0),
+                F64Eq,
+                I32Sub,
+                Call(push),
+            ],
+        );
+
+        self.define_native_word(
+            "S>F",
+            "( n -- ) ( F: -- r )",
+            vec![],
+            vec![Call(pop), F64ConvertSI32, Call(push_f)],
+        );
+        self.define_native_word(
+            "D>F",
+            "( d -- ) ( F: -- r )",
+            vec![],
+            vec![Call(pop_d), F64ConvertSI64, Call(push_f)],
+        );
+        // These truncate towards zero, with a flag for whether the integer part fit.
+        // What doesn't fit (NaN included) would trap, so it comes out as 0 instead.
+        let fits_i32 = vec![
+            GetLocal(2),
+            F64Const((-2147483649f64).to_bits()),
+            F64Gt,
+            GetLocal(2),
+            F64Const(2147483648f64.to_bits()),
+            F64Lt,
+            I32And,
+        ];
+        let fits_i64 = vec![
+            GetLocal(2),
+            F64Const((-9223372036854775808f64).to_bits()),
+            F64Ge,
+            GetLocal(2),
+            F64Const(9223372036854775808f64.to_bits()),
+            F64Lt,
+            I32And,
+        ];
+        let truncate = |fits: Vec<Instruction>, convert: Instruction, push_value: u32| {
+            let mut instructions = vec![Call(pop_f), SetLocal(2)];
+            instructions.extend(fits);
+            instructions.extend(vec![
+                SetLocal(3),
+                GetLocal(2),
+                F64Const(0),
+                GetLocal(3),
+                Select,
+                convert,
+                Call(push_value),
+                I32Const(0),
+                GetLocal(3),
+                I32Sub,
+                Call(push),
+            ]);
+            instructions
+        };
+        self.define_native_word(
+            "(F>S)",
+            "( -- n flag ) ( F: r -- )",
+            vec![ValueType::F64, ValueType::I32],
+            truncate(fits_i32, I32TruncSF64, push),
+        );
+        self.define_native_word(
+            "(F>D)",
+            "( -- d flag ) ( F: r -- )",
+            vec![ValueType::F64, ValueType::I32],
+            truncate(fits_i64, I64TruncSF64, push_d),
+        );
+        // -11 is "result out of range"
+        self.define_forth_word("F>S", "( -- n ) ( F: r -- ) (f>s) =0 if -11 throw then");
+        self.define_forth_word("F>D", "( -- d ) ( F: r -- ) (f>d) =0 if -11 throw then");
+    }
+
+    fn define_exceptions(&mut self) {
+        // Give up on the whole program, leaving the code in the "error" global for the host
+        let error = self.error;
+        self.define_native_word(
+            "TRAP",
+            "( n -- )",
+            vec![],
+            vec![Call(self.pop), SetGlobal(error), Unreachable],
+        );
+
+        // HANDLER points at the return stack frame of the innermost CATCH.
+        // That frame holds the previous HANDLER, the float and data stack pointers,
+        // and where CATCH returns to.
+        self.define_variable_word("HANDLER", 0);
+        // The XT to run when nothing catches an exception, if there is one
+        self.define_variable_word("'UNCAUGHT", 0);
+
+        // The return stack which the last THROW unwound, innermost cell first, for backtraces
+        let layout = self.options.layout;
+        self.define_constant_word("BACKTRACE", layout.backtrace());
+        self.define_constant_word("BACKTRACE-CAPACITY", layout.backtrace_depth);
+        self.define_variable_word("#BACKTRACE", 0);
+        self.define_forth_word(
+            "SAVE-BACKTRACE",
+            r#"( -- ) \ copy the frames between THROW and the CATCH it's about to return from
+            0 #backtrace !
+            handler @ ?dup =0 if return-stack-base then \ with nothing to catch it, save everything
+            rp@ 4 + \ skip returning to THROW, which is always there
+            begin
+                2dup u> #backtrace @ backtrace-capacity < and
+            while
+                dup @ #backtrace @ cells backtrace + !
+                1 #backtrace +!
+                4 +
+            repeat
+            2drop
+            "#,
+        );
+
+        self.define_forth_word(
+            "CATCH",
+            r#"( i*x xt -- j*x 0 | i*x n )
+            0 #backtrace ! \ anything thrown before this has been dealt with
+            sp@ >r \ save the data stack, including xt
+            fp@ >r \ and the float stack
+            handler @ >r rp@ handler ! \ push a new frame
+            execute
+            r> handler ! \ pop the frame
+            r> drop r> drop 0 \ nothing was thrown
+            "#,
+        );
+
+        // Like THROW, but keeps the backtrace of the exception it's passing on
+        self.define_forth_word(
+            "RETHROW",
+            r#"( k*x n -- k*x | i*x n )
+            ?dup if
+                handler @ =0 if \ nobody is going to catch this
+                    dup 'uncaught @ ?dup if execute else drop then
+                    trap \ in case the handler came back
+                then
+                handler @ rp! \ back to the innermost CATCH's frame
+                r> handler ! \ pop the frame
+                r> fp!
+                r> swap >r sp! drop r> \ restore the data stack, replacing xt with n
+                \ the return stack is CATCH's now, so exiting returns from CATCH
+            then
+            "#,
+        );
+
+        self.define_forth_word(
+            "THROW",
+            r#"( k*x n -- k*x | i*x n )
+            ?dup if save-backtrace rethrow then
+            "#,
+        );
+    }
+
     fn finalize(mut self) -> Self {
         // For testing purposes, define a word that just calls another word and stops.
-        self.define_colon_word(
+        // The entry points run it by pointing the IP into it, so it has to be threaded.
+        self.define_colon_word_with(
             "RUN-WORD",
+            "( i*x xt -- j*x )",
             vec![ColonValue::XT("EXECUTE"), ColonValue::XT("STOP")],
+            Threading::Indirect,
         );
 
+        // Every word is defined now, so patch in any the colon words used early
+        for reference in std::mem::take(&mut self.forward_references) {
+            match self.find_execution_token(reference.word) {
+                Some(xt) => self
+                    .assembler
+                    .add_data(reference.address, xt.to_le_bytes().to_vec()),
+                None => self.errors.push(format!(
+                    "In {}: unknown word {}",
+                    reference.definition, reference.word
+                )),
+            }
+        }
+        self.compile_subroutines();
+        self.check_effects();
+
         // Now that we're done adding things to the dictionary,
         // set values for CP (a var containing the next address in the dictionary)
         // and LATEST (a var containing the address of the final word).
 
         let cp_storage_address = self.get_execution_token("CP") + 4;
-        let cp_bytes: Vec<u8> = self.cp.to_le_bytes().iter().copied().collect();
-        self.assembler.add_data(cp_storage_address, cp_bytes);
+        self.assembler
+            .add_data(cp_storage_address, self.cp.to_le_bytes().to_vec());
 
         let latest_storage_address = self.get_execution_token("LATEST") + 4;
-        let latest_bytes = self.latest_address.to_le_bytes().iter().copied().collect();
+        self.assembler.add_data(
+            latest_storage_address,
+            self.latest_address.to_le_bytes().to_vec(),
+        );
+
+        // The heap starts after any static data, so it's not known until now either.
+        let heap_base_storage_address = self.get_execution_token("HEAP-BASE") + 4;
+        self.assembler.add_data(
+            heap_base_storage_address,
+            self.heap_base.to_le_bytes().to_vec(),
+        );
+        // Start with enough memory for the heap's initial bookkeeping
+        let pages = (self.heap_base + 2 * 4 + PAGE_SIZE - 1) / PAGE_SIZE;
+        self.assembler.add_memory(pages as u32);
+
+        // List every word, so that tools can find them without running the module
+        let mut entries: Vec<DictionaryEntry> = self
+            .execution_tokens
+            .iter()
+            .map(|(name, xt)| DictionaryEntry {
+                name: name.clone(),
+                xt: *xt,
+                kind: self.word_kinds[xt],
+                immediate: false,
+                hidden: false,
+                trampolined: false,
+            })
+            .collect();
+        entries.sort_by_key(|entry| entry.xt);
         self.assembler
-            .add_data(latest_storage_address, latest_bytes);
+            .add_custom_section(DICTIONARY_SECTION, encode_dictionary(&entries));
+        let mut origins: Vec<(i32, &str, u32)> = self
+            .origins
+            .iter()
+            .map(|(xt, location)| (*xt, location.file(), location.line()))
+            .collect();
+        origins.sort_unstable();
+        self.assembler
+            .add_custom_section(ORIGINS_SECTION, encode_origins(&origins));
 
         let run_xt = self.get_execution_token("RUN-WORD");
-        #[cfg(test)]
-        let xts = {
-            // For testing, export every word as a function-which-EXECUTEs-that-word
-            self.execution_tokens.clone()
-        };
-        #[cfg(not(test))]
-        let xts = {
-            // Export _start, the conventional WASI entry point
-            let mut xts = HashMap::new();
-            xts.insert("_start".to_owned(), self.get_execution_token("_start"));
-            xts
+        let mut xts = match self.options.exports {
+            Exports::All => {
+                // Export the stacks, and every word as a function-which-EXECUTEs-that-word
+                self.assembler.add_exported_func("push", self.push);
+                self.assembler.add_exported_func("pop", self.pop);
+                self.assembler.add_exported_func("push_d", self.push_d);
+                self.assembler.add_exported_func("pop_d", self.pop_d);
+                self.assembler.add_exported_func("push_f", self.push_f);
+                self.assembler.add_exported_func("pop_f", self.pop_f);
+                self.execution_tokens.clone()
+            }
+            Exports::Start => {
+                // Export _start, the conventional WASI entry point
+                let mut xts = HashMap::new();
+                match self.find_execution_token("_start") {
+                    Some(xt) => drop(xts.insert("_start".to_owned(), xt)),
+                    None => self.errors.push("No _start word to export".to_owned()),
+                }
+                xts
+            }
         };
+        for (export, word) in self.entry_points.iter() {
+            match self.find_execution_token(word) {
+                Some(xt) => drop(xts.insert(export.clone(), xt)),
+                None => self
+                    .errors
+                    .push(format!("Entry point {} runs unknown word {}", export, word)),
+            }
+        }
         for (word, xt) in xts {
             let func = self.assembler.add_native_func(
                 vec![],
@@ -1421,23 +2784,328 @@ impl Compiler {
                     End,
                 ],
             );
+            self.assembler
+                .name_func(func, &format!("entry:{}", word), &[]);
             self.assembler.add_exported_func(&word, func);
         }
         self
     }
 
+    // Replace the stack functions with ones which keep the top of the stack in a global
+    fn define_cached_top(&mut self, param_stack: &StackBounds) {
+        let stack = self.stack;
+        let tos = self.add_global(0);
+        self.assembler.add_exported_global("tos", tos);
+
+        let mut push_instructions = self.check_overflow(stack, param_stack, 4);
+        push_instructions.extend(vec![
+            // spill the old top into memory
+            GetGlobal(stack),
+            GetGlobal(tos),
+            I32Store(2, 0),
+            GetGlobal(stack),
+            I32Const(4),
+            I32Sub,
+            SetGlobal(stack),
+            GetLocal(0),
+            SetGlobal(tos),
+            End,
+        ]);
+        let push =
+            self.assembler
+                .add_native_func(vec![ValueType::I32], vec![], vec![], push_instructions);
+
+        let mut pop_instructions = self.check_underflow(stack, param_stack, 4);
+        pop_instructions.extend(vec![
+            GetGlobal(tos),
+            GetGlobal(stack),
+            I32Const(4),
+            I32Add,
+            SetGlobal(stack),
+            // the next value down is the new top
+            GetGlobal(stack),
+            I32Load(2, 0),
+            SetGlobal(tos),
+            End,
+        ]);
+        let pop =
+            self.assembler
+                .add_native_func(vec![], vec![ValueType::I32], vec![], pop_instructions);
+
+        // doubles keep their high cell on top
+        let push_d = self.assembler.add_native_func(
+            vec![ValueType::I64],
+            vec![],
+            vec![],
+            vec![
+                GetLocal(0),
+                I32WrapI64,
+                Call(push),
+                GetLocal(0),
+                I64Const(32),
+                I64ShrU,
+                I32WrapI64,
+                Call(push),
+                End,
+            ],
+        );
+        let pop_d = self.assembler.add_native_func(
+            vec![],
+            vec![ValueType::I64],
+            vec![],
+            vec![
+                Call(pop),
+                I64ExtendUI32,
+                I64Const(32),
+                I64Shl,
+                Call(pop),
+                I64ExtendUI32,
+                I64Or,
+                End,
+            ],
+        );
+
+        self.assembler.name_func(push, "cached:push", &["value"]);
+        self.assembler.name_func(pop, "cached:pop", &[]);
+        self.assembler
+            .name_func(push_d, "cached:push_d", &["value"]);
+        self.assembler.name_func(pop_d, "cached:pop_d", &[]);
+        let memory_calls = [
+            (push, self.push),
+            (pop, self.pop),
+            (push_d, self.push_d),
+            (pop_d, self.pop_d),
+        ];
+        self.push = push;
+        self.pop = pop;
+        self.push_d = push_d;
+        self.pop_d = pop_d;
+        self.cached_top = Some(CachedTop {
+            tos,
+            memory_calls: memory_calls.iter().copied().collect(),
+        });
+    }
+
+    // A native which reaches into the parameter stack, with a body for each stack model
+    #[track_caller]
+    fn define_stack_word(
+        &mut self,
+        name: &str,
+        effect: &str,
+        memory: Vec<Instruction>,
+        cached: Vec<Instruction>,
+    ) {
+        let instructions = match self.cached_top {
+            Some(_) => cached,
+            None => memory,
+        };
+        self.add_native_word(name, effect, vec![], instructions);
+    }
+
+    // A cached body which replaces the top two cells with one, without calling push or pop
+    fn cached_binary_i32(&self, op: Instruction) -> Vec<Instruction> {
+        let stack = self.stack;
+        let tos = self.cached_tos();
+        vec![
+            GetGlobal(stack),
+            I32Load(2, 4),
+            GetGlobal(tos),
+            op,
+            SetGlobal(tos),
+            GetGlobal(stack),
+            I32Const(4),
+            I32Add,
+            SetGlobal(stack),
+        ]
+    }
+
+    // A cached body which stores the second cell at the address on top, without calling pop
+    fn cached_store(&self, store: Instruction) -> Vec<Instruction> {
+        let stack = self.stack;
+        let tos = self.cached_tos();
+        vec![
+            GetGlobal(tos),
+            GetGlobal(stack),
+            I32Load(2, 4),
+            store,
+            GetGlobal(stack),
+            I32Const(8),
+            I32Add,
+            SetGlobal(stack),
+            // the third cell down is the new top
+            GetGlobal(stack),
+            I32Load(2, 0),
+            SetGlobal(tos),
+        ]
+    }
+
+    // A cached body which replaces the address on top with what it points to
+    fn cached_fetch(&self, load: Instruction) -> Vec<Instruction> {
+        let tos = self.cached_tos();
+        vec![GetGlobal(tos), load, SetGlobal(tos)]
+    }
+
+    // only the cached bodies of stack words use this
+    fn cached_tos(&self) -> u32 {
+        self.cached_top
+            .as_ref()
+            .map_or(0, |cached_top| cached_top.tos)
+    }
+
+    #[track_caller]
     fn define_native_word(
         &mut self,
         name: &str,
+        effect: &str,
         locals: Vec<ValueType>,
         instructions: Vec<Instruction>,
     ) {
-        let code = self.create_native_callable(locals, instructions);
-        self.define_word(name, code, &[]);
+        let instructions = self.spill_cached_top(instructions);
+        self.add_native_word(name, effect, locals, instructions);
+    }
+
+    // If the top of the stack is cached, let code which reads the stack's memory find it there
+    fn spill_cached_top(&self, instructions: Vec<Instruction>) -> Vec<Instruction> {
+        let (stack, cached_top) = match &self.cached_top {
+            Some(cached_top) => (self.stack, cached_top),
+            None => return instructions,
+        };
+        let reaches_into_stack = instructions
+            .iter()
+            .any(|i| matches!(i, GetGlobal(global) | SetGlobal(global) if *global == stack));
+        if !reaches_into_stack {
+            return instructions;
+        }
+        let tos = cached_top.tos;
+        let mut spilled = vec![GetGlobal(stack), GetGlobal(tos), I32Store(2, 0)];
+        spilled.extend(
+            instructions
+                .into_iter()
+                .map(|instruction| match instruction {
+                    Call(func) => Call(*cached_top.memory_calls.get(&func).unwrap_or(&func)),
+                    instruction => instruction,
+                }),
+        );
+        spilled.extend(vec![GetGlobal(stack), I32Load(2, 0), SetGlobal(tos)]);
+        spilled
+    }
+
+    #[track_caller]
+    fn add_native_word(
+        &mut self,
+        name: &str,
+        effect: &str,
+        locals: Vec<ValueType>,
+        instructions: Vec<Instruction>,
+    ) {
+        self.declare_effect(name, effect);
+        // Native words touch the stack directly, so check that their effect fits up front
+        let mut checked_instructions = vec![];
+        let param_stack = self.param_stack();
+        if let Some(effect) = self.effects.get(name) {
+            let inputs = effect.inputs() * 4;
+            let growth = effect.growth().unwrap_or(0) * 4;
+            if inputs > 0 {
+                checked_instructions.extend(self.check_underflow(self.stack, &param_stack, inputs));
+            }
+            if growth > 0 {
+                checked_instructions.extend(self.check_overflow(self.stack, &param_stack, growth));
+            }
+        }
+        checked_instructions.extend(instructions);
+        let inlinable = self.options.inline_primitives && inliner::inlinable(&checked_instructions);
+        let body = inlinable.then(|| (locals.clone(), checked_instructions.clone()));
+        let func =
+            self.create_native_func(&format!("forth:{}", name), locals, checked_instructions);
+        if let Some(body) = body {
+            self.inlinable_natives.insert(func, body);
+        }
+        let code = self.add_callable(func);
+        let xt = self.define_word(name, WordKind::Native, code, &[]);
+        self.direct_calls.insert(xt, DirectCall::Native(func));
+    }
+
+    // The parameter stack grows down into the end of the dictionary's space.
+    // A cached top spills into the cell at "sp", so then an empty stack starts one cell lower.
+    fn param_stack(&self) -> StackBounds {
+        let layout = &self.options.layout;
+        let spill = match self.options.stack_model {
+            StackModel::Memory => 0,
+            StackModel::CachedTop => 4,
+        };
+        StackBounds {
+            base: layout.param_stack_base() - spill,
+            limit: layout.dictionary_base() + layout.dictionary_capacity,
+            overflow: -3,
+            underflow: -4,
+        }
+    }
+
+    fn return_stack(&self) -> StackBounds {
+        let layout = &self.options.layout;
+        StackBounds {
+            base: layout.return_stack_base(),
+            limit: layout.param_stack_base(),
+            overflow: -5,
+            underflow: -6,
+        }
+    }
+
+    fn float_stack(&self) -> StackBounds {
+        let layout = &self.options.layout;
+        StackBounds {
+            base: layout.float_stack_base(),
+            limit: layout.return_stack_base(),
+            overflow: -44,
+            underflow: -45,
+        }
+    }
+
+    // Instructions to trap unless the stack has room for this many more bytes
+    fn check_overflow(&self, stack: u32, bounds: &StackBounds, bytes: i32) -> Vec<Instruction> {
+        if !self.options.checked_stacks {
+            return vec![];
+        }
+        let mut instructions = vec![GetGlobal(stack), I32Const(bounds.limit + bytes), I32LtS];
+        instructions.extend(self.trap_if(bounds.overflow));
+        instructions
+    }
+
+    // Instructions to trap unless the stack holds at least this many bytes
+    fn check_underflow(&self, stack: u32, bounds: &StackBounds, bytes: i32) -> Vec<Instruction> {
+        if !self.options.checked_stacks {
+            return vec![];
+        }
+        let mut instructions = vec![GetGlobal(stack), I32Const(bounds.base - bytes), I32GtS];
+        instructions.extend(self.trap_if(bounds.underflow));
+        instructions
+    }
+
+    // Trap if the top of the wasm stack is true, leaving the code for the host to find
+    fn trap_if(&self, code: i32) -> Vec<Instruction> {
+        vec![
+            If(BlockType::NoResult),
+            I32Const(code),
+            SetGlobal(self.error),
+            Unreachable,
+            End,
+        ]
     }
 
     fn create_native_callable(
         &mut self,
+        name: &str,
+        locals: Vec<ValueType>,
+        instructions: Vec<Instruction>,
+    ) -> u32 {
+        let func = self.create_native_func(&format!("callable:{}", name), locals, instructions);
+        self.add_callable(func)
+    }
+
+    // A function which can go in a code field
+    fn create_native_func(
+        &mut self,
+        name: &str,
         locals: Vec<ValueType>,
         mut instructions: Vec<Instruction>,
     ) -> u32 {
@@ -1448,17 +3116,168 @@ impl Compiler {
             locals,
             instructions,
         );
-        self.assembler.add_table_entry(func)
+        self.assembler
+            .name_func(func, name, &["parameter", "immediate"]);
+        func
+    }
+
+    // Put a function in the table, so that code fields can refer to it
+    fn add_callable(&mut self, func: u32) -> u32 {
+        let index = self.assembler.add_table_entry(func);
+        if index == 1 << CODE_INDEX_BITS {
+            self.errors.push(format!(
+                "Code fields can only refer to {} callables",
+                1 << CODE_INDEX_BITS
+            ));
+        }
+        index
+    }
+
+    fn declare_effect(&mut self, name: &str, effect: &str) {
+        match StackEffect::parse(effect) {
+            Ok(effect) => drop(self.effects.insert(name.to_owned(), effect)),
+            Err(error) => self.errors.push(format!("In {}: {}", name, error)),
+        }
+    }
+
+    // Like execution tokens, lowercase names can refer to uppercase words
+    fn find_effect(&self, name: &str) -> Option<&StackEffect> {
+        self.effects
+            .get(name)
+            .or_else(|| self.effects.get(&name.to_ascii_uppercase()))
+    }
+
+    // Fill in the functions of subroutine-threaded colon words, now that every word they call exists
+    fn compile_subroutines(&mut self) {
+        for PendingSubroutine { name, func, ops } in std::mem::take(&mut self.subroutines) {
+            // local 2 holds the return stack pointer from when the word was called
+            let mut instructions = vec![GetGlobal(self.r_stack), SetLocal(2)];
+            self.assembler.name_func(
+                func,
+                &format!("forth:{}", name),
+                &["parameter", "immediate", "entry-rp"],
+            );
+            let mut inliner = Inliner::new(3);
+            // Calls which can run threaded code might have unwound past this word, or stopped
+            let return_if_unwound = [
+                GetGlobal(self.r_stack),
+                GetLocal(2),
+                I32GtU,
+                GetGlobal(self.stopped),
+                I32Or,
+                If(BlockType::NoResult),
+                Return,
+                End,
+            ];
+            for op in ops {
+                match op {
+                    Op::Call(word) => {
+                        let xt = match self.find_execution_token(word) {
+                            Some(xt) => xt,
+                            None => {
+                                self.errors
+                                    .push(format!("In {}: unknown word {}", name, word));
+                                continue;
+                            }
+                        };
+                        match self.direct_calls.get(&xt) {
+                            Some(DirectCall::Native(func)) => {
+                                match self.inlinable_natives.get(func) {
+                                    Some((locals, body)) => instructions.extend(inliner.inline(
+                                        &[ValueType::I32, ValueType::I32],
+                                        locals,
+                                        body,
+                                    )),
+                                    None => instructions.extend([
+                                        I32Const(xt + 4),
+                                        I32Const(0),
+                                        Call(*func),
+                                    ]),
+                                }
+                            }
+                            Some(DirectCall::Colon(func)) => {
+                                instructions.extend([I32Const(xt + 4), I32Const(0), Call(*func)]);
+                                instructions.extend(return_if_unwound.clone());
+                            }
+                            Some(DirectCall::Execute) => {
+                                instructions.extend([Call(self.pop), Call(self.execute_nested)]);
+                                instructions.extend(return_if_unwound.clone());
+                            }
+                            None => {
+                                instructions.extend([I32Const(xt), Call(self.execute_nested)]);
+                                instructions.extend(return_if_unwound.clone());
+                            }
+                        }
+                    }
+                    Op::PushXt(word) => match self.find_execution_token(word) {
+                        Some(xt) => instructions.extend([I32Const(xt), Call(self.push)]),
+                        None => self
+                            .errors
+                            .push(format!("In {}: unknown word {}", name, word)),
+                    },
+                    Op::Push(value) => instructions.extend([I32Const(value), Call(self.push)]),
+                    Op::Exit => instructions.push(Return),
+                    Op::Block => instructions.push(Block(BlockType::NoResult)),
+                    Op::Loop => instructions.push(Loop(BlockType::NoResult)),
+                    Op::End => instructions.push(End),
+                    Op::Br(depth) => instructions.push(Br(depth)),
+                    Op::BrIfFalse(depth) => {
+                        instructions.extend([Call(self.pop), I32Eqz, BrIf(depth)])
+                    }
+                }
+            }
+            instructions.push(End);
+            let mut locals = vec![ValueType::I32];
+            locals.extend(inliner.locals());
+            if self.options.inline_primitives {
+                let first_local = 2 + locals.len() as u32;
+                let mut stack_globals = vec![self.stack];
+                stack_globals.extend(self.cached_top.as_ref().map(|cached_top| cached_top.tos));
+                let scratch = inliner::cancel_pushes(
+                    &mut instructions,
+                    self.push,
+                    self.pop,
+                    &stack_globals,
+                    first_local,
+                );
+                locals.extend(vec![ValueType::I32; scratch as usize]);
+            }
+            self.assembler
+                .set_native_func_body(func, locals, instructions);
+        }
+    }
+
+    // Make sure every colon word leaves the stack the way it says it does
+    fn check_effects(&mut self) {
+        let errors: Vec<String> = self
+            .colon_words
+            .iter()
+            .filter_map(|(name, steps)| {
+                let effect = self.effects.get(name)?;
+                let checked = stack_effect::check(effect, steps, |word| self.find_effect(word));
+                checked.err().map(|error| format!("In {}: {}", name, error))
+            })
+            .collect();
+        self.errors.extend(errors);
     }
 
     fn get_execution_token(&self, name: &str) -> i32 {
-        match self.execution_tokens.get(name) {
-            Some(xt) => *xt,
+        match self.find_execution_token(name) {
+            Some(xt) => xt,
             None => panic!("Could not find definition for \"{}\"", name),
         }
     }
 
-    fn define_word(&mut self, name: &str, code: u32, parameter: &[u8]) {
+    // Like the interpreter, lowercase names can refer to uppercase words
+    fn find_execution_token(&self, name: &str) -> Option<i32> {
+        self.execution_tokens
+            .get(name)
+            .or_else(|| self.execution_tokens.get(&name.to_ascii_uppercase()))
+            .copied()
+    }
+
+    #[track_caller]
+    fn define_word(&mut self, name: &str, kind: WordKind, code: u32, parameter: &[u8]) -> i32 {
         let old_latest_address = self.latest_address;
         let latest_address = self.cp;
 
@@ -1471,13 +3290,16 @@ impl Compiler {
         data.extend_from_slice(parameter);
 
         // for testing purposes, store execution tokens for later
-        self.execution_tokens
-            .insert(name.to_owned(), latest_address + header_size(name) - 4);
+        let xt = latest_address + header_size(name) - 4;
+        self.execution_tokens.insert(name.to_owned(), xt);
+        self.word_kinds.insert(xt, kind);
+        self.origins.insert(xt, Location::caller());
 
         let cp = self.cp + data.len() as i32;
         self.assembler.add_data(self.cp, data);
         self.cp = cp;
         self.latest_address = latest_address;
+        xt
     }
 
     fn add_global(&mut self, initial_value: i32) -> u32 {
@@ -1489,39 +3311,71 @@ impl Compiler {
         })
     }
 }
-impl Default for Compiler {
-    fn default() -> Self {
-        let mut assembler: Assembler = Default::default();
-        assembler.add_memory();
+impl Compiler {
+    pub fn new(options: CompilerOptions) -> Self {
+        let layout = options.layout;
+        let errors = match layout.validate() {
+            Ok(()) => vec![],
+            Err(error) => vec![error.to_string()],
+        };
         Self {
-            assembler,
+            options,
+            assembler: Default::default(),
+            error: 0,
             stack: 0,
             push: 0,
             pop: 0,
             push_d: 0,
             pop_d: 0,
+            r_stack: 0,
             push_r: 0,
             pop_r: 0,
+            f_stack: 0,
+            push_f: 0,
+            pop_f: 0,
             docon: 0,
             dovar: 0,
             docol: 0,
             start: 0,
+            execute_nested: 0,
             ip: 0,
-            cp: DICTIONARY_BASE,
+            stopped: 0,
+            cp: layout.dictionary_base(),
             latest_address: 0,
+            heap_base: layout.heap_base(),
             execution_tokens: HashMap::new(),
+            word_kinds: HashMap::new(),
+            origins: HashMap::new(),
+            entry_points: vec![],
+            forward_references: vec![],
+            effects: HashMap::new(),
+            colon_words: vec![],
+            direct_calls: HashMap::new(),
+            subroutines: vec![],
+            inlinable_natives: HashMap::new(),
+            cached_top: None,
+            errors,
         }
         .initialize()
     }
 }
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new(CompilerOptions::default())
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use anyhow::Result;
     use wasmer::{imports, Function, ImportObject, Module, Store};
 
-    use super::{ColonValue::*, Compiler};
-    use crate::{compiler::ParamType, runtime::BasicRuntime};
+    use super::{
+        read_dictionary, ColonValue::*, Compiler, CompilerOptions, Exports, MemoryLayout,
+        StackModel, Threading, WordKind,
+    };
+    use crate::{assembler::ValidationError, compiler::ParamType, runtime::BasicRuntime};
+    use parity_wasm::elements::Instruction::{Call, Drop, GetLocal, I32Const};
 
     fn build<T>(func: T) -> Result<BasicRuntime>
     where
@@ -1530,12 +3384,36 @@ mod tests {
         build_with_imports(func, |_, _| imports! {})
     }
 
+    fn build_checked<T>(func: T) -> Result<BasicRuntime>
+    where
+        T: FnOnce(&mut Compiler),
+    {
+        let options = CompilerOptions {
+            exports: Exports::All,
+            checked_stacks: true,
+            ..Default::default()
+        };
+        build_with(options, func, |_, _| imports! {})
+    }
+
     fn build_with_imports<T, F>(func: T, imports: F) -> Result<BasicRuntime>
     where
         T: FnOnce(&mut Compiler),
         F: FnOnce(&Store, &Module) -> ImportObject,
     {
-        let mut compiler = Compiler::default();
+        let options = CompilerOptions {
+            exports: Exports::All,
+            ..Default::default()
+        };
+        build_with(options, func, imports)
+    }
+
+    fn build_with<T, F>(options: CompilerOptions, func: T, imports: F) -> Result<BasicRuntime>
+    where
+        T: FnOnce(&mut Compiler),
+        F: FnOnce(&Store, &Module) -> ImportObject,
+    {
+        let mut compiler = Compiler::new(options);
         func(&mut compiler);
         let binary = compiler.compile()?;
         BasicRuntime::new(&binary, imports)
@@ -1736,10 +3614,129 @@ mod tests {
         assert_eq!(runtime.pop_double().unwrap(), 489);
     }
 
+    #[test]
+    fn should_do_float_math() {
+        let runtime = build(|_| {}).unwrap();
+        let binary = |a: f64, b: f64, word: &str| {
+            runtime.push_float(a).unwrap();
+            runtime.push_float(b).unwrap();
+            runtime.execute(word).unwrap();
+            runtime.pop_float().unwrap()
+        };
+        assert_eq!(binary(1.5, 2.25, "F+"), 3.75);
+        assert_eq!(binary(1.5, 2.25, "F-"), -0.75);
+        assert_eq!(binary(1.5, 2.0, "F*"), 3.0);
+        assert_eq!(binary(1.0, 4.0, "F/"), 0.25);
+        assert_eq!(binary(1.0, -4.0, "FMIN"), -4.0);
+        assert_eq!(binary(1.0, -4.0, "FMAX"), 1.0);
+        assert!(binary(1.0, 0.0, "F/").is_infinite());
+
+        let unary = |a: f64, word: &str| {
+            runtime.push_float(a).unwrap();
+            runtime.execute(word).unwrap();
+            runtime.pop_float().unwrap()
+        };
+        assert_eq!(unary(1.5, "FNEGATE"), -1.5);
+        assert_eq!(unary(-1.5, "FABS"), 1.5);
+        assert_eq!(unary(2.25, "FSQRT"), 1.5);
+        assert_eq!(unary(-1.5, "FLOOR"), -2.0);
+        assert_eq!(unary(-1.5, "FTRUNC"), -1.0);
+        assert_eq!(unary(2.5, "FROUND"), 2.0);
+        assert_eq!(unary(3.5, "FROUND"), 4.0);
+    }
+
+    #[test]
+    fn should_compare_floats() {
+        let runtime = build(|_| {}).unwrap();
+
+        runtime.push_float(1.0).unwrap();
+        runtime.push_float(2.0).unwrap();
+        runtime.execute("F<").unwrap();
+        assert_eq!(runtime.pop().unwrap(), -1);
+
+        runtime.push_float(f64::NAN).unwrap();
+        runtime.push_float(2.0).unwrap();
+        runtime.execute("F<").unwrap();
+        assert_eq!(runtime.pop().unwrap(), 0);
+
+        runtime.push_float(-0.5).unwrap();
+        runtime.execute("F0<").unwrap();
+        assert_eq!(runtime.pop().unwrap(), -1);
+
+        runtime.push_float(-0.0).unwrap();
+        runtime.execute("F0=").unwrap();
+        assert_eq!(runtime.pop().unwrap(), -1);
+    }
+
+    #[test]
+    fn should_convert_floats() {
+        let runtime = build(|_| {}).unwrap();
+
+        runtime.push(-7).unwrap();
+        runtime.execute("S>F").unwrap();
+        assert_eq!(runtime.pop_float().unwrap(), -7.0);
+
+        runtime.push_double(1 << 40).unwrap();
+        runtime.execute("D>F").unwrap();
+        assert_eq!(runtime.pop_float().unwrap(), (1u64 << 40) as f64);
+
+        runtime.push_float(-2.75).unwrap();
+        runtime.execute("F>S").unwrap();
+        assert_eq!(runtime.pop().unwrap(), -2);
+
+        runtime.push_float(1e12).unwrap();
+        runtime.execute("F>D").unwrap();
+        assert_eq!(runtime.pop_double().unwrap(), 1_000_000_000_000);
+
+        // what doesn't fit throws "result out of range" instead of trapping
+        for (value, word) in [
+            (1e12, "F>S"),
+            (-3e9, "F>S"),
+            (f64::NAN, "F>S"),
+            (1e20, "F>D"),
+        ] {
+            runtime.push_float(value).unwrap();
+            assert!(runtime.execute(word).is_err());
+            assert_eq!(runtime.global("error").unwrap(), -11);
+        }
+        runtime.push_float(-2147483648.5).unwrap();
+        runtime.execute("F>S").unwrap();
+        assert_eq!(runtime.pop().unwrap(), i32::MIN);
+    }
+
+    #[test]
+    fn should_manipulate_float_stack() {
+        let runtime = build(|compiler| {
+            compiler.define_forth_word("STASH", "( f-addr -- ) dup f! f@");
+        })
+        .unwrap();
+        for value in [1.0, 2.0, 3.0] {
+            runtime.push_float(value).unwrap();
+        }
+        runtime.execute("FROT").unwrap();
+        runtime.execute("FOVER").unwrap();
+        runtime.execute("FDEPTH").unwrap();
+        assert_eq!(runtime.pop().unwrap(), 4);
+        runtime.execute("FSWAP").unwrap();
+        runtime.execute("FDUP").unwrap();
+        runtime.execute("FDROP").unwrap();
+        assert_eq!(runtime.pop_float().unwrap(), 1.0);
+        assert_eq!(runtime.pop_float().unwrap(), 3.0);
+        assert_eq!(runtime.pop_float().unwrap(), 3.0);
+        assert_eq!(runtime.pop_float().unwrap(), 2.0);
+
+        runtime.push_float(0.1).unwrap();
+        runtime.execute("HEAP-BASE").unwrap();
+        runtime.execute("STASH").unwrap();
+        assert_eq!(runtime.pop_float().unwrap(), 0.1);
+        runtime.execute("FDEPTH").unwrap();
+        assert_eq!(runtime.pop().unwrap(), 0);
+    }
+
     #[test]
     fn should_support_colon_words() {
         let runtime = build(|compiler| {
-            compiler.define_colon_word("TEST", vec![Lit(2), Lit(3), XT("+")]);
+            compiler.define_colon_word("TEST", "( -- n )", vec![Lit(2), Lit(3), XT("+")]);
         })
         .unwrap();
         runtime.execute("TEST").unwrap();
@@ -1752,6 +3749,7 @@ mod tests {
             compiler.define_variable_word("TESTVAR", 0);
             compiler.define_colon_word(
                 "TEST",
+                "( -- n )",
                 vec![Lit(1), XT("TESTVAR"), XT("!"), XT("TESTVAR"), XT("@")],
             );
         })
@@ -1766,6 +3764,7 @@ mod tests {
             compiler.define_variable_word("TESTVAR", 6);
             compiler.define_colon_word(
                 "TEST",
+                "( -- n )",
                 vec![Lit(7), XT("TESTVAR"), XT("+!"), XT("TESTVAR"), XT("@")],
             );
         })
@@ -1915,7 +3914,7 @@ mod tests {
     #[test]
     fn should_support_literals() {
         let runtime = build(|compiler| {
-            compiler.define_colon_word("THREE", vec![Lit(3)]);
+            compiler.define_colon_word("THREE", "( -- n )", vec![Lit(3)]);
         })
         .unwrap();
 
@@ -1926,7 +3925,11 @@ mod tests {
     #[test]
     fn should_support_string_literals() {
         let runtime = build(|compiler| {
-            compiler.define_colon_word("SOME-WORD", vec![StringLit("Hello world!".to_owned())]);
+            compiler.define_colon_word(
+                "SOME-WORD",
+                "( -- c-addr u )",
+                vec![StringLit("Hello world!".to_owned())],
+            );
         })
         .unwrap();
 
@@ -1939,6 +3942,7 @@ mod tests {
         let runtime = build(|compiler| {
             compiler.define_colon_word(
                 "TEST",
+                "( -- n )",
                 vec![Lit(3), XT("DUP"), XT("DUP"), XT("+"), XT("SWAP"), XT("/")],
             );
         })
@@ -1950,8 +3954,8 @@ mod tests {
     #[test]
     fn should_support_nested_colon_calls() {
         let runtime = build(|compiler| {
-            compiler.define_colon_word("SQUARE", vec![XT("DUP"), XT("*")]);
-            compiler.define_colon_word("TEST", vec![Lit(3), XT("SQUARE")]);
+            compiler.define_colon_word("SQUARE", "( n1 -- n2 )", vec![XT("DUP"), XT("*")]);
+            compiler.define_colon_word("TEST", "( -- n )", vec![Lit(3), XT("SQUARE")]);
         })
         .unwrap();
         runtime.execute("TEST").unwrap();
@@ -1962,11 +3966,12 @@ mod tests {
     fn should_support_branching() {
         let runtime = build(|compiler| {
             #[rustfmt::skip]
-            compiler.define_colon_word("UPCHAR", vec![
+            compiler.define_colon_word("UPCHAR", "( c -- c )", vec![
                 XT("DUP"), XT("DUP"),
                 Lit(97), XT(">="), XT("SWAP"), Lit(122), XT("<="), XT("AND"),
-                QBranch(12), // Lit(32) is 8 bytes, XT("-") is 4
+                If,
                 Lit(32), XT("-"),
+                Then,
             ]);
         })
         .unwrap();
@@ -1980,6 +3985,599 @@ mod tests {
         assert_eq!(runtime.pop().unwrap(), 'B' as i32);
     }
 
+    #[test]
+    fn should_support_labels() {
+        let runtime = build(|compiler| {
+            #[rustfmt::skip]
+            compiler.define_colon_word("COUNTDOWN", "( n -- sum )", vec![
+                Lit(0), XT("SWAP"), // ( sum n )
+                Label("loop"),
+                XT("DUP"), QBranch("done"),
+                XT("DUP"), XT("ROT"), XT("+"), XT("SWAP"), XT("1-"),
+                Branch("loop"),
+                Label("done"),
+                XT("DROP"),
+            ]);
+        })
+        .unwrap();
+
+        runtime.push(4).unwrap();
+        runtime.execute("COUNTDOWN").unwrap();
+        assert_eq!(runtime.pop().unwrap(), 10);
+    }
+
+    #[test]
+    fn should_support_loops() {
+        let runtime = build(|compiler| {
+            #[rustfmt::skip]
+            compiler.define_colon_word("FACTORIAL", "( n -- product )", vec![
+                Lit(1), XT("SWAP"), // ( product n )
+                Begin,
+                    XT("DUP"),
+                While,
+                    XT("DUP"), XT("ROT"), XT("*"), XT("SWAP"), XT("1-"),
+                Repeat,
+                XT("DROP"),
+            ]);
+            #[rustfmt::skip]
+            compiler.define_colon_word("HALVINGS", "( n -- count )", vec![
+                Lit(0), XT("SWAP"), // ( count n )
+                Begin,
+                    Lit(2), XT("/"), XT("SWAP"), XT("1+"), XT("SWAP"),
+                    XT("DUP"), XT("=0"),
+                Until,
+                XT("DROP"),
+            ]);
+        })
+        .unwrap();
+
+        runtime.push(5).unwrap();
+        runtime.execute("FACTORIAL").unwrap();
+        assert_eq!(runtime.pop().unwrap(), 120);
+
+        runtime.push(8).unwrap();
+        runtime.execute("HALVINGS").unwrap();
+        assert_eq!(runtime.pop().unwrap(), 4);
+    }
+
+    #[test]
+    fn should_support_forth_source() {
+        let runtime = build(|compiler| {
+            compiler.define_forth_word(
+                "CLAMP-DIGIT",
+                r#"( c -- n ) \ turn a character into a digit, or -1
+                dup '0' < over '9' > or if
+                    drop -1 exit
+                then
+                $30 -
+                "#,
+            );
+            compiler.define_forth_word("GREETING", r#"( -- u ) s" Hello!" nip"#);
+            compiler.define_forth_word("ANSWER", "( -- n ) \\\n 42");
+        })
+        .unwrap();
+
+        runtime.push('7' as i32).unwrap();
+        runtime.execute("CLAMP-DIGIT").unwrap();
+        assert_eq!(runtime.pop().unwrap(), 7);
+
+        runtime.push('x' as i32).unwrap();
+        runtime.execute("CLAMP-DIGIT").unwrap();
+        assert_eq!(runtime.pop().unwrap(), -1);
+
+        runtime.execute("GREETING").unwrap();
+        assert_eq!(runtime.pop().unwrap(), 6);
+
+        runtime.execute("ANSWER").unwrap();
+        assert_eq!(runtime.pop().unwrap(), 42);
+    }
+
+    #[test]
+    fn should_support_forward_references() {
+        let runtime = build(|compiler| {
+            compiler.define_forth_word("QUARTIC", "( n1 -- n2 ) square square");
+            compiler.define_forth_word("SQUARE", "( n1 -- n2 ) dup *");
+        })
+        .unwrap();
+
+        runtime.push(3).unwrap();
+        runtime.execute("QUARTIC").unwrap();
+        assert_eq!(runtime.pop().unwrap(), 81);
+    }
+
+    #[test]
+    fn should_report_unknown_words() {
+        let error = build(|compiler| {
+            compiler.define_forth_word("TYPO", "( -- x x ) 1 2 swpa");
+            compiler.define_forth_word("UNTERMINATED", r#"( -- c-addr u ) s" oops"#);
+            compiler.define_forth_word("MISSPELLED", "( x -- x x ) dpu * 3 rto");
+        })
+        .err()
+        .unwrap()
+        .to_string();
+        assert!(error.contains("In TYPO: unknown word swpa"));
+        assert!(error.contains("In UNTERMINATED: expected \" to end s\""));
+        assert!(error.contains("In MISSPELLED: unknown word dpu"));
+        assert!(error.contains("In MISSPELLED: unknown word rto"));
+    }
+
+    #[test]
+    fn should_report_bad_labels() {
+        let error = build(|compiler| {
+            compiler.define_colon_word("MISSING", "( -- )", vec![Branch("nowhere")]);
+            compiler.define_colon_word("TWICE", "( -- )", vec![Label("here"), Label("here")]);
+            compiler.define_colon_word("UNBALANCED", "( x -- )", vec![If, Repeat]);
+        })
+        .err()
+        .unwrap()
+        .to_string();
+        assert!(error.contains("In MISSING: undefined label nowhere"));
+        assert!(error.contains("In TWICE: duplicate label here"));
+        assert!(error.contains("In UNBALANCED: REPEAT without a matching BEGIN"));
+    }
+
+    #[test]
+    fn should_check_stack_effects() {
+        let error = build(|compiler| {
+            compiler.define_forth_word("LEAKY", "( x -- ) dup if drop then");
+            compiler.define_forth_word("GREEDY", "( x -- x ) +");
+            compiler.define_forth_word("GROWING", "( -- ) begin 1 again");
+            compiler.define_forth_word("UNDECLARED", "dup");
+        })
+        .err()
+        .unwrap()
+        .to_string();
+        assert!(error
+            .contains("In LEAKY: declared ( x -- ), but the stack can be 1 deep when it returns"));
+        assert!(
+            error.contains("In GREEDY: declared ( x -- x ), but it reaches 2 deep into the stack")
+        );
+        assert!(error.contains("In GROWING: declared ( -- ), but its stack grows or shrinks"));
+        assert!(error.contains("In UNDECLARED: expected a stack effect"));
+    }
+
+    #[test]
+    fn should_follow_flags_through_stack_effects() {
+        let runtime = build(|compiler| {
+            compiler.define_forth_word("SQUARE-OR-ZERO", "( n1 -- n2 ) ?dup if dup * else 0 then");
+            compiler.define_forth_word(
+                "?ODD",
+                "( n -- n -1 | 0 ) dup 1 and if true else drop false then",
+            );
+            compiler.define_forth_word("ODD-OR-ZERO", "( n1 -- n2 ) ?odd =0 if 0 then");
+        })
+        .unwrap();
+
+        runtime.push(3).unwrap();
+        runtime.execute("SQUARE-OR-ZERO").unwrap();
+        assert_eq!(runtime.pop().unwrap(), 9);
+
+        runtime.push(0).unwrap();
+        runtime.execute("SQUARE-OR-ZERO").unwrap();
+        assert_eq!(runtime.pop().unwrap(), 0);
+
+        runtime.push(4).unwrap();
+        runtime.execute("ODD-OR-ZERO").unwrap();
+        assert_eq!(runtime.pop().unwrap(), 0);
+
+        runtime.push(5).unwrap();
+        runtime.execute("ODD-OR-ZERO").unwrap();
+        assert_eq!(runtime.pop().unwrap(), 5);
+    }
+
+    #[test]
+    fn should_detect_stack_underflow() {
+        let runtime = build_checked(|_| {}).unwrap();
+
+        assert!(runtime.execute("DROP").is_err());
+        assert_eq!(runtime.global("error").unwrap(), -4);
+
+        runtime.push(1).unwrap();
+        assert!(runtime.execute("+").is_err());
+        assert!(runtime.pop_double().is_err());
+        assert_eq!(runtime.pop().unwrap(), 1);
+        assert!(runtime.pop().is_err());
+    }
+
+    #[test]
+    fn should_detect_stack_overflow() {
+        let runtime = build_checked(|compiler| {
+            compiler.define_forth_word("FLOOD", "( -- i*x ) begin 1 again");
+        })
+        .unwrap();
+        assert!(runtime.execute("FLOOD").is_err());
+        assert_eq!(runtime.global("error").unwrap(), -3);
+
+        let runtime = build_checked(|compiler| {
+            compiler.define_forth_word("FOREVER", "( -- ) forever");
+        })
+        .unwrap();
+        assert!(runtime.execute("FOREVER").is_err());
+        assert_eq!(runtime.global("error").unwrap(), -5);
+    }
+
+    #[test]
+    fn should_detect_return_stack_underflow() {
+        let runtime = build_checked(|_| {}).unwrap();
+
+        assert!(runtime.execute("R>").is_err());
+        assert_eq!(runtime.global("error").unwrap(), -6);
+    }
+
+    #[test]
+    fn should_detect_float_stack_underflow() {
+        let runtime = build_checked(|_| {}).unwrap();
+
+        runtime.push_float(1.0).unwrap();
+        assert!(runtime.execute("F+").is_err());
+        assert_eq!(runtime.global("error").unwrap(), -45);
+    }
+
+    #[test]
+    fn should_follow_memory_layout() {
+        let layout = MemoryLayout {
+            dictionary_capacity: 0x10000,
+            param_stack_depth: 4,
+            float_stack_depth: 2,
+            ..Default::default()
+        };
+        let options = CompilerOptions {
+            exports: Exports::All,
+            checked_stacks: true,
+            layout,
+            ..Default::default()
+        };
+        let runtime = build_with(options, |_| {}, |_, _| imports! {}).unwrap();
+
+        runtime.execute("DICT-CAPACITY").unwrap();
+        assert_eq!(runtime.pop().unwrap(), 0x10000);
+        runtime.execute("RETURN-STACK-BASE").unwrap();
+        assert_eq!(runtime.pop().unwrap(), layout.return_stack_base());
+        // the heap starts after the backtrace and source map
+        runtime.execute("HEAP-BASE").unwrap();
+        assert_eq!(runtime.pop().unwrap(), layout.heap_base());
+        assert_eq!(
+            layout.heap_base(),
+            layout.float_stack_base() + 64 * 4 + 2048 * 16
+        );
+        assert_eq!(layout.param_stack_base(), 0x1000 + 0x10000 + 16);
+        assert_eq!(runtime.global("sp").unwrap(), layout.param_stack_base());
+        assert_eq!(layout.float_stack_base(), layout.return_stack_base() + 16);
+        assert_eq!(runtime.global("fp").unwrap(), layout.float_stack_base());
+
+        for value in 0..4 {
+            runtime.push(value).unwrap();
+        }
+        assert!(runtime.execute("DUP").is_err());
+        assert_eq!(runtime.global("error").unwrap(), -3);
+
+        for _ in 0..4 {
+            runtime.pop().unwrap();
+        }
+        for value in 0..2 {
+            runtime.push_float(value as f64).unwrap();
+        }
+        assert!(runtime.execute("FDUP").is_err());
+        assert_eq!(runtime.global("error").unwrap(), -44);
+    }
+
+    #[test]
+    fn should_reject_bad_memory_layouts() {
+        let options = CompilerOptions {
+            layout: MemoryLayout {
+                heap_base: Some(0x2000),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let error = Compiler::new(options).compile().err().unwrap();
+        assert!(error.to_string().contains("inside the stacks"));
+    }
+
+    #[test]
+    fn should_execute_more_than_256_callables() {
+        let runtime = build(|compiler| {
+            for index in 0..300 {
+                let name = format!("PUSH{}", index);
+                compiler.define_native_word(
+                    &name,
+                    "( -- n )",
+                    vec![],
+                    vec![I32Const(index), Call(compiler.push)],
+                );
+            }
+            compiler.define_forth_word("TEST", "( -- n ) lit push299 execute");
+        })
+        .unwrap();
+        runtime.execute("TEST").unwrap();
+        assert_eq!(runtime.pop().unwrap(), 299);
+    }
+
+    #[test]
+    fn should_catch_and_throw() {
+        let runtime = build(|compiler| {
+            compiler.define_forth_word("THROWER", "( n -- n 1 2 ) dup 1 2 rot throw");
+            compiler.define_forth_word("TRY", "( n -- i*x n ) >r 5 r> lit thrower catch");
+            compiler.define_forth_word("NESTED", "( -- i*x n ) lit try catch");
+            compiler.define_forth_word("FTHROWER", "( -- ) ( F: -- r ) 1 s>f -1 throw");
+            compiler.define_forth_word("FTRY", "( -- n ) lit fthrower catch");
+        })
+        .unwrap();
+
+        // nothing thrown
+        runtime.push(0).unwrap();
+        runtime.execute("TRY").unwrap();
+        assert_eq!(runtime.pop().unwrap(), 0);
+        assert_eq!(runtime.pop().unwrap(), 2);
+        assert_eq!(runtime.pop().unwrap(), 1);
+        assert_eq!(runtime.pop().unwrap(), 0);
+        assert_eq!(runtime.pop().unwrap(), 5);
+
+        // the stack is as deep as it was when CATCH was called
+        runtime.push(-7).unwrap();
+        runtime.execute("TRY").unwrap();
+        assert_eq!(runtime.pop().unwrap(), -7);
+        assert_eq!(runtime.pop().unwrap(), -7);
+        assert_eq!(runtime.pop().unwrap(), 5);
+        runtime.execute("DEPTH").unwrap();
+        assert_eq!(runtime.pop().unwrap(), 0);
+
+        // the innermost CATCH gets it
+        runtime.push(-8).unwrap();
+        runtime.execute("NESTED").unwrap();
+        assert_eq!(runtime.pop().unwrap(), 0);
+        assert_eq!(runtime.pop().unwrap(), -8);
+        assert_eq!(runtime.pop().unwrap(), -8);
+        assert_eq!(runtime.pop().unwrap(), 5);
+
+        // and so is the float stack
+        runtime.push_float(0.5).unwrap();
+        runtime.execute("FTRY").unwrap();
+        assert_eq!(runtime.pop().unwrap(), -1);
+        runtime.execute("FDEPTH").unwrap();
+        assert_eq!(runtime.pop().unwrap(), 1);
+        assert_eq!(runtime.pop_float().unwrap(), 0.5);
+
+        // without a CATCH, THROW traps
+        runtime.push(-9).unwrap();
+        assert!(runtime.execute("THROW").is_err());
+        assert_eq!(runtime.global("error").unwrap(), -9);
+    }
+
+    #[test]
+    fn should_support_subroutine_threading() {
+        check_subroutine_threading(false);
+    }
+
+    #[test]
+    fn should_inline_primitives() {
+        check_subroutine_threading(true);
+    }
+
+    fn check_subroutine_threading(inline_primitives: bool) {
+        let options = CompilerOptions {
+            exports: Exports::All,
+            threading: Threading::Subroutine,
+            inline_primitives,
+            ..Default::default()
+        };
+        let runtime = build_with(
+            options,
+            |compiler| {
+                compiler.define_forth_word(
+                    "SUM",
+                    "( n1 -- n2 ) 0 swap begin dup while tuck + swap 1- repeat drop",
+                );
+                compiler
+                    .define_forth_word("ARITHMETIC", "( n1 -- n2 ) 1 2 3 + + swap - abs negate");
+                compiler.define_forth_word(
+                    "SIGN",
+                    "( n1 -- n2 ) dup <0 if drop -1 else >0 if 1 else 0 then then",
+                );
+                compiler.define_forth_word(
+                    "COUNTDOWN",
+                    "( n1 -- n2 ) label top 1- dup >0 ?branch done branch top label done",
+                );
+                // jumps into the middle of a loop can't be structured, so this stays threaded
+                compiler.define_forth_word(
+                    "JUMPS",
+                    "( n1 -- n2 ) branch two label one 1+ exit label two dup >0 ?branch one",
+                );
+                compiler.define_forth_word("GREETING", "( -- c-addr u ) s\" hello\"");
+                compiler.define_forth_word("THROWER", "( n -- n 1 2 ) dup 1 2 rot throw");
+                compiler.define_forth_word("TRY", "( n -- i*x n ) lit thrower catch");
+                compiler.define_forth_word("THREADED?", "( xt -- ? ) @ (docol) =");
+                compiler.define_forth_word(
+                    "THREADED-WORDS",
+                    "( -- ? ? ? ) lit sum threaded? lit jumps threaded? lit catch threaded?",
+                );
+            },
+            |_, _| imports! {},
+        )
+        .unwrap();
+
+        runtime.push(4).unwrap();
+        runtime.execute("SUM").unwrap();
+        assert_eq!(runtime.pop().unwrap(), 10);
+
+        runtime.push(10).unwrap();
+        runtime.execute("ARITHMETIC").unwrap();
+        assert_eq!(runtime.pop().unwrap(), -4);
+
+        for (n, sign) in [(-5, -1), (0, 0), (7, 1)] {
+            runtime.push(n).unwrap();
+            runtime.execute("SIGN").unwrap();
+            assert_eq!(runtime.pop().unwrap(), sign);
+        }
+
+        runtime.push(3).unwrap();
+        runtime.execute("COUNTDOWN").unwrap();
+        assert_eq!(runtime.pop().unwrap(), 0);
+
+        runtime.push(3).unwrap();
+        runtime.execute("JUMPS").unwrap();
+        assert_eq!(runtime.pop().unwrap(), 3);
+        runtime.push(-3).unwrap();
+        runtime.execute("JUMPS").unwrap();
+        assert_eq!(runtime.pop().unwrap(), -2);
+
+        runtime.execute("GREETING").unwrap();
+        assert_eq!(runtime.pop_string().unwrap(), "hello");
+
+        // CATCH and THROW stay threaded, and unwind through words which aren't
+        runtime.push(-7).unwrap();
+        runtime.execute("TRY").unwrap();
+        assert_eq!(runtime.pop().unwrap(), -7);
+        assert_eq!(runtime.pop().unwrap(), -7);
+        runtime.execute("DEPTH").unwrap();
+        assert_eq!(runtime.pop().unwrap(), 0);
+
+        runtime.execute("THREADED-WORDS").unwrap();
+        assert_eq!(runtime.pop().unwrap(), -1);
+        assert_eq!(runtime.pop().unwrap(), -1);
+        assert_eq!(runtime.pop().unwrap(), 0);
+    }
+
+    #[test]
+    fn should_name_functions() {
+        let mut compiler = Compiler::new(CompilerOptions {
+            exports: Exports::All,
+            threading: Threading::Subroutine,
+            ..Default::default()
+        });
+        compiler.define_forth_word("SQUARE", "( n1 -- n2 ) dup *");
+        compiler.define_imported_word("TICKS", "test", "ticks", vec![], vec![ParamType::I64]);
+        compiler.define_entry_point("main", "SQUARE");
+        let binary = compiler.compile().unwrap();
+        let module = parity_wasm::elements::Module::from_bytes(&binary)
+            .unwrap()
+            .parse_names()
+            .unwrap();
+        let names = module.names_section().unwrap();
+
+        let functions = names.functions().unwrap().names();
+        let find = |name: &str| {
+            functions
+                .iter()
+                .find(|(_, n)| *n == name)
+                .map(|(index, _)| index)
+                .unwrap_or_else(|| panic!("{} is not named", name))
+        };
+        find("forth:UM/MOD");
+        find("callable:DOCOL");
+        find("import:test.ticks");
+        find("forth:TICKS");
+        find("entry:main");
+
+        let locals = names.locals().unwrap().local_names();
+        let square = locals.get(find("forth:SQUARE")).unwrap();
+        assert_eq!(square.get(2).unwrap(), "entry-rp");
+        let execute = locals.get(find("execute")).unwrap();
+        assert_eq!(execute.get(0).unwrap(), "xt");
+    }
+
+    #[test]
+    fn should_blame_invalid_code_on_its_word() {
+        let mut compiler = Compiler::new(CompilerOptions {
+            exports: Exports::All,
+            ..Default::default()
+        });
+        // natives only have two params, and this one has no locals
+        compiler.define_native_word("BROKEN", "( -- )", vec![], vec![GetLocal(5), Drop]);
+        let error = compiler.compile().unwrap_err();
+        let error = error.downcast_ref::<ValidationError>().unwrap();
+        assert_eq!(error.function.as_deref(), Some("forth:BROKEN"));
+        assert!(error.message.contains("local"), "{}", error.message);
+    }
+
+    #[test]
+    fn should_list_the_dictionary() {
+        let mut compiler = Compiler::new(CompilerOptions {
+            exports: Exports::All,
+            ..Default::default()
+        });
+        compiler.define_forth_word("SQUARE", "( n1 -- n2 ) dup *");
+        let square = compiler.get_execution_token("SQUARE");
+        let binary = compiler.compile().unwrap();
+        let dictionary = read_dictionary(&binary).unwrap();
+
+        let find = |name: &str| dictionary.iter().find(|e| e.name == name).unwrap();
+        assert_eq!(find("SQUARE").xt, square);
+        assert_eq!(find("SQUARE").kind, WordKind::Colon);
+        assert_eq!(find("DUP").kind, WordKind::Native);
+        assert_eq!(find("HEAP-BASE").kind, WordKind::Constant);
+        assert_eq!(find("LATEST").kind, WordKind::Variable);
+        assert!(dictionary.windows(2).all(|pair| pair[0].xt < pair[1].xt));
+        assert!(dictionary.iter().all(|e| !e.immediate && !e.hidden));
+    }
+
+    #[test]
+    fn should_cache_top_of_stack() {
+        for stack_model in [StackModel::Memory, StackModel::CachedTop] {
+            let options = CompilerOptions {
+                exports: Exports::All,
+                checked_stacks: true,
+                stack_model,
+                ..Default::default()
+            };
+            let runtime = build_with(
+                options,
+                |compiler| {
+                    compiler.define_forth_word(
+                        "SHUFFLE",
+                        "( x1 x2 x3 -- x2 x3 x3 x1 x3 x1 x3 ) rot 2dup swap tuck nip -rot over 3 pick",
+                    );
+                    compiler.define_forth_word("DOUBLES", "( d1 d2 -- d2 d1 d2 ) 2swap 2over");
+                    compiler.define_forth_word(
+                        "POKE",
+                        "( x -- n c ) heap-base tuck ! dup @ 3 - over 7 swap c! swap c@",
+                    );
+                    compiler.define_forth_word("FLOOD", "( -- i*x ) begin 1 again");
+                },
+                |_, _| imports! {},
+            )
+            .unwrap();
+
+            runtime.push(1).unwrap();
+            runtime.push(2).unwrap();
+            runtime.push(3).unwrap();
+            runtime.execute("SHUFFLE").unwrap();
+            runtime.execute("DEPTH").unwrap();
+            assert_eq!(runtime.pop().unwrap(), 7);
+            for expected in [3, 1, 3, 1, 3, 3, 2] {
+                assert_eq!(runtime.pop().unwrap(), expected);
+            }
+
+            runtime.push_double(1 << 40).unwrap();
+            runtime.push_double(-5).unwrap();
+            runtime.execute("DOUBLES").unwrap();
+            assert_eq!(runtime.pop_double().unwrap(), -5);
+            assert_eq!(runtime.pop_double().unwrap(), 1 << 40);
+            assert_eq!(runtime.pop_double().unwrap(), -5);
+
+            runtime.push(4).unwrap();
+            runtime.push(10).unwrap();
+            runtime.execute("POKE").unwrap();
+            assert_eq!(runtime.pop().unwrap(), 7);
+            assert_eq!(runtime.pop().unwrap(), 7);
+            assert_eq!(runtime.pop().unwrap(), 4);
+
+            // an empty stack still underflows and overflows where it used to
+            assert!(runtime.execute("DROP").is_err());
+            assert_eq!(runtime.global("error").unwrap(), -4);
+            for word in ["+", "@", "!"] {
+                assert!(runtime.execute(word).is_err());
+                assert_eq!(runtime.global("error").unwrap(), -4);
+            }
+            assert!(runtime.execute("FLOOD").is_err());
+            assert_eq!(runtime.global("error").unwrap(), -3);
+            assert_eq!(
+                runtime.global("tos").is_ok(),
+                stack_model == StackModel::CachedTop
+            );
+        }
+    }
+
     #[test]
     fn should_support_imports() {
         let runtime = build_with_imports(
@@ -1997,7 +4595,7 @@ mod tests {
                         "trim" => Function::new_native(store, |a: i32, b: i32| {
                             (a + 4, b - 8)
                         }),
-                        "has64" => Function::new_native(store, |a: i64| { assert_eq!(a, 13); 64 as i64 }),
+                        "has64" => Function::new_native(store, |a: i64| { assert_eq!(a, 13); 64_i64 }),
                     }
                 }
             },
@@ -0,0 +1,258 @@
+use super::layout::CODE_INDEX_BITS;
+use anyhow::{anyhow, bail, Result};
+use parity_wasm::elements::Module;
+use std::{collections::HashMap, convert::TryInto};
+
+/// The custom section which lists the words in a module's dictionary, oldest first.
+///
+/// It starts with a version byte (currently 1) and a little-endian u32 count of words.
+/// Each word is its little-endian u32 XT, a [`WordKind`] byte, a flags byte
+/// (1 for immediate, 2 for hidden, 4 for trampolined), a length byte, and then its name.
+pub const DICTIONARY_SECTION: &str = "forsm.dictionary";
+
+/// The custom section which says where in the bootstrapper's Rust source each of its words was defined.
+///
+/// It starts with a version byte (currently 1), a little-endian u32 count of files,
+/// and each file's name as a little-endian u16 length and then the name itself.
+/// After them is a u32 count of words, and each word is three u32s: its XT, the index of its file, and its line.
+pub const ORIGINS_SECTION: &str = "forsm.origins";
+
+const VERSION: u8 = 1;
+const IMMEDIATE: u8 = 1;
+const HIDDEN: u8 = 2;
+const TRAMPOLINED: u8 = 4;
+
+/// What a word's code field does when it's executed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WordKind {
+    Native = 0,
+    Colon = 1,
+    Constant = 2,
+    Variable = 3,
+    /// A word built by CREATE and DOES>
+    Does = 4,
+}
+
+/// One word, as listed in the [`DICTIONARY_SECTION`] of a module
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DictionaryEntry {
+    pub name: String,
+    pub xt: i32,
+    pub kind: WordKind,
+    pub immediate: bool,
+    pub hidden: bool,
+    /// Trampolined words call a word the host defined, from before the prelude redefined it
+    pub trampolined: bool,
+}
+
+/// Read the dictionary section of a compiled module, without running it
+pub fn read_dictionary(binary: &[u8]) -> Result<Vec<DictionaryEntry>> {
+    let module = Module::from_bytes(binary)?;
+    let section = module
+        .custom_sections()
+        .find(|section| section.name() == DICTIONARY_SECTION)
+        .ok_or_else(|| anyhow!("Module has no {} section", DICTIONARY_SECTION))?;
+    decode_dictionary(section.payload())
+}
+
+pub(crate) fn encode_dictionary(entries: &[DictionaryEntry]) -> Vec<u8> {
+    let mut payload = vec![VERSION];
+    payload.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+    for entry in entries {
+        let mut flags = 0;
+        for (set, flag) in [
+            (entry.immediate, IMMEDIATE),
+            (entry.hidden, HIDDEN),
+            (entry.trampolined, TRAMPOLINED),
+        ] {
+            if set {
+                flags |= flag;
+            }
+        }
+        payload.extend_from_slice(&entry.xt.to_le_bytes());
+        payload.push(entry.kind as u8);
+        payload.push(flags);
+        payload.push(entry.name.len() as u8);
+        payload.extend_from_slice(entry.name.as_bytes());
+    }
+    payload
+}
+
+// Where a word was defined, as its XT, file and line
+pub(crate) type Origin = (i32, String, u32);
+
+pub(crate) fn encode_origins(origins: &[(i32, &str, u32)]) -> Vec<u8> {
+    let mut files: Vec<&str> = origins.iter().map(|(_, file, _)| *file).collect();
+    files.sort_unstable();
+    files.dedup();
+    let mut payload = vec![VERSION];
+    payload.extend_from_slice(&(files.len() as u32).to_le_bytes());
+    for file in files.iter() {
+        payload.extend_from_slice(&(file.len() as u16).to_le_bytes());
+        payload.extend_from_slice(file.as_bytes());
+    }
+    payload.extend_from_slice(&(origins.len() as u32).to_le_bytes());
+    for (xt, file, line) in origins {
+        let file = files.binary_search(file).unwrap() as u32;
+        for value in [*xt as u32, file, *line] {
+            payload.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    payload
+}
+
+/// Read the origins section of a compiled module, if it has one
+pub(crate) fn read_origins(binary: &[u8]) -> Result<Vec<Origin>> {
+    let module = Module::from_bytes(binary)?;
+    let payload = match module
+        .custom_sections()
+        .find(|section| section.name() == ORIGINS_SECTION)
+    {
+        Some(section) => section.payload(),
+        None => return Ok(vec![]),
+    };
+    let mut reader = Reader { payload, offset: 0 };
+    let version = reader.bytes(1)?[0];
+    if version != VERSION {
+        bail!("Unsupported origins version {}", version);
+    }
+    let mut files = vec![];
+    for _ in 0..reader.u32()? {
+        let len = u16::from_le_bytes(reader.bytes(2)?.try_into()?) as usize;
+        files.push(String::from_utf8(reader.bytes(len)?.to_vec())?);
+    }
+    let mut origins = vec![];
+    for _ in 0..reader.u32()? {
+        let xt = reader.u32()? as i32;
+        let file = files
+            .get(reader.u32()? as usize)
+            .ok_or_else(|| anyhow!("Origin of word {} is in an unknown file", xt))?;
+        origins.push((xt, file.clone(), reader.u32()?));
+    }
+    Ok(origins)
+}
+
+/* Walk the dictionary in a memory image, from the newest word back to the oldest.
+ * Words which the compiler didn't define are told apart by their code fields,
+ * using the kinds of the words which share those code fields with them. */
+pub(crate) fn walk_dictionary(
+    memory: &[u8],
+    compiled: &[DictionaryEntry],
+) -> Result<Vec<DictionaryEntry>> {
+    let cell = |address: i32| -> Result<i32> {
+        let bytes = memory
+            .get(address as usize..address as usize + 4)
+            .ok_or_else(|| anyhow!("Dictionary points outside of memory at {}", address))?;
+        Ok(i32::from_le_bytes(bytes.try_into()?))
+    };
+    let code_index = |xt: i32| -> Result<i32> { Ok(cell(xt)? & ((1 << CODE_INDEX_BITS) - 1)) };
+    let find = |name: &str| -> Result<&DictionaryEntry> {
+        compiled
+            .iter()
+            .find(|entry| entry.name == name)
+            .ok_or_else(|| anyhow!("Dictionary has no {}", name))
+    };
+
+    let mut kinds = HashMap::new();
+    for entry in compiled {
+        kinds.insert(code_index(entry.xt)?, entry.kind);
+    }
+    for (callable, kind) in [
+        ("(DOCOL)", WordKind::Colon),
+        ("(DOCON)", WordKind::Constant),
+        ("(DOVAR)", WordKind::Variable),
+        ("(DODOES)", WordKind::Does),
+    ] {
+        kinds.insert(cell(find(callable)?.xt + 4)?, kind);
+    }
+
+    let mut entries = vec![];
+    let mut nt = cell(find("LATEST")?.xt + 4)?;
+    while nt != 0 {
+        let header = *memory
+            .get(nt as usize)
+            .ok_or_else(|| anyhow!("Dictionary points outside of memory at {}", nt))?;
+        let len = (header & 31) as usize;
+        let start = nt as usize + 1;
+        let mut name = memory
+            .get(start..start + len)
+            .ok_or_else(|| anyhow!("Dictionary points outside of memory at {}", nt))?
+            .to_vec();
+        // the hidden bit is the high bit of the name's first character
+        let hidden = name.first().is_some_and(|c| c & 0x80 != 0);
+        if let Some(c) = name.first_mut() {
+            *c &= 0x7f;
+        }
+        // the link to the previous word is in the next aligned cell after the name
+        let backword = (nt + 1 + len as i32 + 3) & -4;
+        let xt = backword + 4;
+        entries.push(DictionaryEntry {
+            name: String::from_utf8(name)?,
+            xt,
+            kind: kinds
+                .get(&code_index(xt)?)
+                .copied()
+                .unwrap_or(WordKind::Native),
+            // the same flags as NAME>IMMEDIATE? and NAME>TRAMPOLINED? read
+            immediate: header & 128 != 0,
+            hidden,
+            trampolined: header & 64 != 0,
+        });
+        nt = cell(backword)?;
+    }
+    entries.reverse();
+    Ok(entries)
+}
+
+fn decode_dictionary(payload: &[u8]) -> Result<Vec<DictionaryEntry>> {
+    let mut reader = Reader { payload, offset: 0 };
+    let version = reader.bytes(1)?[0];
+    if version != VERSION {
+        bail!("Unsupported dictionary version {}", version);
+    }
+    let count = reader.u32()?;
+    let mut entries = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let xt = reader.u32()? as i32;
+        let kind = match reader.bytes(1)?[0] {
+            0 => WordKind::Native,
+            1 => WordKind::Colon,
+            2 => WordKind::Constant,
+            3 => WordKind::Variable,
+            4 => WordKind::Does,
+            kind => bail!("Unknown word kind {}", kind),
+        };
+        let flags = reader.bytes(1)?[0];
+        let len = reader.bytes(1)?[0] as usize;
+        let name = String::from_utf8(reader.bytes(len)?.to_vec())?;
+        entries.push(DictionaryEntry {
+            name,
+            xt,
+            kind,
+            immediate: flags & IMMEDIATE != 0,
+            hidden: flags & HIDDEN != 0,
+            trampolined: flags & TRAMPOLINED != 0,
+        });
+    }
+    Ok(entries)
+}
+
+struct Reader<'a> {
+    payload: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .payload
+            .get(self.offset..self.offset + len)
+            .ok_or_else(|| anyhow!("Section ends early"))?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into()?))
+    }
+}
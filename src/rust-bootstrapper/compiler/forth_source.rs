@@ -0,0 +1,122 @@
+use super::ColonValue::{self, *};
+use anyhow::{anyhow, bail, Result};
+
+/* Parses Forth-like source into the body of a colon word.
+ * Words and control flow (IF, BEGIN, etc) are case-insensitive, like the interpreter itself.
+ * Besides the usual comments, literals and S" strings, there are three extras:
+ * LABEL name marks a spot in the word, BRANCH name and ?BRANCH name jump to it. */
+pub fn parse(source: &'static str) -> Result<Vec<ColonValue>> {
+    let mut tokens = Tokens { source, offset: 0 };
+    let mut values = vec![];
+    while let Some(token) = tokens.next_word() {
+        let value = match token.to_ascii_uppercase().as_str() {
+            "\\" => {
+                tokens.parse_until('\n');
+                continue;
+            }
+            "(" => {
+                tokens.expect_until(')', token)?;
+                continue;
+            }
+            "S\"" => StringLit(tokens.expect_until('"', token)?.to_owned()),
+            "IF" => If,
+            "ELSE" => Else,
+            "THEN" => Then,
+            "BEGIN" => Begin,
+            "UNTIL" => Until,
+            "AGAIN" => Again,
+            "WHILE" => While,
+            "REPEAT" => Repeat,
+            "LABEL" => Label(tokens.expect_word(token)?),
+            "BRANCH" => Branch(tokens.expect_word(token)?),
+            "?BRANCH" => QBranch(tokens.expect_word(token)?),
+            _ => match parse_number(token) {
+                Some(value) => Lit(value),
+                None => XT(token),
+            },
+        };
+        values.push(value);
+    }
+    Ok(values)
+}
+
+/* Splits the stack effect comment which starts a word's source, like ( c-addr u -- n ),
+ * from the rest of the source. */
+pub fn split_effect(source: &'static str) -> Result<(&'static str, &'static str)> {
+    let start = source.trim_start();
+    let end = match start.find(')') {
+        Some(end) if start.starts_with("( ") => end,
+        _ => bail!("expected a stack effect like ( x -- x ) at the start of the word"),
+    };
+    Ok((&start[..=end], &start[end + 1..]))
+}
+
+// Numbers are decimal, $hex, or 'c'haracters
+fn parse_number(token: &str) -> Option<i32> {
+    let (negative, digits) = match token.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, token),
+    };
+    let value = if let Some(hex) = digits.strip_prefix('$') {
+        u32::from_str_radix(hex, 16).ok()? as i32
+    } else if digits.len() == 3 && digits.starts_with('\'') && digits.ends_with('\'') {
+        digits.as_bytes()[1] as i32
+    } else if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative {
+        value.wrapping_neg()
+    } else {
+        value
+    })
+}
+
+struct Tokens {
+    source: &'static str,
+    offset: usize,
+}
+
+impl Tokens {
+    // the next whitespace-delimited word, if there is one
+    fn next_word(&mut self) -> Option<&'static str> {
+        let rest = &self.source[self.offset..];
+        let start = rest.find(|c: char| !c.is_whitespace())?;
+        let len = rest[start..]
+            .find(char::is_whitespace)
+            .unwrap_or(rest.len() - start);
+        self.offset += start + len;
+        Some(&rest[start..start + len])
+    }
+
+    fn expect_word(&mut self, after: &str) -> Result<&'static str> {
+        self.next_word()
+            .ok_or_else(|| anyhow!("expected a name after {}", after))
+    }
+
+    // everything up to a delimiter (skipping one leading space), or None if it never appears
+    fn parse_until(&mut self, delimiter: char) -> Option<&'static str> {
+        let rest = &self.source[self.offset..];
+        // the space might be the delimiter itself, like the newline after a bare \
+        let rest = rest
+            .strip_prefix(|c: char| c.is_whitespace() && c != delimiter)
+            .unwrap_or(rest);
+        let skipped = self.source.len() - self.offset - rest.len();
+        match rest.find(delimiter) {
+            Some(len) => {
+                self.offset += skipped + len + delimiter.len_utf8();
+                Some(&rest[..len])
+            }
+            None => {
+                self.offset = self.source.len();
+                None
+            }
+        }
+    }
+
+    fn expect_until(&mut self, delimiter: char, after: &str) -> Result<&'static str> {
+        self.parse_until(delimiter)
+            .ok_or_else(|| anyhow!("expected {} to end {}", delimiter, after))
+    }
+}
@@ -0,0 +1,174 @@
+use parity_wasm::elements::{
+    Instruction::{self, *},
+    ValueType,
+};
+use std::collections::{HashMap, HashSet};
+
+// Natives longer than this are called, not inlined
+const INLINE_LIMIT: usize = 24;
+
+/* Whether a native word's body still means the same thing when it's pasted into another function.
+ * It can't return or branch out of itself, and it can't read a local before it sets one,
+ * because an inlined copy doesn't get its own arguments or zeroed locals. */
+pub fn inlinable(instructions: &[Instruction]) -> bool {
+    if instructions.len() > INLINE_LIMIT {
+        return false;
+    }
+    let mut depth = 0;
+    let mut written = HashSet::new();
+    for instruction in instructions {
+        match instruction {
+            Block(_) | Loop(_) | If(_) => depth += 1,
+            End => depth -= 1,
+            Br(target) | BrIf(target) if *target >= depth => return false,
+            BrTable(table)
+                if table
+                    .table
+                    .iter()
+                    .chain([&table.default])
+                    .any(|t| *t >= depth) =>
+            {
+                return false
+            }
+            Return => return false,
+            GetLocal(local) if !written.contains(local) => return false,
+            SetLocal(local) | TeeLocal(local) if depth == 0 => drop(written.insert(*local)),
+            _ => {}
+        }
+    }
+    true
+}
+
+/* Gives inlined natives locals in the function they're inlined into.
+ * Only one inlined body runs at a time, so they all share the same few locals. */
+pub struct Inliner {
+    first_local: u32,
+    locals: Vec<ValueType>,
+    slots: HashMap<(ValueType, usize), u32>,
+}
+
+impl Inliner {
+    pub fn new(first_local: u32) -> Self {
+        Self {
+            first_local,
+            locals: vec![],
+            slots: HashMap::new(),
+        }
+    }
+
+    // A copy of a native's body which uses this function's locals instead of its own
+    pub fn inline(
+        &mut self,
+        params: &[ValueType],
+        locals: &[ValueType],
+        body: &[Instruction],
+    ) -> Vec<Instruction> {
+        let mut counts = HashMap::new();
+        let mapping: Vec<u32> = params
+            .iter()
+            .chain(locals)
+            .map(|value_type| {
+                let count = counts.entry(*value_type).or_insert(0);
+                *count += 1;
+                self.slot(*value_type, *count - 1)
+            })
+            .collect();
+        body.iter()
+            .map(|instruction| match instruction {
+                GetLocal(local) => GetLocal(mapping[*local as usize]),
+                SetLocal(local) => SetLocal(mapping[*local as usize]),
+                TeeLocal(local) => TeeLocal(mapping[*local as usize]),
+                instruction => instruction.clone(),
+            })
+            .collect()
+    }
+
+    fn slot(&mut self, value_type: ValueType, index: usize) -> u32 {
+        let first_local = self.first_local;
+        let locals = &mut self.locals;
+        *self.slots.entry((value_type, index)).or_insert_with(|| {
+            locals.push(value_type);
+            first_local + locals.len() as u32 - 1
+        })
+    }
+
+    // Every local the inlined bodies need, after the ones the function already had
+    pub fn locals(&self) -> &[ValueType] {
+        &self.locals
+    }
+}
+
+/* Cancel out values which are pushed onto the parameter stack, only to be popped again.
+ * A push right before its pop disappears entirely. When there's code in between
+ * which doesn't touch the stack, the value waits in a local instead.
+ * Those locals start at first_local, and this returns how many it needed.
+ * The stack globals are the stack pointer, and the top of the stack if it's cached. */
+pub fn cancel_pushes(
+    instructions: &mut Vec<Instruction>,
+    push: u32,
+    pop: u32,
+    stack_globals: &[u32],
+    first_local: u32,
+) -> u32 {
+    let mut optimized = Vec::with_capacity(instructions.len());
+    // pushes which haven't been popped, with nothing since which touches the stack
+    let mut pending: Vec<usize> = vec![];
+    let mut scratch = 0;
+    for instruction in instructions.drain(..) {
+        match instruction {
+            Call(func) if func == push => {
+                pending.push(optimized.len());
+                optimized.push(instruction);
+            }
+            Call(func) if func == pop => match pending.pop() {
+                Some(index) if index == optimized.len() - 1 => drop(optimized.pop()),
+                Some(index) => {
+                    // nested pushes need their own locals
+                    let local = first_local + pending.len() as u32;
+                    scratch = scratch.max(pending.len() as u32 + 1);
+                    optimized[index] = SetLocal(local);
+                    optimized.push(GetLocal(local));
+                }
+                None => optimized.push(instruction),
+            },
+            instruction => {
+                if touches_stack(&instruction, stack_globals) {
+                    pending.clear();
+                }
+                optimized.push(instruction);
+            }
+        }
+    }
+    *instructions = optimized;
+    scratch
+}
+
+// Whether an instruction could see (or change) what's on the parameter stack,
+// or jump somewhere which does
+fn touches_stack(instruction: &Instruction, stack_globals: &[u32]) -> bool {
+    matches!(
+        instruction,
+        Call(_)
+            | CallIndirect(_, _)
+            | GrowMemory(_)
+            | Block(_)
+            | Loop(_)
+            | If(_)
+            | Else
+            | End
+            | Br(_)
+            | BrIf(_)
+            | BrTable(_)
+            | Return
+            | Unreachable
+            | I32Store(_, _)
+            | I64Store(_, _)
+            | F32Store(_, _)
+            | F64Store(_, _)
+            | I32Store8(_, _)
+            | I32Store16(_, _)
+            | I64Store8(_, _)
+            | I64Store16(_, _)
+            | I64Store32(_, _)
+    ) || matches!(instruction, GetGlobal(global) | SetGlobal(global) if stack_globals.contains(global))
+}
@@ -0,0 +1,185 @@
+use anyhow::{bail, Result};
+use std::fmt::Write;
+
+// The first bytes of memory are unused, so that 0 is never a valid pointer
+const NULL_PAGE: i32 = 0x10;
+// Files are read this many bytes at a time
+pub const FILE_BUFFER_SIZE: i32 = 1024;
+// Code fields hold a function table index in this many low bits.
+// The rest hold a cell-aligned address divided by 4, like the runtime code of a DOES> word.
+pub const CODE_INDEX_BITS: i32 = 12;
+// The dictionary starts on a boundary like this, after the buffers in low memory
+const DICTIONARY_ALIGNMENT: i32 = 0x1000;
+
+/// Where everything lives in the interpreter's linear memory.
+///
+/// Low memory holds the input buffers, then the dictionary grows up towards the parameter stack.
+/// All three stacks grow down, with the return stack above the parameter stack
+/// and the float stack above that. The backtrace and source map come next,
+/// and the heap starts after them.
+#[derive(Clone, Copy, Debug)]
+pub struct MemoryLayout {
+    /// How many bytes the terminal input buffer (TIB) holds
+    pub tib_size: i32,
+    /// How many bytes the dictionary can grow to
+    pub dictionary_capacity: i32,
+    /// How many cells the parameter stack holds
+    pub param_stack_depth: i32,
+    /// How many cells the return stack holds
+    pub return_stack_depth: i32,
+    /// How many floats the float stack holds
+    pub float_stack_depth: i32,
+    /// How many return stack cells THROW saves for a backtrace
+    pub backtrace_depth: i32,
+    /// How many lines the source map holds, counting only lines which compile something.
+    /// The prelude takes about 1500, and later lines go unmapped once it's full.
+    pub source_map_capacity: i32,
+    /// Where the heap starts, if not right after the float stack
+    pub heap_base: Option<i32>,
+}
+
+impl Default for MemoryLayout {
+    fn default() -> Self {
+        Self {
+            tib_size: 0xc0,
+            dictionary_capacity: 0xd900,
+            param_stack_depth: 256,
+            return_stack_depth: 256,
+            float_stack_depth: 64,
+            backtrace_depth: 64,
+            source_map_capacity: 2048,
+            heap_base: None,
+        }
+    }
+}
+
+impl MemoryLayout {
+    pub fn tib(&self) -> i32 {
+        NULL_PAGE
+    }
+
+    /// The ciovec which output is written from
+    pub fn ciovec(&self) -> i32 {
+        (self.tib() + self.tib_size + 7) & -8
+    }
+
+    /// The iovec which input is read into
+    pub fn iovec(&self) -> i32 {
+        self.ciovec() + 8
+    }
+
+    pub fn file_buffer(&self) -> i32 {
+        self.iovec() + 8
+    }
+
+    pub fn dictionary_base(&self) -> i32 {
+        let end = self.file_buffer() + FILE_BUFFER_SIZE;
+        (end + DICTIONARY_ALIGNMENT - 1) & -DICTIONARY_ALIGNMENT
+    }
+
+    /// The highest address of the parameter stack. Its lowest is the end of the dictionary.
+    pub fn param_stack_base(&self) -> i32 {
+        self.dictionary_base() + self.dictionary_capacity + self.param_stack_depth * 4
+    }
+
+    /// The highest address of the return stack. Its lowest is the parameter stack's base.
+    pub fn return_stack_base(&self) -> i32 {
+        self.param_stack_base() + self.return_stack_depth * 4
+    }
+
+    /// The highest address of the float stack. Its lowest is the return stack's base.
+    pub fn float_stack_base(&self) -> i32 {
+        self.return_stack_base() + self.float_stack_depth * 8
+    }
+
+    /// How high the dictionary can reach, in its own space or in the heap.
+    /// Code fields point at DOES> code with the bits the table index doesn't use.
+    pub fn dictionary_ceiling(&self) -> i32 {
+        1 << (32 - CODE_INDEX_BITS + 2)
+    }
+
+    /// Where THROW saves the return stack it unwound
+    pub fn backtrace(&self) -> i32 {
+        self.float_stack_base()
+    }
+
+    /// Where each line of source started compiling. Each entry is four cells.
+    pub fn source_map(&self) -> i32 {
+        self.backtrace() + self.backtrace_depth * 4
+    }
+
+    /// The end of everything with a fixed place in memory
+    pub fn reserved_end(&self) -> i32 {
+        self.source_map() + self.source_map_capacity * 16
+    }
+
+    /// The lowest address the heap (and any embedded data) can use
+    pub fn heap_base(&self) -> i32 {
+        self.heap_base.unwrap_or_else(|| self.reserved_end())
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let sizes = [
+            ("TIB size", self.tib_size),
+            ("dictionary capacity", self.dictionary_capacity),
+            ("parameter stack depth", self.param_stack_depth),
+            ("return stack depth", self.return_stack_depth),
+            ("float stack depth", self.float_stack_depth),
+        ];
+        for (name, size) in sizes {
+            if size <= 0 {
+                bail!("The {} must be positive, not {}", name, size);
+            }
+        }
+        // without these, exceptions just don't have backtraces and addresses don't have lines
+        let capacities = [
+            ("backtrace depth", self.backtrace_depth),
+            ("source map capacity", self.source_map_capacity),
+        ];
+        for (name, capacity) in capacities {
+            if capacity < 0 {
+                bail!("The {} can't be negative, not {}", name, capacity);
+            }
+        }
+        // DOES> words point into the dictionary from their code fields
+        let ceiling = self.dictionary_ceiling() as i64;
+        if self.dictionary_base() as i64 + self.dictionary_capacity as i64 > ceiling {
+            bail!(
+                "The dictionary can't reach past {:#x}, so its capacity can't be {:#x}",
+                ceiling,
+                self.dictionary_capacity
+            );
+        }
+        if self.heap_base() < self.reserved_end() {
+            bail!(
+                "The heap can't start at {:#x}, inside the stacks or what's after them (which end at {:#x})",
+                self.heap_base(),
+                self.reserved_end()
+            );
+        }
+        Ok(())
+    }
+
+    /// The layout as Forth constants, for the self-hosted compiler to include
+    pub fn to_forth(&self) -> String {
+        let constants = [
+            ("TIB_BASE", self.tib()),
+            ("TIB_CAPACITY", self.tib_size),
+            ("DICT_BASE", self.dictionary_base()),
+            ("DICT_CAPACITY", self.dictionary_capacity),
+            ("DICT_CEILING", self.dictionary_ceiling()),
+            // stacks grow down, so these are their HIGHEST addresses
+            ("PARAM_STACK_BASE", self.param_stack_base()),
+            ("RETURN_STACK_BASE", self.return_stack_base()),
+            ("FLOAT_STACK_BASE", self.float_stack_base()),
+            ("HEAP_BASE", self.heap_base()),
+            ("CODE_INDEX_BITS", CODE_INDEX_BITS),
+        ];
+        let mut forth = String::from("\\ generated by forsm-bootstrapper --print-layout\nhex\n");
+        for (name, value) in constants {
+            writeln!(forth, "{:04x} constant {}", value, name).unwrap();
+        }
+        forth.push_str("decimal\n");
+        forth
+    }
+}
@@ -0,0 +1,316 @@
+use super::{ColonValue, LoweredValue};
+use anyhow::{anyhow, bail, Result};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+// A point in a word which is reached with this many different stacks is probably in a loop
+// which grows or shrinks the stack every time around.
+const MAX_STATES: usize = 8;
+
+// What we know about the value on top of the stack, so that ?BRANCH can pick an arm
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Flag {
+    Unknown,
+    True,
+    False,
+}
+
+// One of the stacks a word can leave behind
+struct Output {
+    cells: i32,
+    flag: Flag,
+}
+
+/* A declared stack effect, like ( c-addr u -- n -1 | 0 ).
+ * Doubles (d, ud, d1...) take two cells, alternative outputs are separated by |,
+ * and a word which takes or leaves i*x (or any other n*x) can't be checked.
+ * What a word does to the float stack, like ( F: r1 r2 -- r3 ), can follow (or replace) the
+ * parameter stack's effect; only the parameter stack is checked. */
+pub struct StackEffect {
+    text: String,
+    inputs: i32,
+    outputs: Vec<Output>,
+    variadic: bool,
+}
+
+impl StackEffect {
+    // How many cells the word takes, at least
+    pub fn inputs(&self) -> i32 {
+        self.inputs
+    }
+
+    // The most the word can grow the stack by, if that's known
+    pub fn growth(&self) -> Option<i32> {
+        if self.variadic {
+            return None;
+        }
+        let outputs = self.outputs.iter().map(|output| output.cells).max()?;
+        Some(outputs - self.inputs)
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let params = match text.trim().rfind("( F:") {
+            Some(0) => "( -- )",
+            Some(floats) => &text.trim()[..floats],
+            None => text,
+        };
+        let inner = params
+            .trim()
+            .strip_prefix('(')
+            .and_then(|text| text.strip_suffix(')'))
+            .ok_or_else(|| anyhow!("stack effect {} should be in parentheses", text))?;
+        let names: Vec<&str> = inner.split_whitespace().collect();
+        let divider = names
+            .iter()
+            .position(|name| *name == "--")
+            .ok_or_else(|| anyhow!("stack effect {} is missing --", text))?;
+        let inputs = names[..divider].join(" ");
+        let outputs = names[divider + 1..].join(" ");
+
+        let mut variadic = false;
+        let mut count = |names: &str| {
+            let names: Vec<&str> = names.split_whitespace().collect();
+            variadic |= names.iter().any(|name| name.contains('*'));
+            let cells = names.iter().map(|name| cells(name)).sum();
+            (cells, names.last().map_or(Flag::Unknown, |name| flag(name)))
+        };
+        let inputs = count(&inputs).0;
+        let mut outputs: Vec<Output> = outputs
+            .split('|')
+            .map(|names| {
+                let (cells, flag) = count(names);
+                Output { cells, flag }
+            })
+            .collect();
+        // "x | 0" means that x is never 0
+        if outputs.iter().any(|output| output.flag == Flag::False) {
+            for output in outputs.iter_mut() {
+                if output.flag == Flag::Unknown && output.cells > 0 {
+                    output.flag = Flag::True;
+                }
+            }
+        }
+        Ok(Self {
+            text: text.trim().to_owned(),
+            inputs,
+            outputs,
+            variadic,
+        })
+    }
+}
+
+fn cells(name: &str) -> i32 {
+    // i*x could be any number of cells, including none
+    if name.contains('*') {
+        return 0;
+    }
+    let name = name.to_ascii_lowercase();
+    let name = name.strip_prefix('u').unwrap_or(&name);
+    match name.strip_prefix('d') {
+        Some(suffix) if suffix.bytes().all(|b| b.is_ascii_digit()) => 2,
+        _ => 1,
+    }
+}
+
+fn flag(name: &str) -> Flag {
+    match name.to_ascii_lowercase().as_str() {
+        "false" => Flag::False,
+        "true" => Flag::True,
+        name => match name.parse::<i32>() {
+            Ok(0) => Flag::False,
+            Ok(_) => Flag::True,
+            Err(_) => Flag::Unknown,
+        },
+    }
+}
+
+// A colon word, boiled down to what matters for its stack effect
+pub enum Step {
+    Call(&'static str),
+    Push(Flag),
+    Jump { target: usize, conditional: bool },
+    Exit,
+}
+
+pub fn steps(values: &[LoweredValue]) -> Vec<Step> {
+    let mut labels = HashMap::new();
+    let mut jumps = vec![];
+    let mut steps = vec![];
+    let mut literal = false;
+    for value in values {
+        let step = match value {
+            // LIT pushes whatever follows it, even another XT
+            LoweredValue::Value(_) if literal => {
+                literal = false;
+                Step::Push(Flag::Unknown)
+            }
+            LoweredValue::Value(ColonValue::XT(word)) if word.eq_ignore_ascii_case("LIT") => {
+                literal = true;
+                continue;
+            }
+            LoweredValue::Value(ColonValue::XT(word)) if word.eq_ignore_ascii_case("EXIT") => {
+                Step::Exit
+            }
+            LoweredValue::Value(ColonValue::XT(word)) => Step::Call(word),
+            LoweredValue::Value(ColonValue::Lit(value)) => Step::Push(match value {
+                0 => Flag::False,
+                _ => Flag::True,
+            }),
+            LoweredValue::Value(ColonValue::StringLit(value)) => {
+                steps.push(Step::Push(Flag::True));
+                Step::Push(match value.len() {
+                    0 => Flag::False,
+                    _ => Flag::True,
+                })
+            }
+            LoweredValue::Value(_) => unreachable!("control flow was already lowered"),
+            LoweredValue::Mark(label) => {
+                labels.insert(*label, steps.len());
+                continue;
+            }
+            LoweredValue::Jump(word, label) => {
+                jumps.push((steps.len(), *label));
+                Step::Jump {
+                    target: 0,
+                    conditional: *word == "?BRANCH",
+                }
+            }
+        };
+        steps.push(step);
+    }
+    for (index, label) in jumps {
+        if let Step::Jump { target, .. } = &mut steps[index] {
+            *target = labels[&label];
+        }
+    }
+    steps
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct State {
+    // relative to the start of the word
+    depth: i32,
+    flag: Flag,
+}
+
+/* Follow every path through a word, to see whether the stacks it can leave behind match its declaration.
+ * Paths which call a word with an uncheckable effect (like EXECUTE) are given up on. */
+pub fn check<'a, F>(effect: &StackEffect, steps: &[Step], effect_of: F) -> Result<()>
+where
+    F: Fn(&str) -> Option<&'a StackEffect>,
+{
+    if effect.variadic {
+        return Ok(());
+    }
+    let mut seen: Vec<HashSet<State>> = steps.iter().map(|_| HashSet::new()).collect();
+    let mut pending = vec![(
+        0,
+        State {
+            depth: 0,
+            flag: Flag::Unknown,
+        },
+    )];
+    let mut deepest = 0;
+    let mut results = BTreeSet::new();
+    while let Some((index, state)) = pending.pop() {
+        if index == steps.len() {
+            results.insert(state.depth);
+            continue;
+        }
+        if !seen[index].insert(state) {
+            continue;
+        }
+        if seen[index].len() > MAX_STATES {
+            bail!(
+                "declared {}, but its stack grows or shrinks every time around a loop",
+                effect.text
+            );
+        }
+        match steps[index] {
+            Step::Push(flag) => pending.push((
+                index + 1,
+                State {
+                    depth: state.depth + 1,
+                    flag,
+                },
+            )),
+            Step::Exit => drop(results.insert(state.depth)),
+            Step::Jump {
+                target,
+                conditional: false,
+            } => pending.push((target, state)),
+            Step::Jump {
+                target,
+                conditional: true,
+            } => {
+                let popped = State {
+                    depth: state.depth - 1,
+                    flag: Flag::Unknown,
+                };
+                deepest = deepest.min(popped.depth);
+                if state.flag != Flag::True {
+                    pending.push((target, popped));
+                }
+                if state.flag != Flag::False {
+                    pending.push((index + 1, popped));
+                }
+            }
+            Step::Call(word) => {
+                // unknown words are reported on their own
+                let callee = match effect_of(word) {
+                    Some(callee) if !callee.variadic => callee,
+                    _ => continue,
+                };
+                let depth = state.depth - callee.inputs;
+                deepest = deepest.min(depth);
+                for output in callee.outputs.iter() {
+                    if let Some(flag) = pass_flag(word, state.flag, output.flag) {
+                        pending.push((
+                            index + 1,
+                            State {
+                                depth: depth + output.cells,
+                                flag,
+                            },
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    if -deepest > effect.inputs {
+        bail!(
+            "declared {}, but it reaches {} deep into the stack",
+            effect.text,
+            -deepest
+        );
+    }
+    let unexpected: Vec<String> = results
+        .into_iter()
+        .map(|depth| depth + effect.inputs)
+        .filter(|cells| !effect.outputs.iter().any(|output| output.cells == *cells))
+        .map(|cells| cells.to_string())
+        .collect();
+    if !unexpected.is_empty() {
+        bail!(
+            "declared {}, but the stack can be {} deep when it returns",
+            effect.text,
+            unexpected.join(" or ")
+        );
+    }
+    Ok(())
+}
+
+// A few words are worth following a flag through,
+// so that the arms of an "?DUP IF" or "?DIGIT =0 IF" are kept apart.
+// Returns None for an output which can't happen.
+fn pass_flag(word: &str, input: Flag, output: Flag) -> Option<Flag> {
+    match (word.to_ascii_uppercase().as_str(), input) {
+        (_, Flag::Unknown) => Some(output),
+        ("DUP", _) | ("<>0", _) => Some(input),
+        ("=0", Flag::True) => Some(Flag::False),
+        ("=0", Flag::False) => Some(Flag::True),
+        ("?DUP", _) if input == output => Some(output),
+        ("?DUP", _) => None,
+        _ => Some(output),
+    }
+}
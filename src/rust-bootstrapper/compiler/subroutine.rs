@@ -0,0 +1,215 @@
+use super::{aligned, ColonValue, Label, LoweredValue};
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+
+// Words which read the IP, or treat the return stack as a list of frames.
+// A colon word which uses them only works when it's threaded.
+const THREADED_ONLY: [&str; 4] = ["BRANCH", "?BRANCH", "RP@", "RP!"];
+
+/* One step of a colon word which is compiled into a function of its own.
+ * Jumps are turned into WASM blocks and loops, so that the function never touches the IP. */
+pub enum Op {
+    Call(&'static str),
+    // push the XT of a word, like LIT followed by that XT
+    PushXt(&'static str),
+    Push(i32),
+    Exit,
+    Block,
+    Loop,
+    End,
+    // jump out of a block (or back to the start of a loop) this many levels up
+    Br(u32),
+    // the same, if the top of the stack is false
+    BrIfFalse(u32),
+}
+
+// A colon word, ready to be turned into a function once every word it calls is defined
+pub struct Subroutine {
+    pub ops: Vec<Op>,
+    // string literals, which live in the word's parameter field
+    pub data: Vec<u8>,
+}
+
+// Everything but the jumps, which have to be structured first
+enum Item {
+    Op(Op),
+    Jump { label: Label, conditional: bool },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Kind {
+    Block,
+    Loop,
+}
+
+// A block or a loop, which opens and closes between items
+struct Construct {
+    kind: Kind,
+    open: usize,
+    close: usize,
+}
+
+/* Compile a colon word into a function, if it can be.
+ * The word's parameter field (where its string literals go) starts at data_start. */
+pub fn compile(values: &[LoweredValue], data_start: i32) -> Result<Subroutine> {
+    let mut items = vec![];
+    let mut labels = HashMap::new();
+    let mut data = vec![];
+    let mut values = values.iter();
+    while let Some(value) = values.next() {
+        let item = match value {
+            LoweredValue::Value(ColonValue::XT(word)) if word.eq_ignore_ascii_case("LIT") => {
+                match values.next() {
+                    Some(LoweredValue::Value(ColonValue::XT(word))) => Item::Op(Op::PushXt(word)),
+                    _ => bail!("LIT is only compiled when an XT follows it"),
+                }
+            }
+            LoweredValue::Value(ColonValue::XT(word)) if word.eq_ignore_ascii_case("EXIT") => {
+                Item::Op(Op::Exit)
+            }
+            LoweredValue::Value(ColonValue::XT(word)) => {
+                if THREADED_ONLY.iter().any(|t| t.eq_ignore_ascii_case(word)) {
+                    bail!("{} only works in threaded code", word);
+                }
+                Item::Op(Op::Call(word))
+            }
+            LoweredValue::Value(ColonValue::Lit(value)) => Item::Op(Op::Push(*value)),
+            LoweredValue::Value(ColonValue::StringLit(value)) => {
+                let address = data_start + data.len() as i32;
+                data.extend_from_slice(value.as_bytes());
+                data.resize(aligned(data.len() as i32) as usize, 0);
+                items.push(Item::Op(Op::Push(address)));
+                Item::Op(Op::Push(value.len() as i32))
+            }
+            LoweredValue::Value(_) => unreachable!("control flow was already lowered"),
+            LoweredValue::Mark(label) => {
+                labels.insert(*label, items.len());
+                continue;
+            }
+            LoweredValue::Jump(word, label) => Item::Jump {
+                label: *label,
+                conditional: *word == "?BRANCH",
+            },
+        };
+        items.push(item);
+    }
+    let constructs = structure(&items, &labels)?;
+    Ok(Subroutine {
+        ops: emit(items, &labels, &constructs),
+        data,
+    })
+}
+
+/* Jumps forward become blocks which end at their target, and jumps backward become loops which start at it.
+ * Blocks can start earlier and loops can end later than they need to,
+ * which is how overlapping ones are nested inside each other. */
+fn structure(items: &[Item], labels: &HashMap<Label, usize>) -> Result<Vec<Construct>> {
+    let mut constructs: Vec<Construct> = vec![];
+    let mut targets = HashMap::new();
+    for (index, item) in items.iter().enumerate() {
+        if let Item::Jump { label, .. } = item {
+            let target = match labels.get(label) {
+                Some(target) => *target,
+                None => bail!("jump to a missing label"),
+            };
+            let kind = if target > index {
+                Kind::Block
+            } else {
+                Kind::Loop
+            };
+            let construct = *targets.entry((target, kind)).or_insert_with(|| {
+                constructs.push(Construct {
+                    kind,
+                    open: target,
+                    close: target,
+                });
+                constructs.len() - 1
+            });
+            let construct = &mut constructs[construct];
+            match kind {
+                Kind::Block => construct.open = construct.open.min(index),
+                Kind::Loop => construct.close = construct.close.max(index + 1),
+            }
+        }
+    }
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for first in 0..constructs.len() {
+            for second in 0..constructs.len() {
+                let (a, b) = (&constructs[first], &constructs[second]);
+                // first opens, then second opens, then first closes before second does
+                if !(a.open < b.open && b.open < a.close && a.close < b.close) {
+                    continue;
+                }
+                if b.kind == Kind::Block {
+                    constructs[second].open = constructs[first].open;
+                } else if a.kind == Kind::Loop {
+                    constructs[first].close = constructs[second].close;
+                } else {
+                    bail!("its control flow can't be nested");
+                }
+                changed = true;
+            }
+        }
+    }
+    Ok(constructs)
+}
+
+fn emit(items: Vec<Item>, labels: &HashMap<Label, usize>, constructs: &[Construct]) -> Vec<Op> {
+    // outer constructs open first
+    let mut order: Vec<usize> = (0..constructs.len()).collect();
+    order.sort_by_key(|index| {
+        let construct = &constructs[*index];
+        (construct.open, std::cmp::Reverse(construct.close))
+    });
+
+    let mut ops = vec![];
+    let mut open: Vec<usize> = vec![];
+    for (index, item) in items.into_iter().map(Some).chain(Some(None)).enumerate() {
+        while let Some(top) = open.last() {
+            if constructs[*top].close != index {
+                break;
+            }
+            open.pop();
+            ops.push(Op::End);
+        }
+        for construct in order.iter().filter(|c| constructs[**c].open == index) {
+            open.push(*construct);
+            ops.push(match constructs[*construct].kind {
+                Kind::Block => Op::Block,
+                Kind::Loop => Op::Loop,
+            });
+        }
+        match item {
+            Some(Item::Op(op)) => ops.push(op),
+            Some(Item::Jump { label, conditional }) => {
+                let target = labels[&label];
+                let kind = if target > index {
+                    Kind::Block
+                } else {
+                    Kind::Loop
+                };
+                let construct = open
+                    .iter()
+                    .rposition(|c| {
+                        let c = &constructs[*c];
+                        c.kind == kind
+                            && match kind {
+                                Kind::Block => c.close == target,
+                                Kind::Loop => c.open == target,
+                            }
+                    })
+                    .expect("every jump has a construct around it");
+                let depth = (open.len() - 1 - construct) as u32;
+                ops.push(match conditional {
+                    true => Op::BrIfFalse(depth),
+                    false => Op::Br(depth),
+                });
+            }
+            None => {}
+        }
+    }
+    ops
+}
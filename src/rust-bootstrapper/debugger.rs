@@ -0,0 +1,327 @@
+#![cfg(any(test, feature = "runtime"))] // the debugger runs on wasmer
+
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::sync::{Arc, Mutex};
+
+use wasmer::{
+    Exports, Function, Global, HostEnvInitError, ImportObject, Instance, Memory, Module,
+    RuntimeError, Store, Value, WasmerEnv,
+};
+
+use super::compiler::{DictionaryEntry, WordKind};
+use super::profiler::Profiler;
+use super::{Frame, MemoryLayout, SourceLocation, SourceMap};
+
+/// Where the inner interpreter is, each time it's about to run an XT
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Position {
+    /// The cell the XT came from
+    pub ip: i32,
+    pub xt: i32,
+    /// The parameter stack pointer
+    pub sp: i32,
+    /// The return stack pointer
+    pub rp: i32,
+}
+
+/// How to carry on after the debugger stops
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resume {
+    /// Run until the next breakpoint
+    Continue,
+    /// Stop before the next XT, even if it's inside a word this one calls
+    Step,
+    /// Stop before the next XT in this word, once whatever it runs now has returned
+    StepOver,
+    /// Stop once this word has returned to its caller
+    StepOut,
+}
+
+/* A word to stop at. Colon words stop once they've started, at the first XT of their body,
+ * since words like EXECUTE can run them without their own XT ever passing the hook. */
+struct Breakpoint {
+    word: String,
+    colon: bool,
+}
+
+fn set_breakpoint(breakpoints: &mut BTreeMap<i32, Breakpoint>, word: &DictionaryEntry) {
+    let breakpoint = Breakpoint {
+        word: word.name.clone(),
+        colon: word.kind == WordKind::Colon,
+    };
+    breakpoints.insert(word.xt, breakpoint);
+}
+
+/// Whatever decides what to do each time the debugger stops, like a REPL
+pub type DebugHandler = Box<dyn FnMut(&mut Stop<'_>) -> Resume + Send>;
+
+/// Decides when a debug build of the interpreter should stop, and hands it to a handler when it does.
+///
+/// Attach one with [`ForthVm::with_debugger`](crate::ForthVm::with_debugger).
+pub struct Debugger {
+    // the words to stop at, by XT
+    breakpoints: BTreeMap<i32, Breakpoint>,
+    resume: Resume,
+    // the return stack pointer where the debugger last resumed, for stepping over and out
+    rp: i32,
+    // the VM only debugs the words it's asked to run, not its own bookkeeping
+    active: bool,
+    handler: DebugHandler,
+}
+
+impl Debugger {
+    pub fn new(handler: impl FnMut(&mut Stop<'_>) -> Resume + Send + 'static) -> Self {
+        Self {
+            breakpoints: BTreeMap::new(),
+            resume: Resume::Continue,
+            rp: 0,
+            active: false,
+            handler: Box::new(handler),
+        }
+    }
+
+    pub(crate) fn set_breakpoint(&mut self, word: &DictionaryEntry) {
+        set_breakpoint(&mut self.breakpoints, word);
+    }
+
+    pub(crate) fn clear_breakpoint(&mut self, xt: i32) -> bool {
+        self.breakpoints.remove(&xt).is_some()
+    }
+
+    pub(crate) fn set_resume(&mut self, resume: Resume) {
+        // stepping over or out from outside of any word runs to completion
+        self.resume = resume;
+        self.rp = i32::MAX;
+    }
+
+    pub(crate) fn set_active(&mut self, active: bool) {
+        self.active = active;
+    }
+
+    fn should_stop(&self, position: &Position) -> bool {
+        if !self.active {
+            return false;
+        }
+        let breakpoint = |xt, colon| {
+            self.breakpoints
+                .get(&xt)
+                .is_some_and(|breakpoint| breakpoint.colon == colon)
+        };
+        breakpoint(position.xt, false)
+            || breakpoint(position.ip - 4, true)
+            || match self.resume {
+                Resume::Continue => false,
+                Resume::Step => true,
+                // the return stack grows down, so a higher RP is a shallower word
+                Resume::StepOver => position.rp >= self.rp,
+                Resume::StepOut => position.rp > self.rp,
+            }
+    }
+
+    fn stop(&mut self, stop: &mut Stop<'_>) {
+        let resume = (self.handler)(stop);
+        self.resume = resume;
+        self.rp = stop.position.rp;
+    }
+}
+
+/// The interpreter, stopped just before it runs an XT
+pub struct Stop<'a> {
+    pub position: Position,
+    map: SourceMap,
+    memory: Vec<u8>,
+    // the top of the parameter stack, if it's cached in a global
+    tos: Option<i32>,
+    layout: MemoryLayout,
+    breakpoints: &'a mut BTreeMap<i32, Breakpoint>,
+}
+
+impl Stop<'_> {
+    /// The name of the word about to run
+    pub fn word(&self) -> Option<&str> {
+        self.map
+            .word_at(self.position.xt)
+            .map(|word| word.name.as_str())
+    }
+
+    /// Where the code being run was compiled from
+    pub fn location(&self) -> Option<SourceLocation> {
+        self.map.locate(self.position.ip)
+    }
+
+    /// The parameter stack, top first
+    pub fn stack(&self) -> Vec<i32> {
+        let base = self.layout.param_stack_base();
+        match self.tos {
+            None => self.cells(self.position.sp, base),
+            // a cached top spills into the cell at SP, which is garbage until something's under it
+            Some(tos) if self.position.sp < base - 4 => {
+                let mut stack = vec![tos];
+                stack.extend(self.cells(self.position.sp + 4, base - 4));
+                stack
+            }
+            Some(_) => vec![],
+        }
+    }
+
+    /// The return stack, top first
+    pub fn return_stack(&self) -> Vec<i32> {
+        self.cells(self.position.rp, self.layout.return_stack_base())
+    }
+
+    /// The words which are running, innermost first
+    pub fn backtrace(&self) -> Vec<Frame> {
+        let mut cells = vec![self.position.ip];
+        cells.extend(self.return_stack());
+        self.map.backtrace(&cells)
+    }
+
+    pub fn read_memory(&self, address: i32, len: usize) -> Option<&[u8]> {
+        let start: usize = address.try_into().ok()?;
+        self.memory.get(start..start.checked_add(len)?)
+    }
+
+    pub fn source_map(&self) -> &SourceMap {
+        &self.map
+    }
+
+    /// Stop whenever a word is about to run. Returns false if there's no such word.
+    pub fn set_breakpoint(&mut self, word: &str) -> bool {
+        match self.map.find(word) {
+            Some(entry) => {
+                set_breakpoint(self.breakpoints, entry);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns false if the word had no breakpoint
+    pub fn clear_breakpoint(&mut self, word: &str) -> bool {
+        match self.map.find(word) {
+            Some(entry) => self.breakpoints.remove(&entry.xt).is_some(),
+            None => false,
+        }
+    }
+
+    /// The words with breakpoints
+    pub fn breakpoints(&self) -> impl Iterator<Item = &str> {
+        self.breakpoints
+            .values()
+            .map(|breakpoint| breakpoint.word.as_str())
+    }
+
+    fn cells(&self, from: i32, to: i32) -> Vec<i32> {
+        (from..to)
+            .step_by(4)
+            .filter_map(|address| self.read_memory(address, 4))
+            .map(|cell| i32::from_le_bytes(cell.try_into().unwrap()))
+            .collect()
+    }
+}
+
+/* Whatever the debug_hook import calls into */
+#[derive(Clone)]
+pub(crate) enum Hook {
+    Debugger(Arc<Mutex<Debugger>>),
+    Profiler(Arc<Mutex<Profiler>>),
+}
+
+/* What the debug_hook import needs to stop the interpreter and look around */
+#[derive(Clone)]
+struct HookEnv {
+    hook: Hook,
+    binary: Arc<Vec<u8>>,
+    layout: MemoryLayout,
+    memory: Option<Memory>,
+    tos: Option<Global>,
+}
+
+impl WasmerEnv for HookEnv {
+    fn init_with_instance(&mut self, instance: &Instance) -> Result<(), HostEnvInitError> {
+        self.memory = Some(instance.exports.get_memory("memory")?.clone());
+        self.tos = instance.exports.get_global("tos").ok().cloned();
+        Ok(())
+    }
+}
+
+fn debug_hook(env: &HookEnv, ip: i32, xt: i32, sp: i32, rp: i32) -> Result<(), RuntimeError> {
+    let position = Position { ip, xt, sp, rp };
+    let memory = env
+        .memory
+        .as_ref()
+        .ok_or_else(|| RuntimeError::new("debug_hook ran before the module had memory"))?;
+    match &env.hook {
+        Hook::Debugger(debugger) => stop(env, &mut debugger.lock().unwrap(), position, memory)?,
+        Hook::Profiler(profiler) => profiler
+            .lock()
+            .unwrap()
+            .record(&position, memory, env.layout),
+    }
+    Ok(())
+}
+
+fn stop(
+    env: &HookEnv,
+    debugger: &mut Debugger,
+    position: Position,
+    memory: &Memory,
+) -> Result<(), RuntimeError> {
+    if !debugger.should_stop(&position) {
+        return Ok(());
+    }
+    let memory: Vec<u8> = memory.view::<u8>().iter().map(|cell| cell.get()).collect();
+    let map = SourceMap::read(&env.binary, &memory)
+        .map_err(|error| RuntimeError::new(error.to_string()))?;
+    let tos = match env.tos.as_ref().map(Global::get) {
+        Some(Value::I32(tos)) => Some(tos),
+        _ => None,
+    };
+    let mut breakpoints = std::mem::take(&mut debugger.breakpoints);
+    let mut stop = Stop {
+        position,
+        map,
+        memory,
+        tos,
+        layout: env.layout,
+        breakpoints: &mut breakpoints,
+    };
+    debugger.stop(&mut stop);
+    debugger.breakpoints = breakpoints;
+    Ok(())
+}
+
+/* Provide the debug_hook import, if the module has one.
+ * Without a debugger or profiler, it does nothing. */
+pub(crate) fn register_debug_hook(
+    imports: &mut ImportObject,
+    store: &Store,
+    module: &Module,
+    binary: &[u8],
+    layout: MemoryLayout,
+    hook: Option<Hook>,
+) {
+    let hooked = module
+        .imports()
+        .any(|import| import.module() == "forsm" && import.name() == "debug_hook");
+    if !hooked {
+        return;
+    }
+    let hook = match hook {
+        Some(hook) => {
+            let env = HookEnv {
+                hook,
+                binary: Arc::new(binary.to_vec()),
+                layout,
+                memory: None,
+                tos: None,
+            };
+            Function::new_native_with_env(store, env, debug_hook)
+        }
+        None => Function::new_native(store, |_: i32, _: i32, _: i32, _: i32| {}),
+    };
+    let mut namespace = Exports::new();
+    namespace.insert("debug_hook", hook);
+    imports.register("forsm", namespace);
+}
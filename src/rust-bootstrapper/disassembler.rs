@@ -0,0 +1,781 @@
+use crate::compiler::{read_dictionary, DictionaryEntry, WordKind, CODE_INDEX_BITS};
+use anyhow::Result;
+use parity_wasm::elements::{
+    BlockType, External, FuncBody, ImportCountType, InitExpr, Instruction, Internal, Module,
+    NameMap, Type,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryInto,
+    fmt::Write,
+};
+
+// Data segments are split into strings of at most this many bytes, one per line
+const DATA_LINE_BYTES: usize = 32;
+// How many parameter cells to list for words which aren't colon words
+const PARAMETER_CELLS: usize = 8;
+// Comments are wrapped to fit in this many columns
+const COMMENT_WIDTH: usize = 100;
+
+/// Render a compiled module as WebAssembly text.
+/// Functions are named after the words they implement, and if the module has a
+/// [`DICTIONARY_SECTION`](crate::DICTIONARY_SECTION), every word's header is decoded
+/// in a comment beside the data which holds it.
+pub fn disassemble(binary: &[u8]) -> Result<String> {
+    let module = match Module::from_bytes(binary)?.parse_names() {
+        Ok(module) => module,
+        // without names, functions are just numbered
+        Err((_, module)) => module,
+    };
+    let words = read_dictionary(binary).unwrap_or_default();
+    let mut disassembler = Disassembler::new(&module, words);
+    disassembler.module()?;
+    Ok(disassembler.out)
+}
+
+struct Disassembler<'a> {
+    module: &'a Module,
+    out: String,
+    // identifiers (with their $) by function and global index
+    functions: Vec<Option<String>>,
+    globals: Vec<Option<String>>,
+    imported_functions: usize,
+    // which function each table entry calls
+    table: HashMap<u32, u32>,
+    // the dictionary, in order of address
+    words: Vec<DictionaryEntry>,
+    names_by_xt: HashMap<i32, String>,
+    // every data segment copied into one image, with forward references patched
+    memory: Vec<u8>,
+}
+
+impl<'a> Disassembler<'a> {
+    fn new(module: &'a Module, mut words: Vec<DictionaryEntry>) -> Self {
+        let imported_functions = module.import_count(ImportCountType::Function);
+        let function_count = module.functions_space();
+        let names = module
+            .names_section()
+            .and_then(|names| names.functions())
+            .map(|functions| functions.names());
+        let functions = identifiers((0..function_count as u32).map(|index| {
+            names
+                .and_then(|names| names.get(index))
+                .map(|name| name.as_str())
+        }));
+
+        let global_count = module.globals_space();
+        let mut global_names = vec![None; global_count];
+        for export in module.export_section().map_or(&[][..], |s| s.entries()) {
+            if let Internal::Global(index) = export.internal() {
+                global_names[*index as usize] = Some(export.field());
+            }
+        }
+        let globals = identifiers(global_names.into_iter());
+
+        let mut table = HashMap::new();
+        for segment in module.elements_section().map_or(&[][..], |s| s.entries()) {
+            if let Some(offset) = segment.offset().as_ref().and_then(constant) {
+                for (index, func) in segment.members().iter().enumerate() {
+                    table.insert(offset as u32 + index as u32, *func);
+                }
+            }
+        }
+
+        let mut memory = vec![];
+        for segment in module.data_section().map_or(&[][..], |s| s.entries()) {
+            if let Some(offset) = segment.offset().as_ref().and_then(constant) {
+                let start = offset as usize;
+                let end = start + segment.value().len();
+                if memory.len() < end {
+                    memory.resize(end, 0);
+                }
+                memory[start..end].copy_from_slice(segment.value());
+            }
+        }
+
+        words.sort_by_key(|word| word.xt);
+        let names_by_xt = words
+            .iter()
+            .map(|word| (word.xt, word.name.clone()))
+            .collect();
+        Self {
+            module,
+            out: String::new(),
+            functions,
+            globals,
+            imported_functions,
+            table,
+            words,
+            names_by_xt,
+            memory,
+        }
+    }
+
+    fn module(&mut self) -> Result<()> {
+        let module = self.module;
+        writeln!(self.out, "(module")?;
+        for (index, Type::Function(func)) in module
+            .type_section()
+            .map_or(&[][..], |s| s.types())
+            .iter()
+            .enumerate()
+        {
+            writeln!(
+                self.out,
+                "  (type (;{};) (func{}{}))",
+                index,
+                value_types("param", func.params()),
+                value_types("result", func.results())
+            )?;
+        }
+
+        let mut function = 0;
+        for import in module.import_section().map_or(&[][..], |s| s.entries()) {
+            let description = match import.external() {
+                External::Function(type_index) => {
+                    let id = declaration(&self.functions, function as usize);
+                    function += 1;
+                    format!("(func {} (type {}))", id, type_index)
+                }
+                External::Global(global) => format!(
+                    "(global {})",
+                    global_type(global.content_type(), global.is_mutable())
+                ),
+                External::Memory(memory) => format!(
+                    "(memory {})",
+                    limits(memory.limits().initial(), memory.limits().maximum())
+                ),
+                External::Table(table) => format!(
+                    "(table {} funcref)",
+                    limits(table.limits().initial(), table.limits().maximum())
+                ),
+            };
+            writeln!(
+                self.out,
+                "  (import {:?} {:?} {})",
+                import.module(),
+                import.field(),
+                description
+            )?;
+        }
+
+        let declarations = module.function_section().map_or(&[][..], |s| s.entries());
+        let bodies = module.code_section().map_or(&[][..], |s| s.bodies());
+        for (index, (declaration, body)) in declarations.iter().zip(bodies).enumerate() {
+            self.func(
+                (self.imported_functions + index) as u32,
+                declaration.type_ref(),
+                body,
+            )?;
+        }
+
+        for (index, table) in module
+            .table_section()
+            .map_or(&[][..], |s| s.entries())
+            .iter()
+            .enumerate()
+        {
+            writeln!(
+                self.out,
+                "  (table (;{};) {} funcref)",
+                index,
+                limits(table.limits().initial(), table.limits().maximum())
+            )?;
+        }
+        for (index, memory) in module
+            .memory_section()
+            .map_or(&[][..], |s| s.entries())
+            .iter()
+            .enumerate()
+        {
+            writeln!(
+                self.out,
+                "  (memory (;{};) {})",
+                index,
+                limits(memory.limits().initial(), memory.limits().maximum())
+            )?;
+        }
+        let imported_globals = module.import_count(ImportCountType::Global);
+        for (index, global) in module
+            .global_section()
+            .map_or(&[][..], |s| s.entries())
+            .iter()
+            .enumerate()
+        {
+            let global_type = global.global_type();
+            writeln!(
+                self.out,
+                "  (global {} {} {})",
+                declaration(&self.globals, imported_globals + index),
+                self::global_type(global_type.content_type(), global_type.is_mutable()),
+                self.init_expr(global.init_expr())
+            )?;
+        }
+        for export in module.export_section().map_or(&[][..], |s| s.entries()) {
+            let internal = match export.internal() {
+                Internal::Function(index) => format!("func {}", self.function(*index)),
+                Internal::Global(index) => format!("global {}", self.global(*index as usize)),
+                Internal::Memory(index) => format!("memory {}", index),
+                Internal::Table(index) => format!("table {}", index),
+            };
+            writeln!(self.out, "  (export {:?} ({}))", export.field(), internal)?;
+        }
+        if let Some(start) = module.start_section() {
+            writeln!(self.out, "  (start {})", self.function(start))?;
+        }
+
+        for (index, segment) in module
+            .elements_section()
+            .map_or(&[][..], |s| s.entries())
+            .iter()
+            .enumerate()
+        {
+            let offset = match segment.offset() {
+                Some(offset) => offset,
+                None => continue,
+            };
+            writeln!(self.out, "  (elem (;{};) {}", index, self.init_expr(offset))?;
+            for func in segment.members() {
+                writeln!(self.out, "    {}", self.function(*func))?;
+            }
+            writeln!(self.out, "  )")?;
+        }
+        for (index, segment) in module
+            .data_section()
+            .map_or(&[][..], |s| s.entries())
+            .iter()
+            .enumerate()
+        {
+            let offset = match segment.offset() {
+                Some(offset) => offset,
+                None => continue,
+            };
+            writeln!(self.out, "  (data (;{};) {}", index, self.init_expr(offset))?;
+            self.data(constant(offset).unwrap_or(0), segment.value())?;
+            writeln!(self.out, "  )")?;
+        }
+
+        // the name section is already spelled out as identifiers
+        for section in module.custom_sections() {
+            writeln!(
+                self.out,
+                "  ;; custom section {:?}, {} bytes",
+                section.name(),
+                section.payload().len()
+            )?;
+        }
+        writeln!(self.out, ")")?;
+        Ok(())
+    }
+
+    fn func(&mut self, index: u32, type_index: u32, body: &FuncBody) -> Result<()> {
+        let params = match self
+            .module
+            .type_section()
+            .and_then(|s| s.types().get(type_index as usize))
+        {
+            Some(Type::Function(func)) => func.params().to_vec(),
+            None => vec![],
+        };
+        let local_names = self
+            .module
+            .names_section()
+            .and_then(|names| names.locals())
+            .and_then(|locals| locals.local_names().get(index));
+        let mut local_types = params.clone();
+        for local in body.locals() {
+            local_types.extend((0..local.count()).map(|_| local.value_type()));
+        }
+        let locals = identifiers(
+            (0..local_types.len() as u32)
+                .map(|local| local_names.and_then(|names: &NameMap| names.get(local)))
+                .map(|name| name.map(|name| name.as_str())),
+        );
+
+        write!(
+            self.out,
+            "  (func {} (type {})",
+            declaration(&self.functions, index as usize),
+            type_index
+        )?;
+        for (local, value_type) in locals.iter().zip(&local_types).take(params.len()) {
+            match local {
+                Some(id) => write!(self.out, " (param {} {})", id, value_type)?,
+                None => write!(self.out, " (param {})", value_type)?,
+            }
+        }
+        if let Some(Type::Function(func)) = self
+            .module
+            .type_section()
+            .and_then(|s| s.types().get(type_index as usize))
+        {
+            write!(self.out, "{}", value_types("result", func.results()))?;
+        }
+        writeln!(self.out)?;
+        for (local, value_type) in locals.iter().zip(&local_types).skip(params.len()) {
+            match local {
+                Some(id) => writeln!(self.out, "    (local {} {})", id, value_type)?,
+                None => writeln!(self.out, "    (local {})", value_type)?,
+            }
+        }
+
+        let code = body.code().elements();
+        // the last end closes the function itself, which the text format leaves implicit
+        let code = code.strip_suffix(&[Instruction::End]).unwrap_or(code);
+        let mut depth = 2;
+        for instruction in code {
+            if matches!(instruction, Instruction::End | Instruction::Else) {
+                depth -= 1;
+            }
+            writeln!(
+                self.out,
+                "{:width$}{}",
+                "",
+                self.instruction(instruction, &locals),
+                width = depth * 2
+            )?;
+            if matches!(
+                instruction,
+                Instruction::Block(_)
+                    | Instruction::Loop(_)
+                    | Instruction::If(_)
+                    | Instruction::Else
+            ) {
+                depth += 1;
+            }
+        }
+        writeln!(self.out, "  )")?;
+        Ok(())
+    }
+
+    fn instruction(&self, instruction: &Instruction, locals: &[Option<String>]) -> String {
+        use Instruction::*;
+        let local = |index: &u32| match locals.get(*index as usize) {
+            Some(Some(id)) => id.clone(),
+            _ => index.to_string(),
+        };
+        match instruction {
+            Block(block_type) => format!("block{}", self::block_type(block_type)),
+            Loop(block_type) => format!("loop{}", self::block_type(block_type)),
+            If(block_type) => format!("if{}", self::block_type(block_type)),
+            BrTable(table) => {
+                let mut text = String::from("br_table");
+                for target in table.table.iter().chain([&table.default]) {
+                    text.push_str(&format!(" {}", target));
+                }
+                text
+            }
+            Call(func) => format!("call {}", self.function(*func)),
+            CallIndirect(type_index, _) => format!("call_indirect (type {})", type_index),
+            GetLocal(index) => format!("local.get {}", local(index)),
+            SetLocal(index) => format!("local.set {}", local(index)),
+            TeeLocal(index) => format!("local.tee {}", local(index)),
+            GetGlobal(index) => format!("global.get {}", self.global(*index as usize)),
+            SetGlobal(index) => format!("global.set {}", self.global(*index as usize)),
+            CurrentMemory(_) => "memory.size".to_owned(),
+            GrowMemory(_) => "memory.grow".to_owned(),
+            F32Const(bits) => format!("f32.const {}", float(f32::from_bits(*bits) as f64)),
+            F64Const(bits) => format!("f64.const {}", float(f64::from_bits(*bits))),
+            instruction => match memory_argument(instruction) {
+                Some((align, offset)) => {
+                    let text = instruction.to_string();
+                    let mnemonic = text.split(' ').next().unwrap_or_default();
+                    let mut text = mnemonic.to_owned();
+                    if offset != 0 {
+                        text.push_str(&format!(" offset={}", offset));
+                    }
+                    if align != natural_alignment(mnemonic) {
+                        text.push_str(&format!(" align={}", 1 << align));
+                    }
+                    text
+                }
+                // parity names conversions the old way, like i32.wrap/i64
+                None => match instruction.to_string().split_once('/') {
+                    Some((operation, operand)) => match operation.strip_suffix("_s") {
+                        Some(operation) => format!("{}_{}_s", operation, operand),
+                        None => match operation.strip_suffix("_u") {
+                            Some(operation) => format!("{}_{}_u", operation, operand),
+                            None => format!("{}_{}", operation, operand),
+                        },
+                    },
+                    None => instruction.to_string(),
+                },
+            },
+        }
+    }
+
+    fn init_expr(&self, expr: &InitExpr) -> String {
+        let code = expr.code();
+        let code = code.strip_suffix(&[Instruction::End]).unwrap_or(code);
+        let instructions: Vec<String> = code
+            .iter()
+            .map(|instruction| format!("({})", self.instruction(instruction, &[])))
+            .collect();
+        instructions.join(" ")
+    }
+
+    // The bytes of a data segment, with a comment before the header of every word in it
+    fn data(&mut self, base: i32, bytes: &[u8]) -> Result<()> {
+        let base = base as usize;
+        let mut headers: Vec<(usize, Vec<String>)> = self
+            .words
+            .iter()
+            .map(|word| (name_field(word) as usize, word))
+            .filter(|(address, _)| (base..base + bytes.len()).contains(address))
+            .map(|(address, word)| (address - base, self.header(word)))
+            .collect();
+        headers.sort_by_key(|(offset, _)| *offset);
+        let mut headers = headers.into_iter().peekable();
+
+        let mut offset = 0;
+        while offset < bytes.len() {
+            if let Some((_, comment)) = headers.next_if(|(start, _)| *start == offset) {
+                for line in comment {
+                    writeln!(self.out, "    ;; {}", line)?;
+                }
+            }
+            let mut end = (offset + DATA_LINE_BYTES).min(bytes.len());
+            if let Some((start, _)) = headers.peek() {
+                end = end.min(*start);
+            }
+            writeln!(self.out, "    \"{}\"", escape(&bytes[offset..end]))?;
+            offset = end;
+        }
+        Ok(())
+    }
+
+    // Describe a word's header, and what its parameter field holds
+    fn header(&self, word: &DictionaryEntry) -> Vec<String> {
+        let mut flags = vec![match word.kind {
+            WordKind::Native => "native",
+            WordKind::Colon => "colon",
+            WordKind::Constant => "constant",
+            WordKind::Variable => "variable",
+            WordKind::Does => "does>",
+        }];
+        for (set, flag) in [
+            (word.immediate, "immediate"),
+            (word.hidden, "hidden"),
+            (word.trampolined, "trampolined"),
+        ] {
+            if set {
+                flags.push(flag);
+            }
+        }
+        let mut lines = vec![format!(
+            "{} ({}) at {:#x}, xt {:#x}",
+            word.name,
+            flags.join(", "),
+            name_field(word),
+            word.xt
+        )];
+
+        let code_field = self.cell(word.xt).unwrap_or(0);
+        let index = code_field as u32 & ((1 << CODE_INDEX_BITS) - 1);
+        let immediate = (code_field as u32 >> (CODE_INDEX_BITS - 2)) & !3;
+        let callable = match self.table.get(&index) {
+            Some(func) => self.function(*func),
+            None => format!("table entry {}", index),
+        };
+        let mut code = format!(
+            "link {:#x}, code field {:#x}: {}",
+            self.cell(word.xt - 4).unwrap_or(0),
+            code_field,
+            callable
+        );
+        if immediate != 0 {
+            code.push_str(&format!(" with {:#x}", immediate));
+        }
+        lines.push(code);
+
+        let start = word.xt + 4;
+        let end = self.parameter_end(word);
+        let parameter = match word.kind {
+            WordKind::Colon => format!("body: {}", self.colon_body(start, end).join(" ")),
+            _ => {
+                let mut cells: Vec<String> = (start..end)
+                    .step_by(4)
+                    .take(PARAMETER_CELLS)
+                    .filter_map(|address| self.cell(address))
+                    .map(|cell| cell.to_string())
+                    .collect();
+                if (end - start) as usize > PARAMETER_CELLS * 4 {
+                    cells.push("...".to_owned());
+                }
+                format!("parameter: {}", cells.join(" "))
+            }
+        };
+        if end > start {
+            lines.extend(wrap(&parameter));
+        }
+        lines
+    }
+
+    // A word's parameter field lasts until the next word's header, or the end of its data
+    fn parameter_end(&self, word: &DictionaryEntry) -> i32 {
+        let next = self
+            .words
+            .iter()
+            .map(name_field)
+            .filter(|address| *address > word.xt)
+            .min();
+        let data_end = self
+            .module
+            .data_section()
+            .map_or(&[][..], |s| s.entries())
+            .iter()
+            .filter_map(|segment| {
+                let start = segment.offset().as_ref().and_then(constant)?;
+                let end = start + segment.value().len() as i32;
+                Some(end).filter(|end| (start..*end).contains(&word.xt))
+            })
+            .max()
+            .unwrap_or(word.xt + 4);
+        next.map_or(data_end, |next| next.min(data_end))
+    }
+
+    // The XTs in a colon word, by name
+    fn colon_body(&self, start: i32, end: i32) -> Vec<String> {
+        let name = |address: i32| {
+            self.cell(address)
+                .and_then(|xt| self.names_by_xt.get(&xt))
+                .map(|name| name.as_str())
+        };
+        let mut body = vec![];
+        let mut address = start;
+        while address + 4 <= end {
+            let operand = self.cell(address + 4).unwrap_or(0);
+            match name(address) {
+                Some("LIT") if address + 8 <= end => {
+                    body.push(format!("LIT {}", operand));
+                    address += 8;
+                }
+                Some("FLIT") if address + 12 <= end => {
+                    let value = self
+                        .bytes(address + 4, 8)
+                        .and_then(|bytes| bytes.try_into().ok())
+                        .map_or(f64::NAN, f64::from_le_bytes);
+                    body.push(format!("FLIT {:?}", value));
+                    address += 12;
+                }
+                Some(jump @ ("BRANCH" | "?BRANCH")) if address + 8 <= end => {
+                    // string literals jump over their text, then push its address and length
+                    let text_start = address + 8;
+                    let target = operand;
+                    let is_string = jump == "BRANCH"
+                        && target + 16 <= end
+                        && name(target) == Some("LIT")
+                        && self.cell(target + 4) == Some(text_start)
+                        && name(target + 8) == Some("LIT");
+                    let length = self.cell(target + 12).unwrap_or(-1);
+                    match self.bytes(text_start, length) {
+                        Some(text) if is_string && text_start + length <= target => {
+                            body.push(format!("{:?}", String::from_utf8_lossy(text)));
+                            address = target + 16;
+                        }
+                        _ => {
+                            body.push(format!("{} {:#x}", jump, target));
+                            address += 8;
+                        }
+                    }
+                }
+                Some(word) => {
+                    body.push(word.to_owned());
+                    address += 4;
+                }
+                None => {
+                    body.push(self.cell(address).unwrap_or(0).to_string());
+                    address += 4;
+                }
+            }
+        }
+        body
+    }
+
+    fn bytes(&self, address: i32, length: i32) -> Option<&[u8]> {
+        if address < 0 || length < 0 {
+            return None;
+        }
+        self.memory
+            .get(address as usize..address as usize + length as usize)
+    }
+
+    fn cell(&self, address: i32) -> Option<i32> {
+        Some(i32::from_le_bytes(self.bytes(address, 4)?.try_into().ok()?))
+    }
+
+    fn function(&self, index: u32) -> String {
+        match self.functions.get(index as usize) {
+            Some(Some(id)) => id.clone(),
+            _ => index.to_string(),
+        }
+    }
+
+    fn global(&self, index: usize) -> String {
+        match self.globals.get(index) {
+            Some(Some(id)) => id.clone(),
+            _ => index.to_string(),
+        }
+    }
+}
+
+// Where a word's header starts: its name's length, then its name, padded to a cell
+fn name_field(word: &DictionaryEntry) -> i32 {
+    word.xt - 4 - ((1 + word.name.len() as i32 + 3) & -4)
+}
+
+/* Turn names into identifiers, which can't contain spaces, quotes, parentheses and the like.
+ * Those are percent-encoded, and names which are used twice get the index of the second one. */
+fn identifiers<'n>(names: impl Iterator<Item = Option<&'n str>>) -> Vec<Option<String>> {
+    let mut used = HashSet::new();
+    names
+        .enumerate()
+        .map(|(index, name)| {
+            let name = name?;
+            let mut id = String::from("$");
+            for c in name.chars() {
+                if c.is_ascii_alphanumeric() || (c != '%' && "!#$%&'*+-./:<=>?@\\^_`|~".contains(c))
+                {
+                    id.push(c);
+                } else {
+                    for byte in c.to_string().bytes() {
+                        id.push_str(&format!("%{:02X}", byte));
+                    }
+                }
+            }
+            if !used.insert(id.clone()) {
+                id.push_str(&format!("#{}", index));
+                used.insert(id.clone());
+            }
+            Some(id)
+        })
+        .collect()
+}
+
+// How a function or global is named where it's defined, even when it has no identifier
+fn declaration(ids: &[Option<String>], index: usize) -> String {
+    match ids.get(index) {
+        Some(Some(id)) => id.clone(),
+        _ => format!("(;{};)", index),
+    }
+}
+
+fn value_types(kind: &str, types: &[parity_wasm::elements::ValueType]) -> String {
+    if types.is_empty() {
+        return String::new();
+    }
+    let types: Vec<String> = types.iter().map(|t| t.to_string()).collect();
+    format!(" ({} {})", kind, types.join(" "))
+}
+
+fn global_type(content_type: parity_wasm::elements::ValueType, mutable: bool) -> String {
+    match mutable {
+        true => format!("(mut {})", content_type),
+        false => content_type.to_string(),
+    }
+}
+
+fn limits(initial: u32, maximum: Option<u32>) -> String {
+    match maximum {
+        Some(maximum) => format!("{} {}", initial, maximum),
+        None => initial.to_string(),
+    }
+}
+
+fn block_type(block_type: &BlockType) -> String {
+    match block_type {
+        BlockType::NoResult => String::new(),
+        BlockType::Value(value_type) => format!(" (result {})", value_type),
+    }
+}
+
+fn constant(expr: &InitExpr) -> Option<i32> {
+    match expr.code() {
+        [Instruction::I32Const(value), Instruction::End] => Some(*value),
+        _ => None,
+    }
+}
+
+// The alignment and offset of a load or store
+fn memory_argument(instruction: &Instruction) -> Option<(u32, u32)> {
+    use Instruction::*;
+    match *instruction {
+        I32Load(align, offset)
+        | I64Load(align, offset)
+        | F32Load(align, offset)
+        | F64Load(align, offset)
+        | I32Load8S(align, offset)
+        | I32Load8U(align, offset)
+        | I32Load16S(align, offset)
+        | I32Load16U(align, offset)
+        | I64Load8S(align, offset)
+        | I64Load8U(align, offset)
+        | I64Load16S(align, offset)
+        | I64Load16U(align, offset)
+        | I64Load32S(align, offset)
+        | I64Load32U(align, offset)
+        | I32Store(align, offset)
+        | I64Store(align, offset)
+        | F32Store(align, offset)
+        | F64Store(align, offset)
+        | I32Store8(align, offset)
+        | I32Store16(align, offset)
+        | I64Store8(align, offset)
+        | I64Store16(align, offset)
+        | I64Store32(align, offset) => Some((align, offset)),
+        _ => None,
+    }
+}
+
+// The alignment the text format assumes when there isn't one, as a power of 2
+fn natural_alignment(mnemonic: &str) -> u32 {
+    let (value_type, operation) = mnemonic.split_once('.').unwrap_or_default();
+    if operation.contains("8") {
+        0
+    } else if operation.contains("16") {
+        1
+    } else if operation.contains("32") || value_type.ends_with("32") {
+        2
+    } else {
+        3
+    }
+}
+
+fn float(value: f64) -> String {
+    if value.is_nan() {
+        "nan".to_owned()
+    } else if value.is_infinite() {
+        if value > 0.0 { "inf" } else { "-inf" }.to_owned()
+    } else {
+        format!("{:?}", value)
+    }
+}
+
+fn escape(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    for byte in bytes {
+        match byte {
+            b'"' | b'\\' => text.push_str(&format!("\\{}", *byte as char)),
+            0x20..=0x7e => text.push(*byte as char),
+            _ => text.push_str(&format!("\\{:02x}", byte)),
+        }
+    }
+    text
+}
+
+// Split a long comment into lines, indenting the ones after the first
+fn wrap(text: &str) -> Vec<String> {
+    let mut lines = vec![String::new()];
+    for word in text.split(' ') {
+        let line = lines.last_mut().unwrap();
+        if !line.trim().is_empty() && line.len() + 1 + word.len() > COMMENT_WIDTH {
+            lines.push(format!("  {}", word));
+        } else {
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+        }
+    }
+    lines
+}
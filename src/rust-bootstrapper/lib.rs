@@ -1,26 +1,127 @@
 mod assembler;
 mod bootstrapped_interpreter;
 mod compiler;
+mod debugger;
+mod disassembler;
+mod profiler;
 mod runtime;
+mod snapshot;
+mod source_map;
+mod vm;
 
 use anyhow::Result;
-use compiler::Compiler;
+use compiler::{Compiler, CompilerOptions};
+use std::path::PathBuf;
+
+pub use assembler::ValidationError;
+pub use compiler::{
+    read_dictionary, DictionaryEntry, Exports, MemoryLayout, StackModel, Threading, WordKind,
+    DICTIONARY_SECTION, ORIGINS_SECTION,
+};
+#[cfg(any(test, feature = "runtime"))]
+pub use debugger::{DebugHandler, Debugger, Position, Resume, Stop};
+pub use disassembler::disassemble;
+#[cfg(any(test, feature = "runtime"))]
+pub use profiler::{profile, Profile, WordProfile};
+pub use source_map::{Frame, SourceLocation, SourceMap};
+#[cfg(any(test, feature = "runtime"))]
+pub use vm::{ForthVm, VmError, VmResult};
+
+/// Everything which can be configured about the interpreter we build
+pub struct Options {
+    /// Prelude files (or directories of them) to include on startup, in order
+    pub prelude: Vec<PathBuf>,
+    /// Which words to export from the module
+    pub exports: Exports,
+    /// Bake the prelude into the module, instead of reading it from disk on startup
+    pub embed_prelude: bool,
+    /// Load the prelude at build time, and start from a snapshot of memory afterwards.
+    /// Needs the "runtime" feature.
+    pub snapshot: bool,
+    /// Trap on stack overflow and underflow, instead of reading or writing past the stacks
+    pub checked_stacks: bool,
+    /// How big the dictionary, stacks and buffers are, and where they go
+    pub layout: MemoryLayout,
+    /// Whether the bootstrapper's colon words are threaded, or compiled into functions of their own
+    pub threading: Threading,
+    /// Inline small native words into subroutine-threaded colon words
+    pub inline_primitives: bool,
+    /// Whether the top of the parameter stack lives in memory, or in a global of its own
+    pub stack_model: StackModel,
+    /// Call the imported "forsm.debug_hook" before running each XT, for a debugger
+    pub debug_hook: bool,
+}
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            prelude: vec![PathBuf::from("src/prelude")],
+            exports: Exports::Start,
+            embed_prelude: false,
+            snapshot: false,
+            checked_stacks: false,
+            layout: MemoryLayout::default(),
+            threading: Threading::Indirect,
+            inline_primitives: false,
+            stack_model: StackModel::Memory,
+            debug_hook: false,
+        }
+    }
+}
 
-pub fn compile_interpreter() -> Result<Vec<u8>> {
-    let mut compiler = Compiler::default();
-    bootstrapped_interpreter::build(&mut compiler);
-    compiler.compile()
+pub struct CompiledInterpreter {
+    /// The compiled WASM module
+    pub binary: Vec<u8>,
+    /// The name of every word the bootstrapper defined, in order
+    pub words: Vec<String>,
+}
+
+pub fn compile_interpreter(options: &Options) -> Result<CompiledInterpreter> {
+    let mut compiler = Compiler::new(CompilerOptions {
+        exports: options.exports,
+        checked_stacks: options.checked_stacks,
+        layout: options.layout,
+        threading: options.threading,
+        inline_primitives: options.inline_primitives,
+        stack_model: options.stack_model,
+        debug_hook: options.debug_hook,
+    });
+    bootstrapped_interpreter::build(&mut compiler, options)?;
+    let words = compiler.words();
+    let mut binary = compiler.compile()?;
+    if options.snapshot {
+        binary = snapshot(&binary)?;
+    }
+    Ok(CompiledInterpreter { binary, words })
+}
+
+#[cfg(any(test, feature = "runtime"))]
+use snapshot::snapshot;
+#[cfg(not(any(test, feature = "runtime")))]
+fn snapshot(_binary: &[u8]) -> Result<Vec<u8>> {
+    anyhow::bail!("Snapshots need forsm to be built with the \"runtime\" feature")
 }
 
 #[cfg(test)]
 mod tests {
-    use super::compile_interpreter;
     use super::runtime::InterpreterRuntime;
+    use super::{
+        compile_interpreter, disassemble, profile, read_dictionary, Exports, Options, StackModel,
+        Threading, WordKind,
+    };
     use anyhow::Result;
+    use std::path::PathBuf;
 
     fn build_interpreter() -> Result<InterpreterRuntime> {
-        let binary = compile_interpreter()?;
-        InterpreterRuntime::new(&binary)
+        build_interpreter_with(Options::default())
+    }
+
+    fn build_interpreter_with(options: Options) -> Result<InterpreterRuntime> {
+        let options = Options {
+            exports: Exports::All,
+            ..options
+        };
+        let interpreter = compile_interpreter(&options)?;
+        InterpreterRuntime::new(&interpreter.binary)
     }
 
     #[test]
@@ -189,4 +290,204 @@ mod tests {
         let output = interpreter.interpret("parse-name k3wl! type").unwrap();
         assert_eq!(output, "k3wl!");
     }
+
+    #[test]
+    fn should_report_uncaught_errors() {
+        let interpreter = build_interpreter().unwrap();
+        interpreter.write_input("(UNCAUGHT) NOPE").unwrap();
+        interpreter.execute("REFILL").unwrap();
+        interpreter.execute("DROP").unwrap();
+        for word in ["PARSE-NAME", "FIND-NAME", "NAME>XT", "'UNCAUGHT", "!"] {
+            interpreter.execute(word).unwrap();
+        }
+
+        assert!(interpreter.execute("INTERPRET").is_err());
+        assert_eq!(
+            interpreter.read_output().unwrap(),
+            "Error -1 in NOPE\n  in INTERPRET+160\n  in RUN-WORD+4\n"
+        );
+    }
+
+    #[test]
+    fn should_evaluate_with_embedded_prelude() {
+        let interpreter = build_interpreter_with(Options {
+            embed_prelude: true,
+            ..Default::default()
+        })
+        .unwrap();
+        let output = interpreter
+            .interpret(": SQUARE DUP * ; 7 SQUARE .")
+            .unwrap();
+        assert_eq!(output, "49 ");
+    }
+
+    #[test]
+    fn should_list_the_prelude_in_a_snapshot() {
+        let extra = std::env::temp_dir().join("forsm-dictionary-test.fth");
+        std::fs::write(&extra, ": CONST CREATE , DOES> @ ; 5 CONST FIVE VARIABLE V").unwrap();
+        // the last file of the prelude starts main, so these words go before it
+        let mut prelude: Vec<PathBuf> = std::fs::read_dir("src/prelude")
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        prelude.sort();
+        prelude.insert(prelude.len() - 1, extra);
+        let interpreter = compile_interpreter(&Options {
+            prelude,
+            embed_prelude: true,
+            snapshot: true,
+            ..Default::default()
+        })
+        .unwrap();
+        let dictionary = read_dictionary(&interpreter.binary).unwrap();
+        let find = |name: &str| dictionary.iter().rev().find(|e| e.name == name).unwrap();
+
+        // words the bootstrapper defined come first
+        assert_eq!(dictionary[0].name, "DUP");
+        assert_eq!(find("DUP").kind, WordKind::Native);
+        assert_eq!(find("(DOCOL)").kind, WordKind::Constant);
+        // and the prelude's words come after them
+        assert!(find("IF").immediate);
+        assert!(!find("SWAP").immediate);
+        assert_eq!(find("IF").kind, WordKind::Colon);
+        assert_eq!(find("FIVE").kind, WordKind::Does);
+        assert_eq!(find("V").kind, WordKind::Variable);
+        assert!(find("SOURCE").trampolined);
+        assert!(dictionary.iter().any(|e| e.name == "SOURCE" && e.hidden));
+    }
+
+    #[test]
+    fn should_evaluate_from_snapshot() {
+        let interpreter = build_interpreter_with(Options {
+            snapshot: true,
+            ..Default::default()
+        })
+        .unwrap();
+        let output = interpreter
+            .interpret(": SQUARE DUP * ; 7 SQUARE .")
+            .unwrap();
+        assert_eq!(output, "49 ");
+    }
+
+    #[test]
+    fn should_evaluate_with_subroutine_threading() {
+        let interpreter = build_interpreter_with(Options {
+            threading: Threading::Subroutine,
+            ..Default::default()
+        })
+        .unwrap();
+        let output = interpreter
+            .interpret(": SQUARE DUP * ; 7 SQUARE . : BIG 100000000 ALLOT ; ' BIG CATCH .")
+            .unwrap();
+        assert_eq!(output, "49 -8 ");
+    }
+
+    #[test]
+    fn should_evaluate_with_inlined_primitives() {
+        let interpreter = build_interpreter_with(Options {
+            threading: Threading::Subroutine,
+            inline_primitives: true,
+            ..Default::default()
+        })
+        .unwrap();
+        let output = interpreter
+            .interpret(
+                ": SUM 0 SWAP 0 DO I + LOOP ; 10 SUM . -7 ABS 3 MAX . \
+                 : T 2E FSQRT -3 THROW ; 1.5E ' T CATCH . FDEPTH . F.",
+            )
+            .unwrap();
+        // THROW puts the float stack back with an inlined FP!
+        assert_eq!(output, "45 7 -3 1 1.5 ");
+    }
+
+    #[test]
+    fn should_disassemble_to_the_same_module() {
+        use parity_wasm::elements::{Local, Module, Section};
+
+        // without names and custom sections, which the text format doesn't keep,
+        // and with locals of the same type declared together, like the text format does
+        fn code(binary: &[u8]) -> Vec<u8> {
+            let mut module = Module::from_bytes(binary).unwrap();
+            module
+                .sections_mut()
+                .retain(|section| !matches!(section, Section::Custom(_) | Section::Name(_)));
+            for body in module.code_section_mut().unwrap().bodies_mut() {
+                let mut locals: Vec<Local> = vec![];
+                for local in body.locals() {
+                    match locals.last_mut() {
+                        Some(last) if last.value_type() == local.value_type() => {
+                            *last = Local::new(last.count() + local.count(), local.value_type())
+                        }
+                        _ => locals.push(*local),
+                    }
+                }
+                *body.locals_mut() = locals;
+            }
+            parity_wasm::serialize(module).unwrap()
+        }
+
+        for options in [
+            Options::default(),
+            Options {
+                threading: Threading::Subroutine,
+                inline_primitives: true,
+                stack_model: StackModel::CachedTop,
+                ..Default::default()
+            },
+        ] {
+            let interpreter = compile_interpreter(&options).unwrap();
+            let text = disassemble(&interpreter.binary).unwrap();
+            assert!(text.contains("(func $forth:DUP "));
+            assert!(text.contains(";; DUP (native) at "));
+            let reassembled = wat::parse_str(&text).unwrap();
+            assert!(code(&reassembled) == code(&interpreter.binary));
+        }
+    }
+
+    #[test]
+    fn should_compile_a_working_self_hosted_interpreter() {
+        std::fs::create_dir_all("bin").unwrap();
+        let interpreter = build_interpreter().unwrap();
+        // the script ends with BYE, once it's written bin/forth.wasm
+        interpreter
+            .write_input("include src/scripts/forsm.fth")
+            .unwrap();
+        assert!(interpreter.execute("_start").is_err());
+        assert!(interpreter
+            .read_output()
+            .unwrap()
+            .contains("File generated!"));
+
+        let binary = std::fs::read("bin/forth.wasm").unwrap();
+        let self_hosted = InterpreterRuntime::new(&binary).unwrap();
+        self_hosted
+            .write_input(": SQUARE DUP * ; 7 SQUARE . bye")
+            .unwrap();
+        assert!(self_hosted.execute("_start").is_err());
+        assert!(self_hosted.read_output().unwrap().contains("49 "));
+    }
+
+    #[test]
+    fn should_profile_the_interpreter() {
+        let source = ": SQUARE dup * ;\n: QUAD square square ;\n3 quad .\n";
+        let profile = profile(&Options::default(), source, 997).unwrap();
+        assert!(profile.output.contains("81 "));
+
+        let word = |name: &str| profile.words.iter().find(|word| word.name == name).unwrap();
+        assert_eq!(word("QUAD").calls, 1);
+        assert_eq!(word("SQUARE").calls, 2);
+        assert!(word("FIND-NAME").inclusive > word("FIND-NAME").exclusive);
+
+        // every sample is counted once, whichever word it lands in
+        let sampled = profile.executed - profile.executed % 997;
+        let exclusive: u64 = profile.words.iter().map(|word| word.exclusive).sum();
+        assert_eq!(exclusive, sampled);
+        let collapsed = profile.collapsed();
+        let total: u64 = collapsed
+            .lines()
+            .map(|line| line.rsplit(' ').next().unwrap().parse::<u64>().unwrap())
+            .sum();
+        assert_eq!(total, sampled);
+        assert!(collapsed.lines().any(|line| line.contains(";FIND-NAME;")));
+    }
 }
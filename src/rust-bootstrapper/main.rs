@@ -1,10 +1,180 @@
-use anyhow::Result;
-use forsm::compile_interpreter;
+use anyhow::{anyhow, bail, Result};
+use forsm::{
+    compile_interpreter, disassemble, Exports, MemoryLayout, Options, StackModel, Threading,
+};
+use std::path::{Path, PathBuf};
+
+const USAGE: &str = "Usage: forsm-bootstrapper [OPTIONS] [PRELUDE...]
+
+Compiles the bootstrapped Forth interpreter to a WASM module.
+PRELUDE is any number of files or directories to include on startup, in order.
+Directories are included in order of file name. Defaults to ./src/prelude.
+
+Options:
+  -o, --output <PATH>  Where to write the module (default: ./bin/forsm.wasm, or ./bin/forsm.wat)
+      --wat            Write the module as WebAssembly text, with the dictionary decoded in comments
+      --disassemble <MODULE>
+                       Print an already compiled module as WebAssembly text, instead of compiling
+      --export-all     Export every word as a function, not just _start
+      --embed-prelude  Bake the prelude into the module instead of loading it at runtime
+      --snapshot       Load the prelude at build time (needs the \"runtime\" feature)
+      --checked-stacks Trap on stack overflow and underflow
+      --subroutine-threading
+                       Compile the bootstrapper's colon words into functions of their own
+      --inline         Also inline small native words into them (implies --subroutine-threading)
+      --cache-top      Keep the top of the parameter stack in a global instead of memory
+      --dictionary-capacity <BYTES>
+                       How big the dictionary can grow (default: 55552)
+      --stack-depth <CELLS>
+                       How many cells each stack holds (default: 256)
+      --float-stack-depth <FLOATS>
+                       How many floats the float stack holds (default: 64)
+      --print-words    Print the name of every word the bootstrapper defines
+      --print-layout   Print the memory layout as Forth constants, instead of compiling
+  -h, --help           Print this message";
+
+struct Args {
+    output: PathBuf,
+    wat: bool,
+    disassemble: Option<PathBuf>,
+    print_words: bool,
+    print_layout: bool,
+    options: Options,
+}
+
+fn parse_size(flag: &str, value: Option<String>) -> Result<i32> {
+    let value = value.ok_or_else(|| anyhow!("{} requires a size", flag))?;
+    value
+        .parse()
+        .map_err(|_| anyhow!("{} requires a size, not {}", flag, value))
+}
+
+fn parse_args() -> Result<Option<Args>> {
+    let mut output = None;
+    let mut wat = false;
+    let mut disassemble = None;
+    let mut print_words = false;
+    let mut print_layout = false;
+    let mut layout = MemoryLayout::default();
+    let mut exports = Exports::Start;
+    let mut embed_prelude = false;
+    let mut snapshot = false;
+    let mut checked_stacks = false;
+    let mut threading = Threading::Indirect;
+    let mut inline_primitives = false;
+    let mut stack_model = StackModel::Memory;
+    let mut prelude = vec![];
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => {
+                let path = args
+                    .next()
+                    .ok_or_else(|| anyhow!("{} requires a path", arg))?;
+                output = Some(PathBuf::from(path));
+            }
+            "--wat" => wat = true,
+            "--disassemble" => {
+                let path = args
+                    .next()
+                    .ok_or_else(|| anyhow!("{} requires a path", arg))?;
+                disassemble = Some(PathBuf::from(path));
+            }
+            "--export-all" => exports = Exports::All,
+            "--embed-prelude" => embed_prelude = true,
+            "--snapshot" => snapshot = true,
+            "--checked-stacks" => checked_stacks = true,
+            "--subroutine-threading" => threading = Threading::Subroutine,
+            "--inline" => {
+                threading = Threading::Subroutine;
+                inline_primitives = true;
+            }
+            "--cache-top" => stack_model = StackModel::CachedTop,
+            "--dictionary-capacity" => {
+                layout.dictionary_capacity = parse_size(&arg, args.next())?;
+            }
+            "--stack-depth" => {
+                let depth = parse_size(&arg, args.next())?;
+                layout.param_stack_depth = depth;
+                layout.return_stack_depth = depth;
+            }
+            "--float-stack-depth" => {
+                layout.float_stack_depth = parse_size(&arg, args.next())?;
+            }
+            "--print-words" => print_words = true,
+            "--print-layout" => print_layout = true,
+            "-h" | "--help" => return Ok(None),
+            flag if flag.starts_with('-') => bail!("Unrecognized option {}\n\n{}", flag, USAGE),
+            path => prelude.push(PathBuf::from(path)),
+        }
+    }
+
+    let mut options = Options {
+        exports,
+        embed_prelude,
+        snapshot,
+        checked_stacks,
+        layout,
+        threading,
+        inline_primitives,
+        stack_model,
+        ..Default::default()
+    };
+    if !prelude.is_empty() {
+        options.prelude = prelude;
+    }
+    let output = output.unwrap_or_else(|| match wat {
+        true => PathBuf::from("./bin/forsm.wat"),
+        false => PathBuf::from("./bin/forsm.wasm"),
+    });
+    Ok(Some(Args {
+        output,
+        wat,
+        disassemble,
+        print_words,
+        print_layout,
+        options,
+    }))
+}
 
 fn main() -> Result<()> {
-    let binary = compile_interpreter()?;
-    std::fs::write("./bin/forsm.wasm", &binary)?;
-    println!("Compiled to ./bin/forsm.wasm. Run with:");
-    println!("wasmer --dir=. ./bin/forsm.wasm");
+    let args = match parse_args()? {
+        Some(args) => args,
+        None => {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+    };
+
+    if args.print_layout {
+        args.options.layout.validate()?;
+        print!("{}", args.options.layout.to_forth());
+        return Ok(());
+    }
+
+    if let Some(module) = args.disassemble {
+        print!("{}", disassemble(&std::fs::read(module)?)?);
+        return Ok(());
+    }
+
+    let interpreter = compile_interpreter(&args.options)?;
+    if args.print_words {
+        for word in interpreter.words.iter() {
+            println!("{}", word);
+        }
+    }
+
+    if let Some(dir) = args.output.parent().filter(|dir| dir != &Path::new("")) {
+        std::fs::create_dir_all(dir)?;
+    }
+    if args.wat {
+        std::fs::write(&args.output, disassemble(&interpreter.binary)?)?;
+        println!("Disassembled to {}", args.output.display());
+        return Ok(());
+    }
+    std::fs::write(&args.output, &interpreter.binary)?;
+    println!("Compiled to {}. Run with:", args.output.display());
+    println!("wasmer --dir=. {}", args.output.display());
     Ok(())
 }
@@ -0,0 +1,270 @@
+#![cfg(any(test, feature = "runtime"))] // the profiler runs on wasmer
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryInto;
+use std::io::{Read, Write};
+use std::str;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, bail, Result};
+use wasmer::{Instance, Memory, Module, Store};
+use wasmer_wasi::{Pipe, WasiError, WasiState};
+
+use super::compiler::WordKind;
+use super::debugger::{register_debug_hook, Hook, Position};
+use super::{compile_interpreter, Exports, MemoryLayout, Options, SourceMap};
+
+fn word<'a>(
+    words: &'a mut HashMap<i32, WordProfile>,
+    map: &SourceMap,
+    xt: i32,
+) -> &'a mut WordProfile {
+    words.entry(xt).or_insert_with(|| WordProfile {
+        name: map
+            .word_at(xt)
+            .map_or_else(|| format!("<{}>", xt), |word| word.name.clone()),
+        xt,
+        calls: 0,
+        exclusive: 0,
+        inclusive: 0,
+    })
+}
+
+fn is_colon(map: &SourceMap, xt: i32) -> bool {
+    map.word_at(xt)
+        .is_some_and(|word| word.kind == WordKind::Colon)
+}
+
+/* Counts what a profiling build of the interpreter runs, by address.
+ * Nothing is resolved to words until the run is over, since the dictionary keeps growing. */
+pub(crate) struct Profiler {
+    // sample the return stack every this many XTs
+    period: u64,
+    executed: u64,
+    calls: HashMap<i32, u64>,
+    // how often each address was run just after the return stack grew, so colon words can count
+    // the calls which EXECUTE made without their XT passing through here, as entries to their body
+    entries: HashMap<i32, u64>,
+    rp: i32,
+    // the XT, the IP, and the return stack (innermost first) of each sample
+    samples: HashMap<Vec<i32>, u64>,
+}
+
+impl Profiler {
+    fn new(period: u64) -> Self {
+        Self {
+            period: period.max(1),
+            executed: 0,
+            calls: HashMap::new(),
+            entries: HashMap::new(),
+            rp: i32::MAX,
+            samples: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn record(&mut self, position: &Position, memory: &Memory, layout: MemoryLayout) {
+        *self.calls.entry(position.xt).or_insert(0) += 1;
+        if position.rp < self.rp {
+            *self.entries.entry(position.ip).or_insert(0) += 1;
+        }
+        self.rp = position.rp;
+        self.executed += 1;
+        if !self.executed.is_multiple_of(self.period) {
+            return;
+        }
+        let view = memory.view::<u8>();
+        let cell = |address: i32| {
+            let bytes: Vec<u8> = view
+                .get(address as usize..address as usize + 4)?
+                .iter()
+                .map(|byte| byte.get())
+                .collect();
+            Some(i32::from_le_bytes(bytes.try_into().ok()?))
+        };
+        let mut sample = vec![position.xt, position.ip];
+        sample.extend(
+            (position.rp..layout.return_stack_base())
+                .step_by(4)
+                .filter_map(cell),
+        );
+        *self.samples.entry(sample).or_insert(0) += 1;
+    }
+
+    fn resolve(&self, map: &SourceMap, output: String) -> Profile {
+        let mut words: HashMap<i32, WordProfile> = HashMap::new();
+        for (&xt, &calls) in &self.calls {
+            if !is_colon(map, xt) {
+                word(&mut words, map, xt).calls = calls;
+            }
+        }
+        for (&address, &calls) in &self.entries {
+            // the XT is just before the body
+            if is_colon(map, address - 4) {
+                word(&mut words, map, address - 4).calls = calls;
+            }
+        }
+
+        // the same few return addresses turn up in most samples
+        let mut enclosing: HashMap<i32, Option<i32>> = HashMap::new();
+        let mut stacks: BTreeMap<Vec<i32>, u64> = BTreeMap::new();
+        for (sample, &count) in &self.samples {
+            let mut stack: Vec<i32> = sample[1..]
+                .iter()
+                .rev()
+                .filter_map(|&address| {
+                    *enclosing
+                        .entry(address)
+                        .or_insert_with(|| map.enclosing(address).map(|word| word.xt))
+                })
+                .collect();
+            // colon words get a frame of their own once they start, so only the rest are leaves
+            let xt = sample[0];
+            if !is_colon(map, xt) {
+                stack.push(xt);
+            }
+            *stacks.entry(stack).or_insert(0) += count * self.period;
+        }
+
+        for (stack, &count) in &stacks {
+            if let Some(&xt) = stack.last() {
+                word(&mut words, map, xt).exclusive += count;
+            }
+            let mut seen = vec![];
+            for &xt in stack {
+                // recursive words only count once
+                if !seen.contains(&xt) {
+                    seen.push(xt);
+                    word(&mut words, map, xt).inclusive += count;
+                }
+            }
+        }
+
+        let stacks = stacks
+            .into_iter()
+            .map(|(stack, count)| {
+                let names = stack
+                    .iter()
+                    .map(|&xt| word(&mut words, map, xt).name.clone())
+                    .collect();
+                (names, count)
+            })
+            .collect();
+        let mut words: Vec<WordProfile> = words.into_values().collect();
+        words.sort_by(|a, b| {
+            (b.exclusive, b.inclusive, b.calls, &a.name).cmp(&(
+                a.exclusive,
+                a.inclusive,
+                a.calls,
+                &b.name,
+            ))
+        });
+        Profile {
+            executed: self.executed,
+            period: self.period,
+            words,
+            stacks,
+            output,
+        }
+    }
+}
+
+/// How much of a profiled run one word accounts for.
+///
+/// Costs are counted in XTs run by the inner interpreter, not time, so they're the same every run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WordProfile {
+    pub name: String,
+    pub xt: i32,
+    /// How many times its XT ran
+    pub calls: u64,
+    /// How many XTs its own body ran, or how many times it ran if it isn't a colon word
+    pub exclusive: u64,
+    /// How many XTs ran while it was running, including in the words it called
+    pub inclusive: u64,
+}
+
+/// The result of [`profile`]
+#[derive(Clone, Debug)]
+pub struct Profile {
+    /// How many XTs the inner interpreter ran
+    pub executed: u64,
+    /// How many XTs each sample of the return stack stands for
+    pub period: u64,
+    /// Every word which ran, most exclusive XTs first
+    pub words: Vec<WordProfile>,
+    /// Each stack of words which was sampled, outermost first, with how many XTs ran in it
+    pub stacks: Vec<(Vec<String>, u64)>,
+    /// Everything the interpreter printed
+    pub output: String,
+}
+
+impl Profile {
+    /// The sampled stacks in the collapsed format which flamegraph tools read,
+    /// one `OUTER;INNER count` line per stack
+    pub fn collapsed(&self) -> String {
+        self.stacks
+            .iter()
+            .map(|(stack, count)| format!("{} {}\n", stack.join(";"), count))
+            .collect()
+    }
+}
+
+/// Compile a profiling build of an interpreter, then run it (prelude and all) with `input` as stdin.
+///
+/// Every XT the inner interpreter runs is counted, and the return stack is sampled every `period` XTs.
+/// Words compiled with subroutine threading don't go through the inner interpreter, so they're only
+/// counted as part of whichever word called them.
+pub fn profile(options: &Options, input: &str, period: u64) -> Result<Profile> {
+    let options = Options {
+        prelude: options.prelude.clone(),
+        exports: Exports::Start,
+        snapshot: false,
+        debug_hook: true,
+        ..*options
+    };
+    let binary = compile_interpreter(&options)?.binary;
+
+    let store = Store::default();
+    let module = Module::from_binary(&store, &binary)?;
+    let mut wasi_env = WasiState::new("forsm")
+        .stdin(Box::new(Pipe::new()))
+        .stdout(Box::new(Pipe::new()))
+        .preopen_dir(".")?
+        .finalize()?;
+    if let Some(stdin) = wasi_env.state().fs.stdin_mut()?.as_mut() {
+        stdin.write_all(input.as_bytes())?;
+    }
+    let mut imports = wasi_env.import_object(&module)?;
+    let profiler = Arc::new(Mutex::new(Profiler::new(period)));
+    register_debug_hook(
+        &mut imports,
+        &store,
+        &module,
+        &binary,
+        options.layout,
+        Some(Hook::Profiler(profiler.clone())),
+    );
+    let instance = Instance::new(&module, &imports)?;
+
+    if let Err(error) = instance.exports.get_function("_start")?.call(&[]) {
+        match error.downcast::<WasiError>() {
+            Ok(WasiError::Exit(0)) => {}
+            Ok(WasiError::Exit(code)) => bail!("Interpreter exited with code {}", code),
+            Ok(error) => bail!("Interpreter failed: {}", error),
+            Err(error) => bail!("Interpreter failed: {}", error),
+        }
+    }
+
+    let mut output = vec![];
+    if let Some(stdout) = wasi_env.state().fs.stdout_mut()?.as_mut() {
+        stdout.read_to_end(&mut output)?;
+    }
+    let output = str::from_utf8(&output)?.to_owned();
+    let memory = instance.exports.get_memory("memory")?;
+    let memory: Vec<u8> = memory.view::<u8>().iter().map(|byte| byte.get()).collect();
+    let map = SourceMap::read(&binary, &memory)?;
+    let profiler = profiler
+        .lock()
+        .map_err(|_| anyhow!("The profiler panicked"))?;
+    Ok(profiler.resolve(&map, output))
+}
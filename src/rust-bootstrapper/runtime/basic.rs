@@ -37,6 +37,15 @@ impl BasicRuntime {
         }
     }
 
+    pub fn push_float(&self, value: f64) -> Result<()> {
+        let push = self.instance.exports.get_function("push_f")?;
+        let result = push.call(&[Value::F64(value)])?;
+        match *result {
+            [] => Ok(()),
+            _ => Err(anyhow!("Unexpected output {:?}", result)),
+        }
+    }
+
     pub fn pop(&self) -> Result<i32> {
         let pop = self.instance.exports.get_function("pop")?;
         let result = pop.call(&[])?;
@@ -55,6 +64,15 @@ impl BasicRuntime {
         }
     }
 
+    pub fn pop_float(&self) -> Result<f64> {
+        let pop = self.instance.exports.get_function("pop_f")?;
+        let result = pop.call(&[])?;
+        match *result {
+            [Value::F64(val)] => Ok(val),
+            _ => Err(anyhow!("Unexpected output {:?}", result)),
+        }
+    }
+
     pub fn pop_string(&self) -> Result<String> {
         let len = self.pop()?;
         let start = self.pop()?;
@@ -76,7 +94,14 @@ impl BasicRuntime {
         }
     }
 
-    pub fn memory(&self) -> Result<MemoryView<u8>> {
+    pub fn global(&self, name: &str) -> Result<i32> {
+        match self.instance.exports.get_global(name)?.get() {
+            Value::I32(val) => Ok(val),
+            val => Err(anyhow!("Unexpected value {:?}", val)),
+        }
+    }
+
+    pub fn memory(&self) -> Result<MemoryView<'_, u8>> {
         let view = self.instance.exports.get_memory("memory")?;
         Ok(view.view())
     }
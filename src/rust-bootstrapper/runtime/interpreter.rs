@@ -1,6 +1,6 @@
 use std::str;
 
-use anyhow::{Result};
+use anyhow::Result;
 use wasmer_wasi::{Pipe, WasiEnv, WasiStateBuilder};
 
 use super::basic::BasicRuntime;
@@ -19,7 +19,8 @@ impl InterpreterRuntime {
             .preopen_dir(".")?
             .finalize()
             .unwrap();
-        let runtime = BasicRuntime::new(binary, |_, module| wasi_env.import_object(module).unwrap())?;
+        let runtime =
+            BasicRuntime::new(binary, |_, module| wasi_env.import_object(module).unwrap())?;
         Ok(Self { wasi_env, runtime })
     }
 
@@ -0,0 +1,171 @@
+#![cfg(any(test, feature = "runtime"))] // snapshots need to run the interpreter
+
+use crate::compiler::{encode_dictionary, read_dictionary, walk_dictionary, DICTIONARY_SECTION};
+use crate::debugger::register_debug_hook;
+use crate::MemoryLayout;
+use anyhow::{anyhow, bail, Result};
+use parity_wasm::elements::{
+    DataSegment, InitExpr,
+    Instruction::{End, I32Const},
+    Internal, MemoryType, Module, Section,
+};
+use wasmer::{Extern, Instance, Module as WasmerModule, Store, Value};
+use wasmer_wasi::{Pipe, WasiError, WasiState};
+
+// Runs of zeroes shorter than this aren't worth starting a new data segment for
+const MIN_GAP: usize = 16;
+
+/* The state of an interpreter which has loaded its prelude, and is about to run main */
+struct Image {
+    memory: Vec<u8>,
+    globals: Vec<(String, i32)>,
+}
+
+/// Run the prelude of a freshly compiled interpreter, and build a module
+/// which starts with the resulting memory and globals, and jumps straight to main.
+pub fn snapshot(binary: &[u8]) -> Result<Vec<u8>> {
+    let image = run_prelude(binary)?;
+    build_snapshot(binary, &image)
+}
+
+fn run_prelude(binary: &[u8]) -> Result<Image> {
+    let store = Store::default();
+    let module = WasmerModule::from_binary(&store, binary)?;
+    let mut wasi_env = WasiState::new("forsm-bootstrapper")
+        .stdin(Box::new(Pipe::new()))
+        .preopen_dir(".")?
+        .finalize()?;
+    let mut imports = wasi_env.import_object(&module)?;
+    // nothing debugs the prelude
+    register_debug_hook(
+        &mut imports,
+        &store,
+        &module,
+        binary,
+        MemoryLayout::default(),
+        None,
+    );
+    let instance = Instance::new(&module, &imports)?;
+
+    // START-MAIN stops the interpreter once the prelude is done
+    if let Err(error) = instance.exports.get_function("_start")?.call(&[]) {
+        match error.downcast::<WasiError>() {
+            Ok(WasiError::Exit(code)) => bail!("Prelude exited with code {} before main", code),
+            Ok(error) => bail!("Prelude failed: {}", error),
+            Err(error) => bail!("Prelude failed: {}", error),
+        }
+    }
+
+    let memory = instance.exports.get_memory("memory")?;
+    let memory = memory.view::<u8>().iter().map(|byte| byte.get()).collect();
+    let mut globals = vec![];
+    for (name, export) in instance.exports.iter() {
+        if let Extern::Global(global) = export {
+            match global.get() {
+                Value::I32(value) => globals.push((name.clone(), value)),
+                value => bail!("Unexpected value {:?} for global {}", value, name),
+            }
+        }
+    }
+    Ok(Image { memory, globals })
+}
+
+fn build_snapshot(binary: &[u8], image: &Image) -> Result<Vec<u8>> {
+    let mut module = Module::from_bytes(binary)?;
+
+    // Restore every global, except the return stack; main starts with an empty one
+    let exports = module
+        .export_section()
+        .ok_or_else(|| anyhow!("Module has no exports"))?
+        .entries()
+        .to_vec();
+    for export in exports.iter() {
+        if let Internal::Global(index) = *export.internal() {
+            if export.field() == "rp" {
+                continue;
+            }
+            let value = image
+                .globals
+                .iter()
+                .find(|(name, _)| name == export.field())
+                .map(|(_, value)| *value)
+                .ok_or_else(|| anyhow!("Snapshot is missing global {}", export.field()))?;
+            let globals = module
+                .global_section_mut()
+                .ok_or_else(|| anyhow!("Module has no globals"))?;
+            *globals.entries_mut()[index as usize].init_expr_mut() =
+                InitExpr::new(vec![I32Const(value), End]);
+        }
+    }
+
+    // Memory starts out exactly as the prelude left it
+    let memories = module
+        .memory_section_mut()
+        .ok_or_else(|| anyhow!("Module has no memory"))?;
+    let maximum = memories.entries()[0].limits().maximum();
+    let pages = (image.memory.len() / 0x10000) as u32;
+    memories.entries_mut()[0] = MemoryType::new(pages, maximum);
+    let data = module
+        .data_section_mut()
+        .ok_or_else(|| anyhow!("Module has no data"))?;
+    *data.entries_mut() = data_segments(&image.memory);
+
+    // The prelude added words of its own, and flagged some as immediate
+    let dictionary = module
+        .sections_mut()
+        .iter_mut()
+        .find_map(|section| match section {
+            Section::Custom(section) if section.name() == DICTIONARY_SECTION => Some(section),
+            _ => None,
+        })
+        .ok_or_else(|| anyhow!("Module has no {} section", DICTIONARY_SECTION))?;
+    let compiled = read_dictionary(binary)?;
+    *dictionary.payload_mut() = encode_dictionary(&walk_dictionary(&image.memory, &compiled)?);
+
+    // _start goes straight to main
+    let exports = module
+        .export_section_mut()
+        .ok_or_else(|| anyhow!("Module has no exports"))?
+        .entries_mut();
+    let resume = exports
+        .iter()
+        .position(|export| export.field() == "_resume")
+        .ok_or_else(|| anyhow!("Module was not built for snapshots"))?;
+    let resume = exports.remove(resume);
+    let start = exports
+        .iter_mut()
+        .find(|export| export.field() == "_start")
+        .ok_or_else(|| anyhow!("Module has no _start"))?;
+    *start.internal_mut() = *resume.internal();
+
+    Ok(module.to_bytes()?)
+}
+
+// One segment for each run of nonzero memory, since the rest is zeroed already
+fn data_segments(memory: &[u8]) -> Vec<DataSegment> {
+    let mut segments = vec![];
+    let mut offset = 0;
+    while let Some(start) = memory[offset..].iter().position(|b| *b != 0) {
+        let start = offset + start;
+        let mut end = start;
+        let mut zeroes = 0;
+        for (index, byte) in memory.iter().enumerate().skip(start) {
+            if *byte != 0 {
+                end = index + 1;
+                zeroes = 0;
+            } else {
+                zeroes += 1;
+                if zeroes >= MIN_GAP {
+                    break;
+                }
+            }
+        }
+        segments.push(DataSegment::new(
+            0,
+            Some(InitExpr::new(vec![I32Const(start as i32), End])),
+            memory[start..end].to_vec(),
+        ));
+        offset = end;
+    }
+    segments
+}
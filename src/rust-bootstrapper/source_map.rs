@@ -0,0 +1,195 @@
+use crate::compiler::{
+    read_dictionary, read_origins, walk_dictionary, DictionaryEntry, Origin, WordKind,
+};
+use anyhow::{anyhow, Result};
+use std::{collections::HashMap, convert::TryInto, fmt};
+
+// Each entry of the source map is this many cells: HERE, the source's name and length, and the line
+const ENTRY_CELLS: i32 = 4;
+
+/// Where some code came from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceLocation {
+    /// The Forth file it was compiled from, or the Rust file which defined it
+    pub file: String,
+    pub line: u32,
+    /// The word it belongs to
+    pub word: Option<String>,
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)?;
+        if let Some(word) = &self.word {
+            write!(f, " {}", word)?;
+        }
+        Ok(())
+    }
+}
+
+/// One frame of a backtrace: a return address, and the colon word it returns into
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub address: i32,
+    pub word: String,
+    /// How many bytes past the word's XT the address is
+    pub offset: i32,
+    pub location: Option<SourceLocation>,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}+{}", self.word, self.offset)?;
+        if let Some(location) = &self.location {
+            write!(f, " at {}:{}", location.file, location.line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Maps addresses in the dictionary back to the source they were compiled from.
+///
+/// Words the bootstrapper defined point to the Rust code which defined them.
+/// Everything else is found through a table in memory, which REFILL adds a line to
+/// whenever the line before it compiled something.
+pub struct SourceMap {
+    // the dictionary, oldest word first
+    words: Vec<DictionaryEntry>,
+    origins: HashMap<i32, (String, u32)>,
+    // where each line started compiling, in the order the lines were read
+    lines: Vec<(i32, String, u32)>,
+    // where the dictionary ends
+    here: i32,
+}
+
+fn read_cell(memory: &[u8], address: i32) -> Result<i32> {
+    let bytes = memory
+        .get(address as usize..address as usize + 4)
+        .ok_or_else(|| anyhow!("Source map points outside of memory at {}", address))?;
+    Ok(i32::from_le_bytes(bytes.try_into()?))
+}
+
+impl SourceMap {
+    /// Read the source map of an interpreter, from its module and the current contents of its memory
+    pub fn read(binary: &[u8], memory: &[u8]) -> Result<Self> {
+        let words = walk_dictionary(memory, &read_dictionary(binary)?)?;
+        let origins = read_origins(binary)?
+            .into_iter()
+            .map(|(xt, file, line): Origin| (xt, (file, line)))
+            .collect();
+        let cell = |address: i32| read_cell(memory, address);
+        let mut map = Self {
+            words,
+            origins,
+            lines: vec![],
+            here: 0,
+        };
+        map.here = map.value(memory, "CP")?;
+
+        let table = map.value(memory, "SOURCE-MAP")?;
+        for entry in 0..map.value(memory, "#SOURCE-MAP")? {
+            let entry = table + entry * ENTRY_CELLS * 4;
+            let (name, len) = (cell(entry + 4)?, cell(entry + 8)?);
+            let file = match len {
+                0 => "<stdin>".to_owned(),
+                len => {
+                    let bytes = memory
+                        .get(name as usize..(name + len) as usize)
+                        .ok_or_else(|| {
+                            anyhow!("Source map points outside of memory at {}", name)
+                        })?;
+                    String::from_utf8_lossy(bytes).into_owned()
+                }
+            };
+            map.lines
+                .push((cell(entry)?, file, cell(entry + 12)? as u32));
+        }
+        Ok(map)
+    }
+
+    // Where a word's parameter field is
+    pub(crate) fn parameter(&self, name: &str) -> Result<i32> {
+        let word = self
+            .words
+            .iter()
+            .rev()
+            .find(|word| word.name == name)
+            .ok_or_else(|| anyhow!("Dictionary has no {}", name))?;
+        Ok(word.xt + 4)
+    }
+
+    // The value of a constant or variable, which both keep it in their parameter field
+    pub(crate) fn value(&self, memory: &[u8], name: &str) -> Result<i32> {
+        read_cell(memory, self.parameter(name)?)
+    }
+
+    /// The newest visible word with this name, ignoring case, like FIND-NAME
+    pub fn find(&self, name: &str) -> Option<&DictionaryEntry> {
+        self.words
+            .iter()
+            .rev()
+            .find(|word| !word.hidden && word.name.eq_ignore_ascii_case(name))
+    }
+
+    /// The word with this XT
+    pub fn word_at(&self, xt: i32) -> Option<&DictionaryEntry> {
+        self.words.iter().rev().find(|word| word.xt == xt)
+    }
+
+    /// The colon word whose body holds an address, like a return address
+    pub fn enclosing(&self, address: i32) -> Option<&DictionaryEntry> {
+        if address >= self.here {
+            return None;
+        }
+        self.words
+            .iter()
+            .filter(|word| word.xt < address)
+            .max_by_key(|word| word.xt)
+            .filter(|word| word.kind == WordKind::Colon)
+    }
+
+    /// Resolve the cells of a return stack into frames, innermost first.
+    /// Cells which aren't in a colon word, like loop indices, are skipped.
+    pub fn backtrace(&self, cells: &[i32]) -> Vec<Frame> {
+        cells
+            .iter()
+            .filter_map(|&address| {
+                let word = self.enclosing(address)?;
+                Some(Frame {
+                    address,
+                    word: word.name.clone(),
+                    offset: address - word.xt,
+                    location: self.locate(address),
+                })
+            })
+            .collect()
+    }
+
+    /// Where the code at an address (like the IP) was compiled from
+    pub fn locate(&self, address: i32) -> Option<SourceLocation> {
+        let word = self
+            .words
+            .iter()
+            .filter(|word| word.xt <= address)
+            .max_by_key(|word| word.xt);
+        if let Some((file, line)) = word.and_then(|word| self.origins.get(&word.xt)) {
+            return Some(SourceLocation {
+                file: file.clone(),
+                line: *line,
+                word: word.map(|word| word.name.clone()),
+            });
+        }
+        let (_, file, line) = self
+            .lines
+            .iter()
+            .enumerate()
+            .filter(|(_, (start, _, _))| *start <= address)
+            .max_by_key(|(index, (start, _, _))| (*start, *index))?
+            .1;
+        Some(SourceLocation {
+            file: file.clone(),
+            line: *line,
+            word: word.map(|word| word.name.clone()),
+        })
+    }
+}
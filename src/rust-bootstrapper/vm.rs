@@ -0,0 +1,1027 @@
+#![cfg(any(test, feature = "runtime"))] // the VM runs on wasmer
+
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{Read, Write};
+use std::str::{self, Utf8Error};
+use std::sync::{Arc, Mutex};
+
+use wasmer::{Instance, MemoryView, Module, RuntimeError, Store, Value};
+use wasmer_wasi::{Pipe, WasiEnv, WasiError, WasiState};
+
+use super::debugger::{register_debug_hook, Hook};
+use super::{
+    compile_interpreter, Debugger, Exports, Frame, MemoryLayout, Options, Resume, SourceLocation,
+    SourceMap,
+};
+
+/// Everything which can go wrong while driving a [`ForthVm`]
+#[derive(Debug)]
+pub enum VmError {
+    /// The interpreter couldn't be compiled
+    Compile(anyhow::Error),
+    /// The module couldn't be instantiated, or doesn't export what the VM needs
+    Instantiate(String),
+    /// The interpreter trapped, while running the code at this location (if it's known)
+    Trap {
+        error: RuntimeError,
+        location: Option<SourceLocation>,
+    },
+    /// The interpreter exited, e.g. by running BYE
+    Exit(u32),
+    /// Forth code threw an exception with this code
+    Throw(i32),
+    /// There is no word with this name
+    UnknownWord(String),
+    /// A function returned something other than what it should have
+    UnexpectedOutput(Vec<Value>),
+    /// An access fell outside of linear memory
+    OutOfBounds { address: i32, len: usize },
+    /// A string wasn't valid UTF-8
+    Utf8(Utf8Error),
+    /// Talking to the interpreter's stdin or stdout failed
+    Io(std::io::Error),
+    /// The module's debug info couldn't be read
+    DebugInfo(anyhow::Error),
+    /// The VM was built without a debugger
+    NoDebugger,
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Compile(error) => write!(f, "could not compile interpreter: {}", error),
+            Self::Instantiate(message) => {
+                write!(f, "could not instantiate interpreter: {}", message)
+            }
+            Self::Trap {
+                error,
+                location: None,
+            } => write!(f, "interpreter trapped: {}", error),
+            Self::Trap {
+                error,
+                location: Some(location),
+            } => write!(f, "interpreter trapped in {}: {}", location, error),
+            Self::Exit(code) => write!(f, "interpreter exited with code {}", code),
+            Self::Throw(code) => write!(f, "uncaught exception {}", code),
+            Self::UnknownWord(word) => write!(f, "unknown word {}", word),
+            Self::UnexpectedOutput(values) => write!(f, "unexpected output {:?}", values),
+            Self::OutOfBounds { address, len } => {
+                write!(f, "{} bytes at {} are out of bounds", len, address)
+            }
+            Self::Utf8(error) => write!(f, "invalid string: {}", error),
+            Self::Io(error) => write!(f, "could not access stdio: {}", error),
+            Self::DebugInfo(error) => write!(f, "could not read debug info: {}", error),
+            Self::NoDebugger => write!(f, "interpreter has no debugger attached"),
+        }
+    }
+}
+
+impl std::error::Error for VmError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Compile(error) => Some(error.as_ref()),
+            Self::Trap { error, .. } => Some(error),
+            Self::DebugInfo(error) => Some(error.as_ref()),
+            Self::Utf8(error) => Some(error),
+            Self::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<RuntimeError> for VmError {
+    fn from(error: RuntimeError) -> Self {
+        match error.downcast::<WasiError>() {
+            Ok(WasiError::Exit(code)) => Self::Exit(code),
+            Ok(error) => Self::Instantiate(error.to_string()),
+            Err(error) => Self::Trap {
+                error,
+                location: None,
+            },
+        }
+    }
+}
+
+impl From<Utf8Error> for VmError {
+    fn from(error: Utf8Error) -> Self {
+        Self::Utf8(error)
+    }
+}
+
+impl From<std::io::Error> for VmError {
+    fn from(error: std::io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+fn instantiate_error(error: impl fmt::Display) -> VmError {
+    VmError::Instantiate(error.to_string())
+}
+
+fn io_error(error: impl fmt::Display) -> VmError {
+    VmError::Io(std::io::Error::other(error.to_string()))
+}
+
+pub type VmResult<T> = Result<T, VmError>;
+
+/// A Forth interpreter with its prelude loaded, which can be driven from Rust.
+pub struct ForthVm {
+    wasi_env: WasiEnv,
+    instance: Instance,
+    // for the debug info in its custom sections
+    binary: Vec<u8>,
+    // the return stack which the last error unwound, innermost cell first
+    backtrace: RefCell<Vec<i32>>,
+    debugger: Option<Arc<Mutex<Debugger>>>,
+}
+
+impl ForthVm {
+    /// Compile an interpreter, and load its prelude.
+    pub fn new(options: &Options) -> VmResult<Self> {
+        let binary = Self::compile(options)?;
+        Self::instantiate(&binary, options.layout, None)
+    }
+
+    /// Compile a debug build of an interpreter, which calls a debugger before every XT it runs.
+    /// Only the words which the VM is asked to run are debugged, not its own bookkeeping.
+    pub fn with_debugger(options: &Options, debugger: Debugger) -> VmResult<Self> {
+        let binary = Self::compile(&Options {
+            debug_hook: true,
+            prelude: options.prelude.clone(),
+            ..*options
+        })?;
+        Self::instantiate(
+            &binary,
+            options.layout,
+            Some(Arc::new(Mutex::new(debugger))),
+        )
+    }
+
+    /// Instantiate an interpreter which was compiled with `Exports::All` and `snapshot`.
+    pub fn from_binary(binary: &[u8]) -> VmResult<Self> {
+        Self::instantiate(binary, MemoryLayout::default(), None)
+    }
+
+    fn compile(options: &Options) -> VmResult<Vec<u8>> {
+        // The VM looks words up by name and runs them through exports,
+        // and never runs main, so it needs a snapshot which exports everything
+        let options = Options {
+            prelude: options.prelude.clone(),
+            exports: Exports::All,
+            snapshot: true,
+            ..*options
+        };
+        let interpreter = compile_interpreter(&options).map_err(VmError::Compile)?;
+        Ok(interpreter.binary)
+    }
+
+    fn instantiate(
+        binary: &[u8],
+        layout: MemoryLayout,
+        debugger: Option<Arc<Mutex<Debugger>>>,
+    ) -> VmResult<Self> {
+        let store = Store::default();
+        let module = Module::from_binary(&store, binary).map_err(instantiate_error)?;
+        let mut wasi_env = WasiState::new("forsm")
+            .stdin(Box::new(Pipe::new()))
+            .stdout(Box::new(Pipe::new()))
+            .preopen_dir(".")
+            .map_err(instantiate_error)?
+            .finalize()
+            .map_err(instantiate_error)?;
+        let mut imports = wasi_env.import_object(&module).map_err(instantiate_error)?;
+        register_debug_hook(
+            &mut imports,
+            &store,
+            &module,
+            binary,
+            layout,
+            debugger.clone().map(Hook::Debugger),
+        );
+        let instance = Instance::new(&module, &imports).map_err(instantiate_error)?;
+        Ok(Self {
+            wasi_env,
+            instance,
+            binary: binary.to_vec(),
+            backtrace: RefCell::new(vec![]),
+            debugger,
+        })
+    }
+
+    pub fn push(&self, value: i32) -> VmResult<()> {
+        self.call("push", &[Value::I32(value)])?;
+        Ok(())
+    }
+
+    pub fn pop(&self) -> VmResult<i32> {
+        match *self.call("pop", &[])? {
+            [Value::I32(value)] => Ok(value),
+            ref result => Err(VmError::UnexpectedOutput(result.to_vec())),
+        }
+    }
+
+    pub fn push_double(&self, value: i64) -> VmResult<()> {
+        self.call("push_d", &[Value::I64(value)])?;
+        Ok(())
+    }
+
+    pub fn pop_double(&self) -> VmResult<i64> {
+        match *self.call("pop_d", &[])? {
+            [Value::I64(value)] => Ok(value),
+            ref result => Err(VmError::UnexpectedOutput(result.to_vec())),
+        }
+    }
+
+    pub fn push_float(&self, value: f64) -> VmResult<()> {
+        self.call("push_f", &[Value::F64(value)])?;
+        Ok(())
+    }
+
+    pub fn pop_float(&self) -> VmResult<f64> {
+        match *self.call("pop_f", &[])? {
+            [Value::F64(value)] => Ok(value),
+            ref result => Err(VmError::UnexpectedOutput(result.to_vec())),
+        }
+    }
+
+    /// Pop a ( c-addr u ) pair, and read the string it points to
+    pub fn pop_string(&self) -> VmResult<String> {
+        let len = self.pop()?;
+        let address = self.pop()?;
+        let bytes = self.read_memory(address, len as usize)?;
+        Ok(str::from_utf8(&bytes)?.to_owned())
+    }
+
+    /// Run a word by name.
+    /// If it throws, the stacks are as deep as they were before the word ran.
+    pub fn execute(&self, word: &str) -> VmResult<()> {
+        self.run(word, true)
+    }
+
+    fn run(&self, word: &str, debugged: bool) -> VmResult<()> {
+        let xt = self.find(word)?;
+        let catch = self.find("CATCH")?;
+        let depth = self.stack_depth()?;
+        let return_depth = self.global("rp")?;
+        let float_depth = self.global("fp")?;
+        self.push(xt)?;
+        self.push(catch)?;
+        let debugger = self.debugger.as_ref().filter(|_| debugged);
+        if let Some(debugger) = debugger {
+            debugger.lock().unwrap().set_active(true);
+        }
+        let result = self.call("EXECUTE", &[]);
+        if let Some(debugger) = debugger {
+            let mut debugger = debugger.lock().unwrap();
+            debugger.set_active(false);
+            debugger.set_resume(Resume::Continue);
+        }
+        let code = match result {
+            Ok(_) => {
+                let code = self.pop()?;
+                if code != 0 {
+                    *self.backtrace.borrow_mut() = self.thrown_backtrace()?;
+                }
+                code
+            }
+            // failed stack checks trap, so CATCH never saw them or cleaned up after them
+            Err(VmError::Throw(code)) => {
+                self.set_global("rp", return_depth)?;
+                code
+            }
+            Err(error) => return Err(error),
+        };
+        match code {
+            0 => Ok(()),
+            code => {
+                self.restore_stack_depth(depth)?;
+                self.set_global("fp", float_depth)?;
+                Err(VmError::Throw(code))
+            }
+        }
+    }
+
+    /// Interpret some Forth source, and return everything it printed.
+    /// Anything the source leaves on the stack stays there.
+    pub fn evaluate(&self, source: &str) -> VmResult<String> {
+        self.read_output()?; // throw away anything printed earlier
+
+        // Definitions are compiled to HERE, so the source needs a home of its own
+        let len = source.len() as i32;
+        self.push(len)?;
+        self.run("ALLOCATE", false)?;
+        let ior = self.pop()?;
+        let address = self.pop()?;
+        if ior != 0 {
+            return Err(VmError::Throw(ior));
+        }
+        self.write_memory(address, source.as_bytes())?;
+
+        let depth = self.stack_depth()?;
+        self.push(address)?;
+        self.push(len)?;
+        let result = self.execute("EVALUATE");
+        if result.is_err() {
+            self.restore_stack_depth(depth)?;
+        }
+        self.push(address)?;
+        self.run("FREE", false)?;
+        self.pop()?;
+        result?;
+        self.read_output()
+    }
+
+    /// Everything the interpreter has printed since the last read
+    pub fn read_output(&self) -> VmResult<String> {
+        let mut state = self.wasi_env.state();
+        let stdout = state.fs.stdout_mut().map_err(io_error)?;
+        let mut output = vec![];
+        if let Some(stdout) = stdout.as_mut() {
+            stdout.read_to_end(&mut output)?;
+        }
+        Ok(str::from_utf8(&output)?.to_owned())
+    }
+
+    /// Make some text available for the interpreter to read from stdin
+    pub fn write_input(&self, input: &str) -> VmResult<()> {
+        let mut state = self.wasi_env.state();
+        let stdin = state.fs.stdin_mut().map_err(io_error)?;
+        if let Some(stdin) = stdin.as_mut() {
+            stdin.write_all(input.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// The interpreter's linear memory
+    pub fn memory(&self) -> VmResult<MemoryView<'_, u8>> {
+        let memory = self
+            .instance
+            .exports
+            .get_memory("memory")
+            .map_err(instantiate_error)?;
+        Ok(memory.view())
+    }
+
+    /// Map addresses in the dictionary back to the source they were compiled from
+    pub fn source_map(&self) -> VmResult<SourceMap> {
+        SourceMap::read(&self.binary, &self.memory_contents()?).map_err(VmError::DebugInfo)
+    }
+
+    /// The colon words which the last exception (or trap) unwound, innermost first.
+    /// Words compiled with subroutine threading don't keep their return addresses on the
+    /// return stack, so they're missing from it.
+    pub fn backtrace(&self) -> VmResult<Vec<Frame>> {
+        Ok(self.source_map()?.backtrace(&self.backtrace.borrow()))
+    }
+
+    /// Stop the debugger whenever a word is about to run
+    pub fn set_breakpoint(&self, word: &str) -> VmResult<()> {
+        let map = self.source_map()?;
+        let entry = map
+            .find(word)
+            .ok_or_else(|| VmError::UnknownWord(word.to_owned()))?;
+        self.debugger()?.lock().unwrap().set_breakpoint(entry);
+        Ok(())
+    }
+
+    /// Returns false if the word had no breakpoint
+    pub fn clear_breakpoint(&self, word: &str) -> VmResult<bool> {
+        let map = self.source_map()?;
+        let entry = map
+            .find(word)
+            .ok_or_else(|| VmError::UnknownWord(word.to_owned()))?;
+        Ok(self.debugger()?.lock().unwrap().clear_breakpoint(entry.xt))
+    }
+
+    /// Stop the debugger before the next XT that the next word the VM runs executes
+    pub fn break_next(&self) -> VmResult<()> {
+        self.debugger()?.lock().unwrap().set_resume(Resume::Step);
+        Ok(())
+    }
+
+    fn debugger(&self) -> VmResult<&Arc<Mutex<Debugger>>> {
+        self.debugger.as_ref().ok_or(VmError::NoDebugger)
+    }
+
+    /// Where the code at an address (like the IP) was compiled from
+    pub fn locate(&self, address: i32) -> VmResult<Option<SourceLocation>> {
+        Ok(self.source_map()?.locate(address))
+    }
+
+    pub fn read_memory(&self, address: i32, len: usize) -> VmResult<Vec<u8>> {
+        let memory = self.memory()?;
+        let range = Self::range(&memory, address, len)?;
+        Ok(memory[range].iter().map(|cell| cell.get()).collect())
+    }
+
+    pub fn write_memory(&self, address: i32, bytes: &[u8]) -> VmResult<()> {
+        let memory = self.memory()?;
+        let range = Self::range(&memory, address, bytes.len())?;
+        for (cell, byte) in memory[range].iter().zip(bytes) {
+            cell.set(*byte);
+        }
+        Ok(())
+    }
+
+    fn range(
+        memory: &MemoryView<'_, u8>,
+        address: i32,
+        len: usize,
+    ) -> VmResult<std::ops::Range<usize>> {
+        let start = address as usize;
+        match start.checked_add(len) {
+            Some(end) if address >= 0 && end <= memory.len() => Ok(start..end),
+            _ => Err(VmError::OutOfBounds { address, len }),
+        }
+    }
+
+    // Get the XT of a word, ignoring case
+    fn find(&self, word: &str) -> VmResult<i32> {
+        // FIND-NAME only reads the name, so the unused space past HERE can hold it
+        self.call("CP", &[])?;
+        let cp = self.pop()?;
+        let here = i32::from_le_bytes(self.read_cell(cp)?);
+        self.write_memory(here, word.as_bytes())?;
+        self.push(here)?;
+        self.push(word.len() as i32)?;
+        self.call("FIND-NAME", &[])?;
+        let nt = self.pop()?;
+        if nt == 0 {
+            return Err(VmError::UnknownWord(word.to_owned()));
+        }
+        self.push(nt)?;
+        self.call("NAME>XT", &[])?;
+        self.pop()
+    }
+
+    fn memory_contents(&self) -> VmResult<Vec<u8>> {
+        Ok(self.memory()?.iter().map(|cell| cell.get()).collect())
+    }
+
+    // The return stack which THROW saved before it unwound it
+    fn thrown_backtrace(&self) -> VmResult<Vec<i32>> {
+        let memory = self.memory_contents()?;
+        let map = SourceMap::read(&self.binary, &memory).map_err(VmError::DebugInfo)?;
+        let value = |name| map.value(&memory, name).map_err(VmError::DebugInfo);
+        let (saved, len) = (value("BACKTRACE")?, value("#BACKTRACE")?);
+        (0..len)
+            .map(|index| Ok(i32::from_le_bytes(self.read_cell(saved + index * 4)?)))
+            .collect()
+    }
+
+    // Traps skip THROW, so their return stack is still there, under the IP of the innermost frame
+    fn trapped_backtrace(&self) -> VmResult<Vec<i32>> {
+        let memory = self.memory_contents()?;
+        let map = SourceMap::read(&self.binary, &memory).map_err(VmError::DebugInfo)?;
+        let base = map
+            .value(&memory, "RETURN-STACK-BASE")
+            .map_err(VmError::DebugInfo)?;
+        let mut cells = vec![self.global("ip")?];
+        for address in (self.global("rp")?..base).step_by(4) {
+            cells.push(i32::from_le_bytes(self.read_cell(address)?));
+        }
+        Ok(cells)
+    }
+
+    fn read_cell(&self, address: i32) -> VmResult<[u8; 4]> {
+        let mut cell = [0; 4];
+        cell.copy_from_slice(&self.read_memory(address, 4)?);
+        Ok(cell)
+    }
+
+    // The parameter stack pointer, and the top of the stack if it's cached in a global
+    fn stack_depth(&self) -> VmResult<(i32, Option<i32>)> {
+        let tos = match self.instance.exports.contains("tos") {
+            true => Some(self.global("tos")?),
+            false => None,
+        };
+        Ok((self.global("sp")?, tos))
+    }
+
+    fn restore_stack_depth(&self, (sp, tos): (i32, Option<i32>)) -> VmResult<()> {
+        self.set_global("sp", sp)?;
+        if let Some(tos) = tos {
+            self.set_global("tos", tos)?;
+        }
+        Ok(())
+    }
+
+    fn global(&self, name: &str) -> VmResult<i32> {
+        let global = self
+            .instance
+            .exports
+            .get_global(name)
+            .map_err(instantiate_error)?;
+        match global.get() {
+            Value::I32(value) => Ok(value),
+            value => Err(VmError::UnexpectedOutput(vec![value])),
+        }
+    }
+
+    fn set_global(&self, name: &str, value: i32) -> VmResult<()> {
+        let global = self
+            .instance
+            .exports
+            .get_global(name)
+            .map_err(instantiate_error)?;
+        global.set(Value::I32(value))?;
+        Ok(())
+    }
+
+    fn call(&self, name: &str, params: &[Value]) -> VmResult<Box<[Value]>> {
+        let function = self
+            .instance
+            .exports
+            .get_function(name)
+            .map_err(instantiate_error)?;
+        function.call(params).or_else(|error| {
+            let error = VmError::from(error);
+            if let VmError::Trap { .. } = error {
+                // a backtrace is only a nice-to-have, so don't let it hide the trap
+                *self.backtrace.borrow_mut() = self.trapped_backtrace().unwrap_or_default();
+            }
+            // Interpreters with checked stacks trap with the code they would have thrown
+            match self.global("error")? {
+                0 => Err(match error {
+                    // say which colon word was running, going by the IP
+                    VmError::Trap {
+                        error,
+                        location: None,
+                    } => VmError::Trap {
+                        location: self.locate(self.global("ip")?).unwrap_or(None),
+                        error,
+                    },
+                    error => error,
+                }),
+                code => {
+                    self.set_global("error", 0)?;
+                    Err(VmError::Throw(code))
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::{ForthVm, VmError};
+    use crate::{Debugger, Options, Resume, StackModel};
+
+    fn build_vm() -> ForthVm {
+        ForthVm::new(&Options::default()).unwrap()
+    }
+
+    #[test]
+    fn should_evaluate() {
+        let vm = build_vm();
+        let output = vm.evaluate(": SQUARE DUP * ; 7 SQUARE .").unwrap();
+        assert_eq!(output, "49 ");
+        vm.evaluate("3 SQUARE").unwrap();
+        assert_eq!(vm.pop().unwrap(), 9);
+    }
+
+    #[test]
+    fn should_evaluate_strings_with_several_lines() {
+        let vm = build_vm();
+        assert_eq!(vm.evaluate("1 ( a\n b ) 2 + .").unwrap(), "3 ");
+        assert_eq!(vm.evaluate("1 2\n+ . \\ 3\n 4 + .").unwrap(), "3 ");
+        assert_eq!(vm.evaluate("SOURCE NIP .\n").unwrap(), "13 ");
+    }
+
+    #[test]
+    fn should_execute_words_by_name() {
+        let vm = build_vm();
+        vm.push(2).unwrap();
+        vm.push(3).unwrap();
+        vm.execute("+").unwrap();
+        assert_eq!(vm.pop().unwrap(), 5);
+        vm.push_double(-2).unwrap();
+        vm.execute("dnegate").unwrap();
+        assert_eq!(vm.pop_double().unwrap(), 2);
+    }
+
+    #[test]
+    fn should_pop_strings() {
+        let vm = build_vm();
+        vm.evaluate("s\" Hello world!\"").unwrap();
+        assert_eq!(vm.pop_string().unwrap(), "Hello world!");
+    }
+
+    #[test]
+    fn should_report_stack_errors() {
+        let vm = ForthVm::new(&Options {
+            checked_stacks: true,
+            ..Default::default()
+        })
+        .unwrap();
+        vm.push(1).unwrap();
+        assert!(matches!(vm.execute("2DROP"), Err(VmError::Throw(-4))));
+        assert_eq!(vm.pop().unwrap(), 1);
+        assert!(matches!(vm.pop(), Err(VmError::Throw(-4))));
+        let output = vm.evaluate(": SQUARE DUP * ; 7 SQUARE .").unwrap();
+        assert_eq!(output, "49 ");
+    }
+
+    #[test]
+    fn should_restore_a_cached_top_after_errors() {
+        let vm = ForthVm::new(&Options {
+            checked_stacks: true,
+            stack_model: StackModel::CachedTop,
+            ..Default::default()
+        })
+        .unwrap();
+        vm.push(1).unwrap();
+        vm.push(2).unwrap();
+        assert!(matches!(
+            vm.evaluate("5 6 -3 THROW"),
+            Err(VmError::Throw(-3))
+        ));
+        assert!(matches!(vm.execute("ROT"), Err(VmError::Throw(-4))));
+        assert_eq!(vm.pop().unwrap(), 2);
+        vm.evaluate("7 SWAP").unwrap();
+        assert_eq!(vm.pop().unwrap(), 1);
+        assert_eq!(vm.pop().unwrap(), 7);
+        assert!(matches!(vm.pop(), Err(VmError::Throw(-4))));
+    }
+
+    #[test]
+    fn should_throw_when_the_dictionary_is_full() {
+        let vm = build_vm();
+        assert!(matches!(
+            vm.evaluate("DICT-CAPACITY ALLOT"),
+            Err(VmError::Throw(-8))
+        ));
+        assert!(matches!(
+            vm.evaluate(": FILL BEGIN 0 , AGAIN ; FILL"),
+            Err(VmError::Throw(-8))
+        ));
+        vm.evaluate("DICT-LIMIT @ HERE - 4 U<").unwrap();
+        assert_eq!(vm.pop().unwrap(), -1);
+        assert_eq!(vm.evaluate("1 2 + .").unwrap(), "3 ");
+    }
+
+    #[test]
+    fn should_grow_the_dictionary_into_the_heap() {
+        let vm = build_vm();
+        // words which don't fit where the dictionary is carry on in a new chunk
+        vm.evaluate(": FILL BEGIN DICT-LIMIT @ HERE - DICT-RESERVE U< =0 WHILE 0 , REPEAT ; FILL")
+            .unwrap();
+        assert_eq!(
+            vm.evaluate("CREATE BIG HERE 30000 ALLOT BIG = .").unwrap(),
+            "-1 "
+        );
+        vm.evaluate(": AFTER 1 2 + ; VARIABLE V 4 V !").unwrap();
+        assert_eq!(vm.evaluate("AFTER . V @ .").unwrap(), "3 4 ");
+        vm.evaluate("' AFTER DICT-BASE DICT-CAPACITY + U>").unwrap();
+        assert_eq!(vm.pop().unwrap(), -1);
+        assert_eq!(
+            vm.evaluate(": MK CREATE 0 , DOES> @ 1+ ; MK SMALL SMALL .")
+                .unwrap(),
+            "1 "
+        );
+    }
+
+    #[test]
+    fn should_throw_when_data_outgrows_its_chunk() {
+        let vm = build_vm();
+        // definitions don't move, so anything holding HERE still points at them
+        assert!(matches!(
+            vm.evaluate("CREATE TOO-BIG DICT-CHUNK ALLOT"),
+            Err(VmError::Throw(-8))
+        ));
+        assert_eq!(vm.evaluate("1 2 + .").unwrap(), "3 ");
+    }
+
+    #[test]
+    fn should_throw_when_the_dictionary_reaches_its_ceiling() {
+        let vm = build_vm();
+        vm.evaluate(": FILL BEGIN DICT-LIMIT @ HERE - DICT-RESERVE U< =0 WHILE 0 , REPEAT ; FILL")
+            .unwrap();
+        // each of these leaves too little room for the next, so it takes a chunk of its own
+        assert!(matches!(
+            vm.evaluate(": GROW BEGIN S\" CREATE X 62000 ALLOT\" EVALUATE AGAIN ; GROW"),
+            Err(VmError::Throw(-8))
+        ));
+        vm.evaluate("DICT-LIMIT @ DICT-CEILING U> HERE DICT-CEILING DICT-CHUNK 2 * - U>")
+            .unwrap();
+        assert_eq!(vm.pop().unwrap(), -1);
+        assert_eq!(vm.pop().unwrap(), 0);
+        assert_eq!(vm.evaluate("1 2 + .").unwrap(), "3 ");
+    }
+
+    #[test]
+    fn should_throw_when_a_colon_definition_outgrows_its_chunk() {
+        let vm = build_vm();
+        let body = "1 DROP ".repeat(8192);
+        assert!(matches!(
+            vm.evaluate(&format!(": HUGE {};", body)),
+            Err(VmError::Throw(-8))
+        ));
+    }
+
+    #[test]
+    fn should_interpret_floats() {
+        let vm = build_vm();
+        assert_eq!(vm.evaluate("1.5e 2e F* F.").unwrap(), "3. ");
+        assert_eq!(
+            vm.evaluate("2E FSQRT 1e-3 F+ F.").unwrap(),
+            "1.4152135623731 "
+        );
+        assert_eq!(
+            vm.evaluate("-3.7E0 FDUP FLOOR F. FROUND F.").unwrap(),
+            "-4. -4. "
+        );
+        vm.evaluate(": HALF ( F: r1 -- r2 ) 0.5e F* ; 7E HALF")
+            .unwrap();
+        assert_eq!(vm.pop_float().unwrap(), 3.5);
+
+        vm.evaluate("1.25e FCONSTANT QUARTER FVARIABLE V  QUARTER 4e F* V F!")
+            .unwrap();
+        assert_eq!(vm.evaluate("V F@ F. QUARTER F.").unwrap(), "5. 1.25 ");
+        vm.evaluate("FDEPTH").unwrap();
+        assert_eq!(vm.pop().unwrap(), 0);
+
+        vm.push_float(6.0).unwrap();
+        vm.evaluate("F>S 3 S>F").unwrap();
+        assert_eq!(vm.pop().unwrap(), 6);
+        assert_eq!(vm.pop_float().unwrap(), 3.0);
+        assert!(matches!(vm.evaluate("1e20 F>S"), Err(VmError::Throw(-11))));
+        assert_eq!(
+            vm.evaluate("1e20 ' F>S CATCH . FDEPTH . FDROP").unwrap(),
+            "-11 1 "
+        );
+
+        // without an exponent, or in another base, it isn't a float
+        assert!(matches!(vm.evaluate("1.5"), Err(VmError::Throw(-14))));
+        vm.evaluate("HEX 1E DECIMAL").unwrap();
+        assert_eq!(vm.pop().unwrap(), 30);
+    }
+
+    #[test]
+    fn should_parse_floats() {
+        let vm = build_vm();
+        let parse = |text: &str| {
+            vm.evaluate(&format!("S\" {}\" >FLOAT", text)).unwrap();
+            match vm.pop().unwrap() {
+                0 => None,
+                _ => Some(vm.pop_float().unwrap()),
+            }
+        };
+        assert_eq!(parse("1.5"), Some(1.5));
+        assert_eq!(parse("-25E3"), Some(-25000.0));
+        assert_eq!(parse("+.5e-1"), Some(0.05));
+        assert_eq!(parse("1.5d2"), Some(150.0));
+        assert_eq!(parse("2e"), Some(2.0));
+        assert_eq!(parse("1.5+3"), Some(1500.0));
+        assert_eq!(parse("0.1"), Some(0.1));
+        assert_eq!(parse("1.23456789012345"), Some(1.23456789012345));
+        // big exponents are scaled in steps, which can round
+        let big = parse("1e300").unwrap();
+        assert!((big - 1e300).abs() <= 1e300 * f64::EPSILON * 4.0);
+        assert_eq!(parse("   "), Some(0.0));
+        assert_eq!(parse("1e999"), Some(f64::INFINITY));
+        assert_eq!(parse("abc"), None);
+        assert_eq!(parse("."), None);
+        assert_eq!(parse("1.5x"), None);
+        assert_eq!(parse("-e2"), None);
+        vm.evaluate("FDEPTH").unwrap();
+        assert_eq!(vm.pop().unwrap(), 0);
+    }
+
+    #[test]
+    fn should_format_floats() {
+        let vm = build_vm();
+        let format = |word: &str, value: f64| {
+            vm.push_float(value).unwrap();
+            vm.evaluate(word).unwrap()
+        };
+        assert_eq!(format("F.", 1.5), "1.5 ");
+        assert_eq!(format("F.", 100.0), "100. ");
+        assert_eq!(format("F.", 0.001), "0.001 ");
+        assert_eq!(format("F.", 0.0), "0. ");
+        assert_eq!(format("F.", -2500.0), "-2500. ");
+        assert_eq!(format("F.", 1.0 / 3.0), "0.333333333333333 ");
+        assert_eq!(format("F.", 9.9999999999999999), "10. ");
+        assert_eq!(format("F.", f64::NEG_INFINITY), "-Inf ");
+        assert_eq!(format("F.", f64::NAN), "NaN ");
+        assert_eq!(format("FS.", 12345.0), "1.2345E4 ");
+        assert_eq!(format("FS.", -0.00012), "-1.2E-4 ");
+        assert_eq!(format("FS.", 0.0), "0.E0 ");
+        assert_eq!(format("FE.", 12345.0), "12.345E3 ");
+        assert_eq!(format("FE.", 0.01), "10.E-3 ");
+        assert_eq!(format("FE.", 100.0), "100.E0 ");
+
+        assert_eq!(format("3 SET-PRECISION F.", 2.0f64.sqrt()), "1.41 ");
+        assert_eq!(format("F.", 1234.5), "1230. ");
+        vm.evaluate("PRECISION").unwrap();
+        assert_eq!(vm.pop().unwrap(), 3);
+
+        vm.push_float(0.5).unwrap();
+        vm.evaluate("PAD 4 REPRESENT").unwrap();
+        assert_eq!(vm.pop().unwrap(), -1);
+        assert_eq!(vm.pop().unwrap(), 0);
+        assert_eq!(vm.pop().unwrap(), 0);
+        vm.evaluate("PAD 4").unwrap();
+        assert_eq!(vm.pop_string().unwrap(), "5000");
+    }
+
+    #[test]
+    fn should_restore_the_float_stack_after_errors() {
+        let vm = ForthVm::new(&Options {
+            checked_stacks: true,
+            ..Default::default()
+        })
+        .unwrap();
+        vm.push_float(1.0).unwrap();
+        assert!(matches!(
+            vm.evaluate("2e 3e -1 THROW"),
+            Err(VmError::Throw(-1))
+        ));
+        assert!(matches!(vm.execute("F+"), Err(VmError::Throw(-45))));
+        assert_eq!(vm.evaluate("FDEPTH . F.").unwrap(), "1 1. ");
+        assert!(matches!(vm.pop_float(), Err(VmError::Throw(-45))));
+
+        let output = vm
+            .evaluate(": TRY 2e 3e -2 THROW ; 4e ' TRY CATCH . FDEPTH . F.")
+            .unwrap();
+        assert_eq!(output, "-2 1 4. ");
+    }
+
+    #[test]
+    fn should_report_errors() {
+        let vm = build_vm();
+        assert!(matches!(
+            vm.execute("NOT-A-WORD"),
+            Err(VmError::UnknownWord(_))
+        ));
+        vm.push(1).unwrap();
+        assert!(matches!(
+            vm.evaluate("NOT-A-WORD"),
+            Err(VmError::Throw(-14))
+        ));
+        assert!(matches!(vm.evaluate("-3 THROW"), Err(VmError::Throw(-3))));
+        assert_eq!(vm.pop().unwrap(), 1);
+        assert!(matches!(
+            vm.read_memory(-4, 4),
+            Err(VmError::OutOfBounds { .. })
+        ));
+    }
+
+    #[test]
+    fn should_locate_words_in_their_source() {
+        let vm = build_vm();
+        let spaces = vm.locate(vm.find("SPACES").unwrap()).unwrap().unwrap();
+        assert!(spaces.file.ends_with("06_output.fth"), "{}", spaces);
+        assert_eq!(spaces.line, 3);
+        assert_eq!(spaces.word.as_deref(), Some("SPACES"));
+
+        let dup = vm.locate(vm.find("DUP").unwrap()).unwrap().unwrap();
+        assert!(dup.file.ends_with("compiler.rs"), "{}", dup);
+        let record = vm.find("RECORD-SOURCE").unwrap();
+        let record = vm.locate(record).unwrap().unwrap();
+        assert!(
+            record.file.ends_with("bootstrapped_interpreter.rs"),
+            "{}",
+            record
+        );
+        assert!(record.line > 0);
+
+        vm.evaluate(": SQUARE DUP * ;").unwrap();
+        let square = vm.locate(vm.find("SQUARE").unwrap()).unwrap().unwrap();
+        assert_eq!(square.word.as_deref(), Some("SQUARE"));
+        assert_eq!((square.file.as_str(), square.line), ("<evaluate>", 1));
+
+        // each line of a string has its own entry, and nested strings hand back to it
+        vm.evaluate(": CUBE DUP SQUARE * ;\nS\" : INNER ;\" EVALUATE : OUTER ;")
+            .unwrap();
+        for (word, line) in [("CUBE", 1), ("INNER", 1), ("OUTER", 2)] {
+            let location = vm.locate(vm.find(word).unwrap()).unwrap().unwrap();
+            assert_eq!(
+                (location.file.as_str(), location.line),
+                ("<evaluate>", line)
+            );
+        }
+    }
+
+    #[test]
+    fn should_trace_exceptions_back_through_the_return_stack() {
+        let vm = build_vm();
+        vm.evaluate(": INNER 2 0 DO -3 THROW LOOP ; : OUTER 1 INNER DROP ;")
+            .unwrap();
+        assert!(matches!(vm.evaluate("OUTER"), Err(VmError::Throw(-3))));
+        let words: Vec<_> = vm
+            .backtrace()
+            .unwrap()
+            .into_iter()
+            .map(|frame| frame.word)
+            .collect();
+        assert_eq!(words[..2], ["INNER", "OUTER"]);
+
+        let output = vm
+            .evaluate(": TRACE ['] OUTER CATCH . CR .BACKTRACE ; TRACE")
+            .unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines[0], "-3 ");
+        assert!(lines[1].starts_with("  in INNER+"), "{}", output);
+        assert!(lines[2].starts_with("  in OUTER+"), "{}", output);
+        assert!(lines[3].starts_with("  in CATCH+"), "{}", output);
+
+        // a backtrace which was already caught doesn't stand in for a new exception
+        let output = vm
+            .evaluate(
+                ": OTHER -3 THROW ; : RECOVER ['] INNER CATCH DROP OTHER ; \
+                 : TRACE ['] RECOVER CATCH . CR .BACKTRACE ; TRACE",
+            )
+            .unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines[0], "-3 ");
+        assert!(lines[1].starts_with("  in OTHER+"), "{}", output);
+        assert!(lines[2].starts_with("  in RECOVER+"), "{}", output);
+        assert!(lines[3].starts_with("  in CATCH+"), "{}", output);
+
+        let output = vm
+            .evaluate(
+                ": OTHER ; : RECOVER ['] INNER CATCH DROP OTHER -2 THROW ; \
+                 : TRACE ['] RECOVER CATCH . CR .BACKTRACE ; TRACE",
+            )
+            .unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines[0], "-2 ");
+        assert!(lines[1].starts_with("  in RECOVER+"), "{}", output);
+        assert!(lines[2].starts_with("  in CATCH+"), "{}", output);
+
+        // but rethrowing it does
+        let output = vm
+            .evaluate(": PASS-ON ['] INNER CATCH RETHROW ; : TRACE ['] PASS-ON CATCH . CR .BACKTRACE ; TRACE")
+            .unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines[0], "-3 ");
+        assert!(lines[1].starts_with("  in INNER+"), "{}", output);
+
+        vm.evaluate(": BOOM 0 0 / ; : FUSE BOOM ;").unwrap();
+        let error = vm.evaluate("FUSE").unwrap_err();
+        assert!(matches!(error, VmError::Trap { .. }), "{}", error);
+        let words: Vec<_> = vm
+            .backtrace()
+            .unwrap()
+            .into_iter()
+            .map(|frame| frame.word)
+            .collect();
+        assert_eq!(words[..3], ["/", "BOOM", "FUSE"]); // / is a colon word around /MOD
+    }
+
+    #[test]
+    fn should_stop_at_breakpoints_and_step() {
+        // answer every stop with the next of these, and remember where it was
+        let mut resumes = vec![
+            Resume::StepOver,
+            Resume::Step,
+            Resume::Step,
+            Resume::StepOut,
+            Resume::Continue,
+        ]
+        .into_iter();
+        let stops = Arc::new(Mutex::new(vec![]));
+        let seen = stops.clone();
+        let debugger = Debugger::new(move |stop| {
+            let word = stop.word().unwrap_or("?").to_owned();
+            let backtrace: Vec<_> = stop.backtrace().into_iter().map(|f| f.word).collect();
+            let entry = (word, stop.stack(), stop.position.rp, backtrace);
+            seen.lock().unwrap().push(entry);
+            resumes.next().unwrap_or(Resume::Continue)
+        });
+        let vm = ForthVm::with_debugger(&Options::default(), debugger).unwrap();
+        vm.evaluate(": SQUARE DUP * ; : QUAD SQUARE SQUARE ;")
+            .unwrap();
+        assert!(stops.lock().unwrap().is_empty());
+
+        // QUAD stops as soon as it starts, before the first SQUARE
+        vm.set_breakpoint("quad").unwrap();
+        assert_eq!(vm.evaluate("3 QUAD .").unwrap(), "81 ");
+        let stops = stops.lock().unwrap();
+        let words: Vec<_> = stops.iter().map(|stop| stop.0.as_str()).collect();
+        // stepping over the first SQUARE skips its DUP and *, but stepping into the second doesn't
+        assert_eq!(words[..4], ["SQUARE", "SQUARE", "DUP", "*"]);
+        assert_eq!(stops[0].1, [3]);
+        assert_eq!(stops[0].3[0], "QUAD");
+        assert_eq!(stops[1].1, [9]);
+        assert_eq!(stops[3].1, [9, 9]);
+        assert_eq!(stops[0].2, stops[1].2);
+        assert!(stops[2].2 < stops[1].2);
+        assert_eq!(stops[3].3[..2], ["SQUARE", "QUAD"]);
+        // stepping out of SQUARE stops back in QUAD, right before it exits
+        assert!(stops[4].2 > stops[3].2);
+        assert_eq!(stops[4].3[0], "QUAD");
+        assert_eq!(stops.len(), 5);
+
+        assert!(matches!(
+            vm.set_breakpoint("NOT-A-WORD"),
+            Err(VmError::UnknownWord(_))
+        ));
+        assert!(vm.clear_breakpoint("QUAD").unwrap());
+        assert!(matches!(build_vm().break_next(), Err(VmError::NoDebugger)));
+    }
+}